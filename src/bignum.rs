@@ -0,0 +1,335 @@
+use std::{
+    cmp::Ordering,
+    ops::{Add, Mul, Sub},
+};
+
+/// Number of 32 bit limbs used for the integer part.
+const INT_LIMBS: usize = 2;
+
+/// An arbitrary precision fixed-point number. Values taken by
+/// reference orbits are bounded by the escape radius so there is no
+/// need for a floating exponent, only the number of fractional limbs
+/// matters. Results that don't fit in the integer part are flagged (see
+/// [`BigFixed::overflowed`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigFixed {
+    neg: bool,
+    /// Magnitude, little-endian. The first `frac` limbs hold the
+    /// fractional part.
+    mag: Vec<u32>,
+    frac: usize,
+    /// Whether this or one of the values it was computed from was
+    /// truncated, the magnitude is then meaningless.
+    overflow: bool,
+}
+
+impl BigFixed {
+    pub fn zero(frac: usize) -> BigFixed {
+        BigFixed {
+            neg: false,
+            mag: vec![0; frac + INT_LIMBS],
+            frac,
+            overflow: false,
+        }
+    }
+
+    /// Whether the integer part overflowed while computing this value.
+    pub fn overflowed(&self) -> bool {
+        self.overflow
+    }
+
+    /// Number of fractional limbs needed to get `bits` bits of
+    /// precision after the point.
+    pub fn frac_limbs_for(bits: u32) -> usize {
+        bits.div_ceil(32) as usize
+    }
+
+    /// Exact conversion as long as `x` fits in the integer part (otherwise
+    /// it overflows) and its lowest bit is within the precision (otherwise
+    /// it is truncated).
+    pub fn from_f64(x: f64, frac: usize) -> BigFixed {
+        let mut n = BigFixed::zero(frac);
+        if x == 0. || !x.is_finite() {
+            return n;
+        }
+
+        let bits = x.abs().to_bits();
+        let exp = ((bits >> 52) & 0x7ff) as i64;
+        let (mantissa, exp) = if exp == 0 {
+            // Subnormal
+            (bits & ((1 << 52) - 1), -1074)
+        } else {
+            ((bits & ((1 << 52) - 1)) | (1 << 52), exp - 1075)
+        };
+
+        // x = mantissa * 2^exp, the fixed-point value is x * 2^(32 * frac).
+        let shift = exp + 32 * frac as i64;
+        for bit in 0..53 {
+            if mantissa >> bit & 1 == 1 {
+                let pos = shift + bit;
+                if pos >= 32 * n.mag.len() as i64 {
+                    n.overflow = true;
+                } else if pos >= 0 {
+                    let pos = pos as usize;
+                    n.mag[pos / 32] |= 1 << (pos % 32);
+                }
+            }
+        }
+        n.neg = x < 0.;
+
+        n
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Only the highest limbs matter for a f64.
+        let mut value = 0.;
+        for (i, &limb) in self.mag.iter().enumerate().rev().take(4) {
+            value += limb as f64 * 2f64.powi(32 * (i as i32 - self.frac as i32));
+        }
+        if self.neg {
+            -value
+        } else {
+            value
+        }
+    }
+
+    fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+        a.iter().rev().cmp(b.iter().rev())
+    }
+
+    /// Sum of the magnitudes and whether it overflowed.
+    fn add_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, bool) {
+        let mut carry = 0u64;
+        let sum = a
+            .iter()
+            .zip(b)
+            .map(|(&a, &b)| {
+                let s = a as u64 + b as u64 + carry;
+                carry = s >> 32;
+                s as u32
+            })
+            .collect();
+        (sum, carry != 0)
+    }
+
+    /// Requires `a >= b`.
+    fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut borrow = 0i64;
+        a.iter()
+            .zip(b)
+            .map(|(&a, &b)| {
+                let mut d = a as i64 - b as i64 - borrow;
+                borrow = 0;
+                if d < 0 {
+                    d += 1 << 32;
+                    borrow = 1;
+                }
+                d as u32
+            })
+            .collect()
+    }
+
+    fn signed_add(&self, rhs: &BigFixed, rhs_neg: bool) -> BigFixed {
+        debug_assert_eq!(self.frac, rhs.frac);
+
+        let (neg, (mag, overflow)) = if self.neg == rhs_neg {
+            (self.neg, BigFixed::add_mag(&self.mag, &rhs.mag))
+        } else {
+            match BigFixed::cmp_mag(&self.mag, &rhs.mag) {
+                Ordering::Less => (rhs_neg, (BigFixed::sub_mag(&rhs.mag, &self.mag), false)),
+                _ => (self.neg, (BigFixed::sub_mag(&self.mag, &rhs.mag), false)),
+            }
+        };
+
+        BigFixed {
+            neg,
+            mag,
+            frac: self.frac,
+            overflow: overflow || self.overflow || rhs.overflow,
+        }
+    }
+}
+
+impl Add for &BigFixed {
+    type Output = BigFixed;
+
+    fn add(self, rhs: &BigFixed) -> BigFixed {
+        self.signed_add(rhs, rhs.neg)
+    }
+}
+impl Sub for &BigFixed {
+    type Output = BigFixed;
+
+    fn sub(self, rhs: &BigFixed) -> BigFixed {
+        self.signed_add(rhs, !rhs.neg)
+    }
+}
+impl Mul for &BigFixed {
+    type Output = BigFixed;
+
+    fn mul(self, rhs: &BigFixed) -> BigFixed {
+        debug_assert_eq!(self.frac, rhs.frac);
+
+        let n = self.mag.len();
+        let mut product = vec![0u64; 2 * n];
+        for (i, &a) in self.mag.iter().enumerate() {
+            if a == 0 {
+                continue;
+            }
+            let mut carry = 0u64;
+            for (j, &b) in rhs.mag.iter().enumerate() {
+                let t = product[i + j] + a as u64 * b as u64 + carry;
+                product[i + j] = t & 0xffff_ffff;
+                carry = t >> 32;
+            }
+            // Nothing has been written at `i + n` yet.
+            product[i + n] = carry;
+        }
+
+        // Drop the extra fractional limbs, the integer limbs past the
+        // first `INT_LIMBS` must be zero.
+        BigFixed {
+            neg: self.neg != rhs.neg,
+            mag: product[self.frac..self.frac + n]
+                .iter()
+                .map(|&l| l as u32)
+                .collect(),
+            frac: self.frac,
+            overflow: product[self.frac + n..].iter().any(|&l| l != 0)
+                || self.overflow
+                || rhs.overflow,
+        }
+    }
+}
+
+/// A complex number made of two [`BigFixed`].
+#[derive(Debug, Clone)]
+pub struct BigComplex {
+    pub re: BigFixed,
+    pub im: BigFixed,
+}
+
+impl BigComplex {
+    pub fn from_f64(re: f64, im: f64, frac: usize) -> BigComplex {
+        BigComplex {
+            re: BigFixed::from_f64(re, frac),
+            im: BigFixed::from_f64(im, frac),
+        }
+    }
+
+    pub fn to_f64(&self) -> (f64, f64) {
+        (self.re.to_f64(), self.im.to_f64())
+    }
+
+    pub fn overflowed(&self) -> bool {
+        self.re.overflowed() || self.im.overflowed()
+    }
+
+    pub fn add(&self, rhs: &BigComplex) -> BigComplex {
+        BigComplex {
+            re: &self.re + &rhs.re,
+            im: &self.im + &rhs.im,
+        }
+    }
+
+    pub fn mul(&self, rhs: &BigComplex) -> BigComplex {
+        BigComplex {
+            re: &(&self.re * &rhs.re) - &(&self.im * &rhs.im),
+            im: &(&self.re * &rhs.im) + &(&self.im * &rhs.re),
+        }
+    }
+
+    pub fn powi(&self, mut n: u32) -> BigComplex {
+        let frac = self.re.frac;
+        let mut base = self.clone();
+        let mut acc = BigComplex::from_f64(1., 0., frac);
+        while n > 0 {
+            if n & 1 == 1 {
+                acc = acc.mul(&base);
+            }
+            n >>= 1;
+            if n > 0 {
+                base = base.mul(&base);
+            }
+        }
+        acc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAC: usize = 4;
+
+    fn values() -> impl Iterator<Item = f64> {
+        [
+            0.,
+            1.,
+            -1.,
+            0.5,
+            -1.75,
+            std::f64::consts::PI,
+            -std::f64::consts::E,
+            1e-3,
+            13.5,
+        ]
+        .into_iter()
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() <= 1e-14 * b.abs().max(1.), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn from_f64_is_exact() {
+        for x in values().chain([2f64.powi(63), -2f64.powi(-60)]) {
+            let n = BigFixed::from_f64(x, FRAC);
+            assert_eq!(n.to_f64(), x);
+            assert!(!n.overflowed());
+        }
+    }
+
+    #[test]
+    fn arithmetic_matches_f64() {
+        for a in values() {
+            for b in values() {
+                let (x, y) = (BigFixed::from_f64(a, FRAC), BigFixed::from_f64(b, FRAC));
+                assert_close((&x + &y).to_f64(), a + b);
+                assert_close((&x - &y).to_f64(), a - b);
+                assert_close((&x * &y).to_f64(), a * b);
+            }
+        }
+    }
+
+    #[test]
+    fn complex_powi() {
+        let z = BigComplex::from_f64(1., 1., FRAC);
+        assert_eq!(z.powi(0).to_f64(), (1., 0.));
+        assert_eq!(z.powi(4).to_f64(), (-4., 0.));
+        let z = BigComplex::from_f64(0.5, -0.25, FRAC);
+        let (re, im) = z.powi(3).add(&z).to_f64();
+        // (0.5 - 0.25i)^3 = 0.03125 - 0.171875i
+        assert_close(re, 0.53125);
+        assert_close(im, -0.421875);
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(BigFixed::from_f64(2f64.powi(64), FRAC).overflowed());
+
+        let big = BigFixed::from_f64(2f64.powi(40), FRAC);
+        let product = &big * &big;
+        assert!(product.overflowed());
+        // The flag is kept by later operations.
+        assert!((&product - &product).overflowed());
+
+        let half = BigFixed::from_f64(-2f64.powi(63), FRAC);
+        assert!(!(&half - &BigFixed::from_f64(1., FRAC)).overflowed());
+        assert!((&half + &half).overflowed());
+
+        let z = BigComplex::from_f64(2f64.powi(20), 0., FRAC);
+        assert!(!z.powi(3).overflowed());
+        assert!(z.powi(4).overflowed());
+    }
+}
@@ -2,28 +2,46 @@ use cumulative_histogram::{compute_histogram, cumulate_histogram, get_histogram_
 use image::{Rgb, RgbImage};
 use serde::{Deserialize, Serialize};
 
-use crate::{mat::Mat2D, params::FrameParams, F};
+use crate::{
+    params::FrameParams,
+    rendering::{Channel, RawImage},
+    View, F,
+};
 
 pub fn color_raw_image(
     params: &FrameParams,
     coloring_mode: ColoringMode,
     custom_gradient: Option<&Vec<(f32, [u8; 3])>>,
-    mut raw_image: Mat2D<F>,
+    raw_image: &RawImage,
 ) -> RgbImage {
     let &FrameParams {
         img_width,
         img_height,
+        zoom,
+        center_x,
+        center_y,
+        rotate,
         ..
     } = params;
 
     let mut output_image = RgbImage::new(img_width, img_height);
+    let RawImage {
+        ref roots,
+        ref channels,
+        ..
+    } = *raw_image;
+    let mut raw_image = raw_image.channel(coloring_mode.channel());
 
-    let max_v = raw_image.vec.iter().copied().fold(0., F::max);
-    let min_v = raw_image.vec.iter().copied().fold(max_v, F::min);
+    // Channels such as `Argument` can be negative.
+    let max_v = raw_image.vec.iter().copied().fold(F::NEG_INFINITY, F::max);
+    let min_v = raw_image.vec.iter().copied().fold(F::INFINITY, F::min);
 
     match coloring_mode {
-        ColoringMode::CumulativeHistogram { map } => {
-            raw_image.vec.iter_mut().for_each(|v| *v /= max_v);
+        ColoringMode::CumulativeHistogram { map, .. } => {
+            raw_image
+                .vec
+                .iter_mut()
+                .for_each(|v| *v = (*v - min_v) / (max_v - min_v));
             let cumulative_histogram = cumulate_histogram(compute_histogram(&raw_image.vec));
             for j in 0..img_height as usize {
                 for i in 0..img_width as usize {
@@ -35,7 +53,7 @@ pub fn color_raw_image(
                 }
             }
         }
-        ColoringMode::MinMaxNorm { min, max, map } => {
+        ColoringMode::MinMaxNorm { min, max, map, .. } => {
             let min = min.unwrap_custom_or(min_v);
             let max = max.unwrap_custom_or(max_v);
 
@@ -65,6 +83,82 @@ pub fn color_raw_image(
                 }
             }
         }
+        ColoringMode::RootBasins { map } => {
+            let root_count = params.fractal.roots().len().max(1);
+
+            for j in 0..img_height as usize {
+                for i in 0..img_width as usize {
+                    let root = roots.as_ref().and_then(|roots| roots[(i, j)]);
+                    let pixel = if let Some(root) = root {
+                        let value = raw_image[(i, j)];
+                        // Pixels that take longer to converge are darker,
+                        // none when they all take as long.
+                        let t = if max_v > min_v {
+                            map.apply((value - min_v) / (max_v - min_v)).clamp(0., 1.)
+                        } else {
+                            0.
+                        };
+                        let hue = (root as F + 0.5) / root_count as F;
+                        let Rgb(base) = color_mapping(hue, custom_gradient);
+                        Rgb(base.map(|c| (c as F * (1. - t)) as u8))
+                    } else {
+                        Rgb([0, 0, 0])
+                    };
+                    output_image.put_pixel(i as u32, j as u32, pixel);
+                }
+            }
+        }
+        ColoringMode::Lyapunov { map } => {
+            // Diverging orbits give infinite exponents.
+            let finite = raw_image.vec.iter().copied().filter(|v| v.is_finite());
+            let min = finite.clone().fold(0., F::min);
+            let max = finite.fold(0., F::max);
+
+            for j in 0..img_height as usize {
+                for i in 0..img_width as usize {
+                    let value = raw_image[(i, j)];
+
+                    let t = if value < 0. {
+                        0.5 * (1. - map.apply(value / min))
+                    } else if value > 0. {
+                        0.5 + 0.5 * map.apply((value / max).min(1.))
+                    } else {
+                        0.5
+                    };
+
+                    output_image.put_pixel(i as u32, j as u32, color_mapping(t, custom_gradient));
+                }
+            }
+        }
+        ColoringMode::Nebulabrot { map } => {
+            let channels = channels
+                .clone()
+                .unwrap_or_else(|| [raw_image.clone(), raw_image.clone(), raw_image.clone()]);
+            let maxima = channels
+                .each_ref()
+                .map(|channel| channel.vec.iter().copied().fold(0., F::max));
+
+            for j in 0..img_height as usize {
+                for i in 0..img_width as usize {
+                    let pixel = std::array::from_fn(|k| {
+                        let t = map.apply(channels[k][(i, j)] / maxima[k]);
+                        (255. * t).clamp(0., 255.) as u8
+                    });
+                    output_image.put_pixel(i as u32, j as u32, Rgb(pixel));
+                }
+            }
+        }
+        ColoringMode::DistanceEstimate { thickness } => {
+            let view = View::new(img_width, img_height, zoom, center_x, center_y, rotate);
+            let width = thickness * view.pixel_size(img_width);
+
+            for j in 0..img_height as usize {
+                for i in 0..img_width as usize {
+                    let t = (raw_image[(i, j)] / width).min(1.);
+                    output_image.put_pixel(i as u32, j as u32, color_mapping(t, custom_gradient));
+                }
+            }
+        }
     };
 
     output_image
@@ -72,8 +166,12 @@ pub fn color_raw_image(
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum ColoringMode {
+    /// `channel` picks the channel of the raw image that is colored
+    /// instead of its values, the same goes for `MinMaxNorm`.
     CumulativeHistogram {
         map: MapValue,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        channel: Option<Channel>,
     },
     MinMaxNorm {
         #[serde(default)]
@@ -81,8 +179,54 @@ pub enum ColoringMode {
         #[serde(default)]
         max: Extremum,
         map: MapValue,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        channel: Option<Channel>,
     },
     BlackAndWhite,
+    /// For convergent fractals: each pixel gets a color of the gradient
+    /// depending on the root it converges to, darkened by the number of
+    /// iterations needed. Pixels that don't converge are black.
+    RootBasins {
+        map: MapValue,
+    },
+    /// For Lyapunov fractals: stable regions (negative exponents) use the
+    /// first half of the gradient, from the middle for exponents close to
+    /// zero to the start for the lowest one, and chaotic regions
+    /// (positive exponents) use the second half.
+    Lyapunov {
+        map: MapValue,
+    },
+    /// For orbit densities: the red, green and blue channels of the
+    /// image are each normalized by their maximum (without using the
+    /// gradient). Images with a single channel are drawn in grayscale.
+    Nebulabrot {
+        map: MapValue,
+    },
+    /// For polynomial recurrences: pixels closer to the boundary of the
+    /// set than `thickness` pixels get a color from the gradient, from
+    /// the start on the boundary (and inside) to the end at that
+    /// distance. Boundaries stay as thin at any zoom.
+    DistanceEstimate {
+        thickness: F,
+    },
+}
+
+impl ColoringMode {
+    /// Channel of the raw image that is colored instead of its values (see
+    /// [`RawImage::aovs`]).
+    pub fn channel(&self) -> Option<Channel> {
+        match *self {
+            ColoringMode::CumulativeHistogram { channel, .. }
+            | ColoringMode::MinMaxNorm { channel, .. } => channel,
+            ColoringMode::DistanceEstimate { .. } => Some(Channel::Distance),
+            _ => None,
+        }
+    }
+
+    /// Whether the distance estimate has to be rendered.
+    pub fn needs_distance(&self) -> bool {
+        self.channel() == Some(Channel::Distance)
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
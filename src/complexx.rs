@@ -1,29 +1,39 @@
-use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
 
-use crate::{F, FX};
+use crate::{simd::SimdFloat, F};
 
-/// A simd complex type. It holds 4 complex numbers and performs
-/// calculations on them at once.
+/// A simd complex type. It holds as many complex numbers as `X` has
+/// lanes and performs calculations on them at once.
 #[derive(Debug, Clone, Copy)]
-pub struct Complexx {
-    pub re: FX,
-    pub im: FX,
+pub struct Complexx<X> {
+    pub re: X,
+    pub im: X,
 }
 
-impl Complexx {
+impl<X: SimdFloat> Complexx<X> {
     #[inline]
-    pub fn splat(re: F, im: F) -> Complexx {
+    pub fn splat(re: F, im: F) -> Complexx<X> {
         Complexx {
-            re: FX::splat(re),
-            im: FX::splat(im),
+            re: X::splat(re),
+            im: X::splat(im),
         }
     }
 
     #[inline]
-    pub fn zeros() -> Complexx {
+    pub fn zeros() -> Complexx<X> {
         Complexx {
-            re: FX::splat(0.),
-            im: FX::splat(0.),
+            re: X::splat(0.),
+            im: X::splat(0.),
+        }
+    }
+
+    /// Lane-wise select: takes `t` where `mask` is set and `f`
+    /// elsewhere.
+    #[inline(always)]
+    pub fn select(mask: X, t: Complexx<X>, f: Complexx<X>) -> Complexx<X> {
+        Complexx {
+            re: mask.blend(t.re, f.re),
+            im: mask.blend(t.im, f.im),
         }
     }
 
@@ -33,49 +43,118 @@ impl Complexx {
     // }
 
     #[inline]
-    pub fn to_polar(self) -> (FX, FX) {
+    pub fn to_polar(self) -> (X, X) {
         (self.norm(), self.arg())
     }
 
     #[inline]
-    pub fn from_polar(r: FX, theta: FX) -> Complexx {
+    pub fn from_polar(r: X, theta: X) -> Complexx<X> {
         Complexx {
             re: r * theta.cos(),
             im: r * theta.sin(),
         }
     }
     #[inline]
-    pub fn from_polar_splat(r: F, theta: F) -> Complexx {
+    pub fn from_polar_splat(r: F, theta: F) -> Complexx<X> {
         Complexx {
-            re: FX::splat(r * theta.cos()),
-            im: FX::splat(r * theta.sin()),
+            re: X::splat(r * theta.cos()),
+            im: X::splat(r * theta.sin()),
         }
     }
 
     #[inline(always)]
-    pub fn norm_sqr(&self) -> FX {
+    pub fn norm_sqr(&self) -> X {
         self.re * self.re + self.im * self.im
     }
 
     #[inline]
-    pub fn norm(&self) -> FX {
+    pub fn norm(&self) -> X {
         self.norm_sqr().sqrt()
     }
 
     #[inline]
-    pub fn arg(&self) -> FX {
+    pub fn arg(&self) -> X {
         self.im.atan2(self.re)
     }
 
     #[inline]
-    pub fn powu(&self, n: usize) -> Complexx {
+    pub fn powu(&self, n: usize) -> Complexx<X> {
         (0..n).fold(*self, |acc, _| acc * acc)
     }
 
+    /// Computes `self^n` using exponentiation by squaring. The
+    /// accumulator starts at the lowest power instead of 1 so that small
+    /// powers give the same result as plain products (`z * z`,
+    /// `z * z * z`).
     #[inline]
-    pub fn powf(&self, exp: F) -> Complexx {
+    pub fn powi(&self, mut n: u32) -> Complexx<X> {
+        if n == 0 {
+            return Complexx::splat(1., 0.);
+        }
+
+        let mut base = *self;
+        while n & 1 == 0 {
+            base = base * base;
+            n >>= 1;
+        }
+        let mut acc = base;
+        n >>= 1;
+        while n > 0 {
+            base = base * base;
+            if n & 1 == 1 {
+                acc = base * acc;
+            }
+            n >>= 1;
+        }
+        acc
+    }
+
+    #[inline]
+    pub fn powf(&self, exp: F) -> Complexx<X> {
         let (r, theta) = self.to_polar();
-        Complexx::from_polar(r.powf(exp), theta * exp)
+        Complexx::from_polar(r.powf(exp), theta * X::splat(exp))
+    }
+
+    #[inline]
+    pub fn conj(&self) -> Complexx<X> {
+        Complexx {
+            re: self.re,
+            im: -self.im,
+        }
+    }
+
+    #[inline]
+    pub fn exp(&self) -> Complexx<X> {
+        Complexx::from_polar(self.re.exp(), self.im)
+    }
+
+    /// Principal value of the natural logarithm.
+    #[inline]
+    pub fn ln(&self) -> Complexx<X> {
+        Complexx {
+            re: self.norm_sqr().ln() * X::splat(0.5),
+            im: self.arg(),
+        }
+    }
+
+    #[inline]
+    pub fn sin(&self) -> Complexx<X> {
+        let (e, inv_e) = (self.im.exp(), (-self.im).exp());
+        let (cosh, sinh) = ((e + inv_e) * X::splat(0.5), (e - inv_e) * X::splat(0.5));
+        Complexx {
+            re: self.re.sin() * cosh,
+            im: self.re.cos() * sinh,
+        }
+    }
+
+    #[inline]
+    pub fn cos(&self) -> Complexx<X> {
+        let (e, inv_e) = (self.im.exp(), (-self.im).exp());
+        let (cosh, sinh) = ((e + inv_e) * X::splat(0.5), (e - inv_e) * X::splat(0.5));
+        Complexx {
+            re: self.re.cos() * cosh,
+            im: -self.re.sin() * sinh,
+        }
     }
 
     // #[inline]
@@ -85,8 +164,8 @@ impl Complexx {
     // }
 }
 
-impl Add for Complexx {
-    type Output = Complexx;
+impl<X: SimdFloat> Add for Complexx<X> {
+    type Output = Complexx<X>;
 
     #[inline(always)]
     fn add(self, rhs: Self) -> Self::Output {
@@ -96,15 +175,15 @@ impl Add for Complexx {
         }
     }
 }
-impl AddAssign for Complexx {
+impl<X: SimdFloat> AddAssign for Complexx<X> {
     #[inline(always)]
     fn add_assign(&mut self, rhs: Self) {
         self.re += rhs.re;
         self.im += rhs.im;
     }
 }
-impl Mul for Complexx {
-    type Output = Complexx;
+impl<X: SimdFloat> Mul for Complexx<X> {
+    type Output = Complexx<X>;
 
     #[inline(always)]
     fn mul(self, rhs: Self) -> Self::Output {
@@ -118,8 +197,20 @@ impl Mul for Complexx {
         }
     }
 }
-impl Neg for Complexx {
-    type Output = Complexx;
+impl<X: SimdFloat> Div for Complexx<X> {
+    type Output = Complexx<X>;
+
+    #[inline(always)]
+    fn div(self, rhs: Self) -> Self::Output {
+        let d = rhs.norm_sqr();
+        Complexx {
+            re: (self.re * rhs.re + self.im * rhs.im) / d,
+            im: (self.im * rhs.re - self.re * rhs.im) / d,
+        }
+    }
+}
+impl<X: SimdFloat> Neg for Complexx<X> {
+    type Output = Complexx<X>;
 
     #[inline(always)]
     fn neg(self) -> Self::Output {
@@ -129,8 +220,8 @@ impl Neg for Complexx {
         }
     }
 }
-impl Sub for Complexx {
-    type Output = Complexx;
+impl<X: SimdFloat> Sub for Complexx<X> {
+    type Output = Complexx<X>;
 
     #[inline(always)]
     fn sub(self, rhs: Self) -> Self::Output {
@@ -140,7 +231,7 @@ impl Sub for Complexx {
         }
     }
 }
-impl SubAssign for Complexx {
+impl<X: SimdFloat> SubAssign for Complexx<X> {
     #[inline(always)]
     fn sub_assign(&mut self, rhs: Self) {
         self.re -= rhs.re;
@@ -148,11 +239,12 @@ impl SubAssign for Complexx {
     }
 }
 
-impl Mul<F> for Complexx {
-    type Output = Complexx;
+impl<X: SimdFloat> Mul<F> for Complexx<X> {
+    type Output = Complexx<X>;
 
     #[inline(always)]
     fn mul(self, rhs: F) -> Self::Output {
+        let rhs = X::splat(rhs);
         Complexx {
             re: self.re * rhs,
             im: self.im * rhs,
@@ -160,11 +252,11 @@ impl Mul<F> for Complexx {
     }
 }
 
-impl Mul<FX> for Complexx {
-    type Output = Complexx;
+impl<X: SimdFloat> Mul<X> for Complexx<X> {
+    type Output = Complexx<X>;
 
     #[inline(always)]
-    fn mul(self, rhs: FX) -> Self::Output {
+    fn mul(self, rhs: X) -> Self::Output {
         Complexx {
             re: self.re * rhs,
             im: self.im * rhs,
@@ -0,0 +1,198 @@
+//! Orbit density rendering (Buddhabrot and Nebulabrot): instead of
+//! coloring each pixel using the orbit of its own point, random points
+//! are iterated and every value of their orbits is counted in the pixel
+//! it falls in.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    complexx::Complexx,
+    error::{ErrorKind, Result},
+    fractal::SampleOptions,
+    mat::Mat2D,
+    params::FrameParams,
+    progress::Progress,
+    rendering::RawImage,
+    simd::{SimdFloat, MAX_LANES},
+    View, F,
+};
+
+/// Default half size of the square random points are picked in.
+const DEFAULT_RADIUS: F = 2.;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct OrbitDensity {
+    /// Number of random points iterated per pixel of the image (rounded
+    /// up to a multiple of the number of SIMD lanes).
+    pub samples_per_pixel: u32,
+    /// Iteration limits of the red, green and blue channels
+    /// (Nebulabrot). When not set, there is a single channel limited to
+    /// `max_iter` (Buddhabrot).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<[u32; 3]>,
+    /// Count the orbits that don't escape before the limit instead of
+    /// the ones that do (anti-Buddhabrot).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trapped: Option<bool>,
+    /// Points are picked in the square `[-radius, radius]²` (which is
+    /// the set of initial values in Julia mode), defaults to 2.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub radius: Option<F>,
+}
+
+impl Default for OrbitDensity {
+    fn default() -> Self {
+        OrbitDensity {
+            samples_per_pixel: 64,
+            channels: None,
+            trapped: None,
+            radius: None,
+        }
+    }
+}
+
+pub fn render_orbit_density_with<X: SimdFloat>(
+    params: &FrameParams,
+    view: &View,
+    density: &OrbitDensity,
+    progress: Option<Progress>,
+) -> Result<RawImage> {
+    let &FrameParams {
+        img_width,
+        img_height,
+
+        ref fractal,
+        julia,
+
+        max_iter,
+        bailout,
+        escape_metric,
+        check_periodicity,
+        ..
+    } = params;
+
+    if !fractal.has_orbits() {
+        return Err(ErrorKind::NoOrbits);
+    }
+
+    let limits = match density.channels {
+        Some(channels) => channels.to_vec(),
+        None => vec![max_iter],
+    };
+    let trapped = density.trapped.unwrap_or(false);
+    let radius = density.radius.unwrap_or(DEFAULT_RADIUS);
+
+    let formula = fractal.compile_formula()?;
+    let roots = fractal.roots();
+
+    let options = SampleOptions {
+        max_iter: limits.iter().copied().max().unwrap_or(0),
+        smooth: false,
+        julia,
+        bailout,
+        escape_metric: escape_metric.unwrap_or_default(),
+        formula: formula.as_ref(),
+        roots: &roots,
+        distance_estimation: false,
+        orbit_trap: None,
+        orbit_average: None,
+        check_periodicity: check_periodicity.unwrap_or(false),
+    };
+
+    let (img_width, img_height) = (img_width as usize, img_height as usize);
+    let pixel_count = img_width * img_height;
+    let pixel_index = view.pixel_index(img_width, img_height);
+
+    let counts: Vec<AtomicU32> = (0..limits.len() * pixel_count)
+        .map(|_| AtomicU32::new(0))
+        .collect();
+
+    let batch_count = density.samples_per_pixel.div_ceil(X::LANES as u32);
+    (0..pixel_count).into_par_iter().for_each(|unit| {
+        // Seeded so that renders are reproducible.
+        let mut rng = fastrand::Rng::with_seed(unit as u64);
+
+        for _ in 0..batch_count {
+            let re = X::from_fn(|_| radius * (2. * rng.f64() - 1.));
+            let im = X::from_fn(|_| radius * (2. * rng.f64() - 1.));
+            let point = Complexx { re, im };
+
+            // Iteration counts, `options.max_iter` for orbits that
+            // didn't escape.
+            let iter = fractal.sample(point, options).value;
+
+            // Number of values of each orbit counted in each channel.
+            let mut lengths = vec![[0; MAX_LANES]; limits.len()];
+            let mut trace_len = 0;
+            for (lengths, &limit) in lengths.iter_mut().zip(&limits) {
+                for (length, &iter) in lengths.iter_mut().zip(&iter).take(X::LANES) {
+                    let iter = iter as u32;
+                    if (iter < limit) != trapped {
+                        *length = iter.min(limit);
+                        trace_len = trace_len.max(*length);
+                    }
+                }
+            }
+            if trace_len == 0 {
+                continue;
+            }
+
+            fractal.trace(
+                point,
+                SampleOptions {
+                    max_iter: trace_len,
+                    ..options
+                },
+                |i, z| {
+                    let (re, im) = (z.re.to_array(), z.im.to_array());
+                    for lane in 0..X::LANES {
+                        let Some(index) = pixel_index(re[lane], im[lane]) else {
+                            continue;
+                        };
+                        for (channel, lengths) in lengths.iter().enumerate() {
+                            if i < lengths[lane] {
+                                counts[channel * pixel_count + index]
+                                    .fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                },
+            );
+        }
+
+        if let Some(progress) = &progress {
+            progress.incr();
+        }
+    });
+
+    let channels: Vec<Mat2D<F>> = counts
+        .chunks(pixel_count)
+        .map(|counts| Mat2D {
+            width: img_width,
+            height: img_height,
+            vec: counts
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed) as F)
+                .collect(),
+        })
+        .collect();
+
+    let mut values = Mat2D::filled_with(0., img_width, img_height);
+    for channel in &channels {
+        for (value, &count) in values.vec.iter_mut().zip(&channel.vec) {
+            *value += count;
+        }
+    }
+
+    Ok(RawImage {
+        values,
+        roots: None,
+        channels: <[Mat2D<F>; 3]>::try_from(channels).ok(),
+        aovs: None,
+        aov_channels: Vec::new(),
+        samples: pixel_count * batch_count as usize * X::LANES,
+    })
+}
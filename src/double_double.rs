@@ -0,0 +1,473 @@
+//! A simd double-double type: each lane is the unevaluated sum `hi + lo`
+//! of two f64, which gives about 106 bits of precision (around 1e-32).
+//!
+//! It mirrors the parts of the `wide` api used by
+//! [`SimdFloat`](crate::simd::SimdFloat). Comparisons return masks stored in both
+//! `hi` and `lo` so they can be used with [`DoubleF64x4::blend`].
+//!
+//! The algorithms are the usual ones from the QD library (Hida, Li and
+//! Bailey). Products don't rely on fma, they use Dekker's splitting
+//! instead.
+
+use std::{
+    f64::consts,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+};
+
+use wide::{f64x4, CmpEq, CmpGt, CmpLe, CmpLt};
+
+#[derive(Debug, Clone, Copy)]
+pub struct DoubleF64x4 {
+    pub hi: f64x4,
+    pub lo: f64x4,
+}
+
+const LN_2: (f64, f64) = (consts::LN_2, 2.3190468138462996e-17);
+const TAU: (f64, f64) = (consts::TAU, 2.4492935982947064e-16);
+const FRAC_PI_2: (f64, f64) = (consts::FRAC_PI_2, 6.123233995736766e-17);
+
+/// `a + b` and the rounding error.
+#[inline(always)]
+fn two_sum(a: f64x4, b: f64x4) -> (f64x4, f64x4) {
+    let s = a + b;
+    let bb = s - a;
+    (s, (a - (s - bb)) + (b - bb))
+}
+
+/// Same as [`two_sum`], requires `|a| >= |b|`.
+#[inline(always)]
+fn quick_two_sum(a: f64x4, b: f64x4) -> (f64x4, f64x4) {
+    let s = a + b;
+    (s, b - (s - a))
+}
+
+#[inline(always)]
+fn split(a: f64x4) -> (f64x4, f64x4) {
+    const SPLITTER: f64 = 134217729.; // 2^27 + 1
+    let t = a * SPLITTER;
+    let hi = t - (t - a);
+    (hi, a - hi)
+}
+
+/// `a * b` and the rounding error.
+#[inline(always)]
+fn two_prod(a: f64x4, b: f64x4) -> (f64x4, f64x4) {
+    let p = a * b;
+    let (a_hi, a_lo) = split(a);
+    let (b_hi, b_lo) = split(b);
+    (
+        p,
+        ((a_hi * b_hi - p) + a_hi * b_lo + a_lo * b_hi) + a_lo * b_lo,
+    )
+}
+
+impl DoubleF64x4 {
+    /// 2^-104
+    pub const EPSILON: f64 = 4.930380657631324e-32;
+
+    #[inline(always)]
+    pub fn splat(x: f64) -> DoubleF64x4 {
+        DoubleF64x4 {
+            hi: f64x4::splat(x),
+            lo: f64x4::ZERO,
+        }
+    }
+
+    #[inline(always)]
+    fn from_hi(hi: f64x4) -> DoubleF64x4 {
+        DoubleF64x4 {
+            hi,
+            lo: f64x4::ZERO,
+        }
+    }
+
+    #[inline(always)]
+    fn constant((hi, lo): (f64, f64)) -> DoubleF64x4 {
+        DoubleF64x4 {
+            hi: f64x4::splat(hi),
+            lo: f64x4::splat(lo),
+        }
+    }
+
+    #[inline(always)]
+    fn from_mask(mask: f64x4) -> DoubleF64x4 {
+        DoubleF64x4 { hi: mask, lo: mask }
+    }
+
+    #[inline(always)]
+    fn renormalize((hi, lo): (f64x4, f64x4)) -> DoubleF64x4 {
+        let (hi, lo) = quick_two_sum(hi, lo);
+        DoubleF64x4 { hi, lo }
+    }
+
+    #[inline]
+    pub fn to_array(self) -> [f64; 4] {
+        (self.hi + self.lo).to_array()
+    }
+
+    #[inline(always)]
+    pub fn any(self) -> bool {
+        self.hi.any()
+    }
+
+    #[inline(always)]
+    pub fn move_mask(self) -> i32 {
+        self.hi.move_mask()
+    }
+
+    /// Uses `self` as a mask, takes `t` where it is set and `f`
+    /// elsewhere.
+    #[inline(always)]
+    pub fn blend(self, t: DoubleF64x4, f: DoubleF64x4) -> DoubleF64x4 {
+        DoubleF64x4 {
+            hi: self.hi.blend(t.hi, f.hi),
+            lo: self.hi.blend(t.lo, f.lo),
+        }
+    }
+
+    #[inline]
+    pub fn abs(self) -> DoubleF64x4 {
+        DoubleF64x4::from_mask(self.hi.cmp_lt(f64x4::ZERO)).blend(-self, self)
+    }
+
+    #[inline]
+    pub fn max(self, rhs: DoubleF64x4) -> DoubleF64x4 {
+        self.cmp_lt(rhs).blend(rhs, self)
+    }
+
+    #[inline]
+    pub fn min(self, rhs: DoubleF64x4) -> DoubleF64x4 {
+        DoubleF64x4::from_mask((self - rhs).hi.cmp_gt(f64x4::ZERO)).blend(rhs, self)
+    }
+
+    /// Multiplication by a power of two, this is exact.
+    #[inline(always)]
+    fn scale(self, factor: f64x4) -> DoubleF64x4 {
+        DoubleF64x4 {
+            hi: self.hi * factor,
+            lo: self.lo * factor,
+        }
+    }
+
+    #[inline]
+    fn mul_f64x4(self, rhs: f64x4) -> DoubleF64x4 {
+        let (p, e) = two_prod(self.hi, rhs);
+        DoubleF64x4::renormalize((p, e + self.lo * rhs))
+    }
+
+    #[inline]
+    fn div_f64x4(self, rhs: f64x4) -> DoubleF64x4 {
+        let q1 = self.hi / rhs;
+        let (p, e) = two_prod(q1, rhs);
+        let (s, f) = two_sum(self.hi, -p);
+        let q2 = (s + (f - e + self.lo)) / rhs;
+        DoubleF64x4::renormalize((q1, q2))
+    }
+
+    #[inline]
+    pub fn sqrt(self) -> DoubleF64x4 {
+        // One Newton step from the f64 square root: s + (a - s^2) / 2s
+        let s = self.hi.sqrt();
+        let s_dd = DoubleF64x4::from_hi(s);
+        let r = self - s_dd * s_dd;
+        let sqrt = DoubleF64x4::renormalize((s, r.hi / (s + s)));
+
+        DoubleF64x4::from_mask(self.hi.cmp_eq(f64x4::ZERO)).blend(DoubleF64x4::splat(0.), sqrt)
+    }
+
+    pub fn exp(self) -> DoubleF64x4 {
+        // exp(x) = 2^k * exp(r)^512 where x = k ln(2) + 512 r
+        let k = (self.hi / LN_2.0).round();
+        let r = (self - DoubleF64x4::constant(LN_2).mul_f64x4(k)).scale(f64x4::splat(1. / 512.));
+
+        // Taylor series, |r| < 7e-4
+        let mut term = r;
+        let mut sum = DoubleF64x4::splat(1.) + r;
+        for n in 2..=10 {
+            term = (term * r).div_f64x4(f64x4::splat(n as f64));
+            sum += term;
+        }
+        for _ in 0..9 {
+            sum = sum * sum;
+        }
+
+        let pow2 = f64x4::from(k.to_array().map(|k| 2f64.powi(k as i32)));
+        sum.scale(pow2)
+    }
+
+    pub fn ln(self) -> DoubleF64x4 {
+        // One Newton step on exp(y) = x from the f64 logarithm, which
+        // has to be correctly rounded: the step only squares its error.
+        let y_hi = f64x4::from(self.hi.to_array().map(f64::ln));
+        let y = DoubleF64x4::from_hi(y_hi);
+        let ln = y + self * (-y).exp() - DoubleF64x4::splat(1.);
+
+        // -inf for 0 and inf for inf, as for f64.
+        DoubleF64x4::from_mask(y_hi.abs().cmp_eq(f64x4::splat(f64::INFINITY))).blend(y, ln)
+    }
+
+    pub fn sin_cos(self) -> (DoubleF64x4, DoubleF64x4) {
+        // Reduce to |r| <= pi/4 with x = k tau + j pi/2 + r
+        let k = (self.hi / TAU.0).round();
+        let r = self - DoubleF64x4::constant(TAU).mul_f64x4(k);
+        let j = (r.hi / FRAC_PI_2.0).round();
+        let r = r - DoubleF64x4::constant(FRAC_PI_2).mul_f64x4(j);
+
+        // Taylor series
+        let r2 = r * r;
+        let mut term = r;
+        let mut sin = r;
+        for n in (3..=29).step_by(2) {
+            term = -(term * r2).div_f64x4(f64x4::splat(((n - 1) * n) as f64));
+            sin += term;
+        }
+        let mut term = DoubleF64x4::splat(1.);
+        let mut cos = term;
+        for n in (2..=28).step_by(2) {
+            term = -(term * r2).div_f64x4(f64x4::splat(((n - 1) * n) as f64));
+            cos += term;
+        }
+
+        let m1 = DoubleF64x4::from_mask(j.cmp_eq(f64x4::splat(1.)));
+        let m_1 = DoubleF64x4::from_mask(j.cmp_eq(f64x4::splat(-1.)));
+        let m2 = DoubleF64x4::from_mask(j.abs().cmp_eq(f64x4::splat(2.)));
+
+        (
+            m1.blend(cos, m_1.blend(-cos, m2.blend(-sin, sin))),
+            m1.blend(-sin, m_1.blend(sin, m2.blend(-cos, cos))),
+        )
+    }
+
+    #[inline]
+    pub fn sin(self) -> DoubleF64x4 {
+        self.sin_cos().0
+    }
+
+    #[inline]
+    pub fn cos(self) -> DoubleF64x4 {
+        self.sin_cos().1
+    }
+
+    /// Four quadrant arctangent of `self` (y) and `x`.
+    pub fn atan2(self, x: DoubleF64x4) -> DoubleF64x4 {
+        // One Newton step on y cos(t) - x sin(t) = 0 from the f64
+        // arctangent.
+        let t0 = self.hi.atan2(x.hi);
+        let (sin, cos) = DoubleF64x4::from_hi(t0).sin_cos();
+        let t = DoubleF64x4::from_hi(t0) + (self * cos - x * sin) / (x * cos + self * sin);
+
+        let is_origin = self.hi.cmp_eq(f64x4::ZERO) & x.hi.cmp_eq(f64x4::ZERO);
+        DoubleF64x4::from_mask(is_origin).blend(DoubleF64x4::from_hi(t0), t)
+    }
+
+    pub fn powf(self, exp: f64) -> DoubleF64x4 {
+        let pow = (self.ln() * exp).exp();
+        DoubleF64x4::from_mask(self.hi.cmp_eq(f64x4::ZERO)).blend(DoubleF64x4::splat(0.), pow)
+    }
+}
+
+impl From<[f64; 4]> for DoubleF64x4 {
+    #[inline(always)]
+    fn from(array: [f64; 4]) -> DoubleF64x4 {
+        DoubleF64x4::from_hi(f64x4::from(array))
+    }
+}
+
+impl Add for DoubleF64x4 {
+    type Output = DoubleF64x4;
+
+    #[inline(always)]
+    fn add(self, rhs: DoubleF64x4) -> DoubleF64x4 {
+        let (s, e) = two_sum(self.hi, rhs.hi);
+        let (t, f) = two_sum(self.lo, rhs.lo);
+        let (s, e) = quick_two_sum(s, e + t);
+        DoubleF64x4::renormalize((s, e + f))
+    }
+}
+impl AddAssign for DoubleF64x4 {
+    #[inline(always)]
+    fn add_assign(&mut self, rhs: DoubleF64x4) {
+        *self = *self + rhs;
+    }
+}
+impl Neg for DoubleF64x4 {
+    type Output = DoubleF64x4;
+
+    #[inline(always)]
+    fn neg(self) -> DoubleF64x4 {
+        DoubleF64x4 {
+            hi: -self.hi,
+            lo: -self.lo,
+        }
+    }
+}
+impl Sub for DoubleF64x4 {
+    type Output = DoubleF64x4;
+
+    #[inline(always)]
+    fn sub(self, rhs: DoubleF64x4) -> DoubleF64x4 {
+        self + -rhs
+    }
+}
+impl SubAssign for DoubleF64x4 {
+    #[inline(always)]
+    fn sub_assign(&mut self, rhs: DoubleF64x4) {
+        *self = *self - rhs;
+    }
+}
+impl Mul for DoubleF64x4 {
+    type Output = DoubleF64x4;
+
+    #[inline(always)]
+    fn mul(self, rhs: DoubleF64x4) -> DoubleF64x4 {
+        let (p, e) = two_prod(self.hi, rhs.hi);
+        DoubleF64x4::renormalize((p, e + (self.hi * rhs.lo + self.lo * rhs.hi)))
+    }
+}
+impl Div for DoubleF64x4 {
+    type Output = DoubleF64x4;
+
+    #[inline]
+    fn div(self, rhs: DoubleF64x4) -> DoubleF64x4 {
+        let q1 = self.hi / rhs.hi;
+        let r = self - rhs.mul_f64x4(q1);
+        let q2 = r.hi / rhs.hi;
+        let r = r - rhs.mul_f64x4(q2);
+        let q3 = r.hi / rhs.hi;
+        DoubleF64x4::renormalize(quick_two_sum(q1, q2)) + DoubleF64x4::from_hi(q3)
+    }
+}
+
+impl Mul<f64> for DoubleF64x4 {
+    type Output = DoubleF64x4;
+
+    #[inline(always)]
+    fn mul(self, rhs: f64) -> DoubleF64x4 {
+        self.mul_f64x4(f64x4::splat(rhs))
+    }
+}
+impl Mul<DoubleF64x4> for f64 {
+    type Output = DoubleF64x4;
+
+    #[inline(always)]
+    fn mul(self, rhs: DoubleF64x4) -> DoubleF64x4 {
+        rhs * self
+    }
+}
+impl Div<f64> for DoubleF64x4 {
+    type Output = DoubleF64x4;
+
+    #[inline(always)]
+    fn div(self, rhs: f64) -> DoubleF64x4 {
+        self.div_f64x4(f64x4::splat(rhs))
+    }
+}
+
+impl CmpLe for DoubleF64x4 {
+    type Output = DoubleF64x4;
+
+    #[inline(always)]
+    fn cmp_le(self, rhs: DoubleF64x4) -> DoubleF64x4 {
+        DoubleF64x4::from_mask((self - rhs).hi.cmp_le(f64x4::ZERO))
+    }
+}
+impl CmpLt for DoubleF64x4 {
+    type Output = DoubleF64x4;
+
+    #[inline(always)]
+    fn cmp_lt(self, rhs: DoubleF64x4) -> DoubleF64x4 {
+        DoubleF64x4::from_mask((self - rhs).hi.cmp_lt(f64x4::ZERO))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `|a - b|` in each lane.
+    fn error(a: DoubleF64x4, b: DoubleF64x4) -> [f64; 4] {
+        (a - b).abs().to_array()
+    }
+
+    fn assert_close(a: DoubleF64x4, b: DoubleF64x4, tolerance: f64) {
+        let e = error(a, b);
+        assert!(
+            e.iter().all(|&e| e <= tolerance),
+            "{:?} != {:?} ({:?})",
+            a,
+            b,
+            e
+        );
+    }
+
+    fn values() -> DoubleF64x4 {
+        DoubleF64x4::from([0.7, 3., -1.25, 123.456])
+    }
+
+    #[test]
+    fn keeps_low_bits() {
+        let tiny = 2f64.powi(-80);
+        let x = DoubleF64x4::splat(1.) + DoubleF64x4::splat(tiny);
+        assert_eq!((x - DoubleF64x4::splat(1.)).to_array(), [tiny; 4]);
+        assert_eq!((x * x - DoubleF64x4::splat(1.)).to_array(), [2. * tiny; 4]);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let one = DoubleF64x4::splat(1.);
+        let third = one / DoubleF64x4::splat(3.);
+        assert_close(third * 3., one, 1e-31);
+        assert_close(third + third + third, one, 1e-31);
+
+        let x = values();
+        assert_close(x / x, one, 1e-31);
+        assert_close((x * x).sqrt(), x.abs(), 1e-29);
+        assert_close(x.div_f64x4(f64x4::splat(7.)) * 7., x, 1e-29);
+        let two = DoubleF64x4::splat(2.);
+        assert_close(two.sqrt() * two.sqrt(), two, 1e-31);
+    }
+
+    #[test]
+    fn functions() {
+        // The reduction of `exp` costs a few bits.
+        let e = DoubleF64x4::constant((consts::E, 1.4456468917292502e-16));
+        assert_close(DoubleF64x4::splat(1.).exp(), e, 1e-28);
+        assert_close(e.ln(), DoubleF64x4::splat(1.), 1e-28);
+
+        let x = values();
+        assert_close(x.exp().ln(), x, 1e-28);
+        let ln = DoubleF64x4::from([0., f64::INFINITY, -1., 1.])
+            .ln()
+            .to_array();
+        assert_eq!(ln[..2], [f64::NEG_INFINITY, f64::INFINITY]);
+        assert!(ln[2].is_nan());
+        assert_eq!(ln[3], 0.);
+        let (sin, cos) = x.sin_cos();
+        assert_close(sin * sin + cos * cos, DoubleF64x4::splat(1.), 1e-30);
+        assert_close(x.sin(), sin, 0.);
+
+        let pi = DoubleF64x4::constant(TAU) * 0.5;
+        let one = DoubleF64x4::splat(1.);
+        assert_close(one.atan2(one) * 4., pi, 1e-31);
+        assert_close(pi.sin(), DoubleF64x4::splat(0.), 1e-31);
+        assert_close(pi.cos(), -one, 1e-31);
+        assert_close(
+            DoubleF64x4::splat(2.).powf(0.5) * 2f64.sqrt(),
+            DoubleF64x4::splat(2.),
+            1e-15,
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        let one = DoubleF64x4::splat(1.);
+        let above = one + DoubleF64x4::splat(2f64.powi(-80));
+        assert!(!above.cmp_le(one).any());
+        assert!(one.cmp_lt(above).any());
+        assert_eq!(one.cmp_le(one).move_mask(), 0b1111);
+        assert_eq!(one.cmp_lt(one).move_mask(), 0);
+        let mask = values().cmp_lt(DoubleF64x4::splat(1.));
+        assert_eq!(mask.move_mask(), 0b0101);
+        assert_eq!(mask.blend(one, -one).to_array(), [1., -1., 1., -1.]);
+    }
+}
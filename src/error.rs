@@ -2,6 +2,8 @@ use std::{fmt::Debug, io};
 
 use ron::de::SpannedError;
 
+use crate::formula::FormulaError;
+
 pub type Result<T> = std::result::Result<T, ErrorKind>;
 
 pub enum ErrorKind {
@@ -10,6 +12,11 @@ pub enum ErrorKind {
     DecodeParameterFile(SpannedError),
     EncodeParameterFile(ron::Error),
     SaveImage(image::ImageError),
+    ParseFormula(FormulaError),
+    NoOrbits,
+    NoDistanceEstimate,
+    NoOrbitAverage,
+    ConstantPolynomial,
     StartGui,
 }
 
@@ -31,6 +38,33 @@ impl Debug for ErrorKind {
             ErrorKind::SaveImage(e) => {
                 writeln!(f, "Failed to save image: {}", e)
             }
+            ErrorKind::ParseFormula(e) => {
+                writeln!(f, "Failed to parse formula: {}", e)
+            }
+            ErrorKind::NoOrbits => {
+                writeln!(
+                    f,
+                    "This fractal has no orbits, it can't be rendered as an orbit density or with an orbit trap"
+                )
+            }
+            ErrorKind::NoDistanceEstimate => {
+                writeln!(
+                    f,
+                    "Distance estimation only works for polynomial recurrences, without orbit density"
+                )
+            }
+            ErrorKind::NoOrbitAverage => {
+                writeln!(
+                    f,
+                    "Orbit averages only work for escape-time fractals, without orbit density"
+                )
+            }
+            ErrorKind::ConstantPolynomial => {
+                writeln!(
+                    f,
+                    "The polynomial of a root finding fractal must have a degree of at least 1"
+                )
+            }
             ErrorKind::StartGui => {
                 writeln!(f, "Failed to start gui")
             }
@@ -0,0 +1,288 @@
+//! Fractal flames: iterated function systems rendered with the chaos
+//! game. A point jumps from transform to transform (picked at random
+//! according to their weights) and every position it reaches is counted
+//! in a histogram along with the color indices of the transforms it
+//! went through. The histogram is then drawn using the logarithm of the
+//! densities.
+
+use std::f64::consts::PI;
+
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{coloring::color_mapping, params::FlameParams, progress::Progress, View, F};
+
+/// Iterations of each walker that are not counted (at the start and
+/// after diverging), so that the point has reached the attractor.
+const FUSE_ITER: u32 = 20;
+
+const DEFAULT_GAMMA: F = 2.2;
+
+/// A transform of an iterated function system: the point goes through
+/// the affine transform then through the weighted sum of the
+/// variations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenericXform<T> {
+    /// Relative probability of picking this transform.
+    pub weight: T,
+    /// `(a, b, c, d, e, f)`: `(x, y)` becomes `(a x + b y + c, d x + e y + f)`.
+    pub affine: (T, T, T, T, T, T),
+    pub variations: Vec<(Variation, T)>,
+    /// Position in the gradient, between 0 and 1. The color of the point
+    /// moves halfway towards it each time the transform is used.
+    pub color: T,
+}
+
+pub type Xform = GenericXform<F>;
+
+impl<T> GenericXform<T> {
+    /// Maps the values of every parameter.
+    pub fn map_params<U>(&self, f: &impl Fn(&T) -> U) -> GenericXform<U> {
+        let (a, b, c, d, e, g) = &self.affine;
+        GenericXform {
+            weight: f(&self.weight),
+            affine: (f(a), f(b), f(c), f(d), f(e), f(g)),
+            variations: self
+                .variations
+                .iter()
+                .map(|(variation, weight)| (*variation, f(weight)))
+                .collect(),
+            color: f(&self.color),
+        }
+    }
+}
+
+impl Xform {
+    #[inline]
+    fn apply(&self, (x, y): (F, F)) -> (F, F) {
+        let (a, b, c, d, e, f) = self.affine;
+        let p = (a * x + b * y + c, d * x + e * y + f);
+
+        self.variations
+            .iter()
+            .fold((0., 0.), |(x, y), &(variation, weight)| {
+                let (vx, vy) = variation.apply(p);
+                (x + weight * vx, y + weight * vy)
+            })
+    }
+}
+
+/// Nonlinear functions of the plane, see "The Fractal Flame Algorithm"
+/// by Scott Draves and Erik Reckase. `r` is the distance to the origin
+/// and `θ = atan(x / y)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Variation {
+    /// `(x, y)`
+    Linear,
+    /// `(sin x, sin y)`
+    Sinusoidal,
+    /// `(x, y) / r²`
+    Spherical,
+    /// `(x sin r² - y cos r², x cos r² + y sin r²)`
+    Swirl,
+    /// `((x - y) (x + y), 2 x y) / r`
+    Horseshoe,
+    /// `(θ / π, r - 1)`
+    Polar,
+    /// `r (sin(θ + r), cos(θ - r))`
+    Handkerchief,
+    /// `r (sin(θ r), -cos(θ r))`
+    Heart,
+    /// `θ / π (sin(π r), cos(π r))`
+    Disc,
+    /// `(cos θ + sin r, sin θ - cos r) / r`
+    Spiral,
+    /// `(sin θ / r, r cos θ)`
+    Hyperbolic,
+    /// `(sin θ cos r, cos θ sin r)`
+    Diamond,
+    /// `x` doubled when negative and `y` halved when negative.
+    Bent,
+    /// `2 (y, x) / (r + 1)`
+    Fisheye,
+    /// `exp(x - 1) (cos(π y), sin(π y))`
+    Exponential,
+}
+
+impl Variation {
+    #[inline]
+    fn apply(self, (x, y): (F, F)) -> (F, F) {
+        let r2 = x * x + y * y;
+        let r = r2.sqrt();
+        let theta = x.atan2(y);
+
+        match self {
+            Variation::Linear => (x, y),
+            Variation::Sinusoidal => (x.sin(), y.sin()),
+            Variation::Spherical => (x / r2, y / r2),
+            Variation::Swirl => {
+                let (sin, cos) = r2.sin_cos();
+                (x * sin - y * cos, x * cos + y * sin)
+            }
+            Variation::Horseshoe => ((x - y) * (x + y) / r, 2. * x * y / r),
+            Variation::Polar => (theta / PI, r - 1.),
+            Variation::Handkerchief => (r * (theta + r).sin(), r * (theta - r).cos()),
+            Variation::Heart => {
+                let (sin, cos) = (theta * r).sin_cos();
+                (r * sin, -r * cos)
+            }
+            Variation::Disc => {
+                let (sin, cos) = (PI * r).sin_cos();
+                (theta / PI * sin, theta / PI * cos)
+            }
+            Variation::Spiral => ((theta.cos() + r.sin()) / r, (theta.sin() - r.cos()) / r),
+            Variation::Hyperbolic => (theta.sin() / r, r * theta.cos()),
+            Variation::Diamond => (theta.sin() * r.cos(), theta.cos() * r.sin()),
+            Variation::Bent => (
+                if x < 0. { 2. * x } else { x },
+                if y < 0. { y / 2. } else { y },
+            ),
+            Variation::Fisheye => (2. * y / (r + 1.), 2. * x / (r + 1.)),
+            Variation::Exponential => {
+                let (sin, cos) = (PI * y).sin_cos();
+                let e = (x - 1.).exp();
+                (e * cos, e * sin)
+            }
+        }
+    }
+}
+
+/// Number of points and sum of their colors for each pixel.
+pub struct Histogram {
+    pub counts: Vec<F>,
+    pub colors: Vec<F>,
+}
+
+impl Histogram {
+    fn new(len: usize) -> Histogram {
+        Histogram {
+            counts: vec![0.; len],
+            colors: vec![0.; len],
+        }
+    }
+}
+
+/// Runs the chaos game, `img_width * img_height` walkers each count
+/// `samples_per_pixel` points.
+pub fn render_flame(params: &FlameParams, view: &View, progress: Option<Progress>) -> Histogram {
+    let &FlameParams {
+        img_width,
+        img_height,
+        ref xforms,
+        samples_per_pixel,
+        ..
+    } = params;
+
+    let (img_width, img_height) = (img_width as usize, img_height as usize);
+    let pixel_count = img_width * img_height;
+    let pixel_index = view.pixel_index(img_width, img_height);
+
+    let cumulative_weights: Vec<F> = xforms
+        .iter()
+        .scan(0., |total, xform| {
+            *total += xform.weight.max(0.);
+            Some(*total)
+        })
+        .collect();
+    let total_weight = cumulative_weights.last().copied().unwrap_or(0.);
+    if total_weight <= 0. {
+        return Histogram::new(pixel_count);
+    }
+
+    // Each thread fills its own histogram, they are summed afterwards.
+    let thread_count = rayon::current_num_threads();
+    let histograms: Vec<Histogram> = (0..thread_count)
+        .into_par_iter()
+        .map(|thread| {
+            let mut histogram = Histogram::new(pixel_count);
+
+            for walker in (thread..pixel_count).step_by(thread_count) {
+                // Seeded so that renders are reproducible.
+                let mut rng = fastrand::Rng::with_seed(walker as u64);
+                let random_point =
+                    |rng: &mut fastrand::Rng| (2. * rng.f64() - 1., 2. * rng.f64() - 1.);
+
+                let mut p = random_point(&mut rng);
+                let mut color = 0.5;
+                let mut fuse = FUSE_ITER;
+                for _ in 0..FUSE_ITER + samples_per_pixel {
+                    let w = total_weight * rng.f64();
+                    let k = cumulative_weights
+                        .partition_point(|&total| total <= w)
+                        .min(xforms.len() - 1);
+                    let xform = &xforms[k];
+
+                    p = xform.apply(p);
+                    color = 0.5 * (color + xform.color);
+
+                    if !(p.0.is_finite() && p.1.is_finite()) {
+                        p = random_point(&mut rng);
+                        fuse = FUSE_ITER;
+                        continue;
+                    }
+                    if fuse > 0 {
+                        fuse -= 1;
+                        continue;
+                    }
+                    if let Some(index) = pixel_index(p.0, p.1) {
+                        histogram.counts[index] += 1.;
+                        histogram.colors[index] += color;
+                    }
+                }
+
+                if let Some(progress) = &progress {
+                    progress.incr();
+                }
+            }
+
+            histogram
+        })
+        .collect();
+
+    histograms
+        .into_iter()
+        .reduce(|mut total, histogram| {
+            for (a, b) in total.counts.iter_mut().zip(histogram.counts) {
+                *a += b;
+            }
+            for (a, b) in total.colors.iter_mut().zip(histogram.colors) {
+                *a += b;
+            }
+            total
+        })
+        .unwrap_or_else(|| Histogram::new(pixel_count))
+}
+
+/// Draws the histogram: the brightness of a pixel is the logarithm of
+/// its density and its color is the mean of the colors of its points.
+pub fn color_flame(params: &FlameParams, histogram: Histogram) -> RgbImage {
+    let &FlameParams {
+        img_width,
+        img_height,
+        gamma,
+        ref custom_gradient,
+        ..
+    } = params;
+
+    let gamma = gamma.unwrap_or(DEFAULT_GAMMA);
+
+    let max_count = histogram.counts.iter().copied().fold(0., F::max);
+    let max_log = (1. + max_count).ln();
+
+    let mut output_image = RgbImage::new(img_width, img_height);
+    for (index, (&count, &color)) in histogram.counts.iter().zip(&histogram.colors).enumerate() {
+        if count == 0. {
+            continue;
+        }
+
+        let alpha = ((1. + count).ln() / max_log).powf(1. / gamma);
+        let Rgb(base) = color_mapping(color / count, custom_gradient.as_ref());
+        let pixel = Rgb(base.map(|c| (c as F * alpha) as u8));
+
+        let (i, j) = (index % img_width as usize, index / img_width as usize);
+        output_image.put_pixel(i as u32, j as u32, pixel);
+    }
+
+    output_image
+}
@@ -0,0 +1,571 @@
+//! Compiles user-defined formulas (see [`Fractal::Custom`]) into a small
+//! stack program that is evaluated on [`Complexx`].
+//!
+//! A formula is an expression over the history values `z0` (oldest) to
+//! `z{depth-1}` (most recent, also available as `z`), `c`, `i` and the
+//! named parameters of the fractal. Supported operators are `+`, `-`,
+//! `*`, `/` and `^`, and supported functions are `exp`, `ln`, `sin`,
+//! `cos`, `conj`, `abs` (absolute value of both parts), `norm`, `re`,
+//! `im` and `swap` (exchanges the real and imaginary parts).
+//!
+//! [`Fractal::Custom`]: crate::fractal::Fractal::Custom
+
+use std::fmt;
+
+use crate::{complexx::Complexx, simd::SimdFloat, F};
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    ops: Vec<Op>,
+    stack_size: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Const(F, F),
+    Z(usize),
+    C,
+
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+    PowI(u32),
+    PowF(F),
+    Pow,
+
+    Call(Function),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Function {
+    Exp,
+    Ln,
+    Sin,
+    Cos,
+    Conj,
+    Abs,
+    Norm,
+    Re,
+    Im,
+    Swap,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        Some(match name {
+            "exp" => Function::Exp,
+            "ln" => Function::Ln,
+            "sin" => Function::Sin,
+            "cos" => Function::Cos,
+            "conj" => Function::Conj,
+            "abs" => Function::Abs,
+            "norm" => Function::Norm,
+            "re" => Function::Re,
+            "im" => Function::Im,
+            "swap" => Function::Swap,
+            _ => return None,
+        })
+    }
+}
+
+impl Program {
+    /// Compiles `formula` for a recurrence of the given depth. `params`
+    /// holds the named parameters as `(name, re, im)`.
+    pub fn compile(
+        formula: &str,
+        depth: usize,
+        params: &[(String, F, F)],
+    ) -> Result<Program, FormulaError> {
+        if depth == 0 {
+            return Err(FormulaError::new(
+                formula,
+                (0, formula.len()),
+                "depth must be at least 1",
+            ));
+        }
+
+        let tokens = tokenize(formula)?;
+        let mut parser = Parser {
+            formula,
+            tokens: &tokens,
+            pos: 0,
+            depth,
+            params,
+            ops: Vec::new(),
+        };
+
+        parser.expr()?;
+        if let Some(token) = parser.peek() {
+            return Err(parser.error(token.span, "expected an operator"));
+        }
+
+        let ops = parser.ops;
+
+        let mut stack_size = 0;
+        let mut len = 0usize;
+        for op in ops.iter() {
+            match op {
+                Op::Const(..) | Op::Z(_) | Op::C => len += 1,
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow => len -= 1,
+                _ => (),
+            }
+            stack_size = stack_size.max(len);
+        }
+
+        Ok(Program { ops, stack_size })
+    }
+
+    /// Evaluates the program. `z` holds the history values, oldest
+    /// first. `stack` is a scratch buffer that is reused between calls
+    /// to avoid allocations.
+    pub fn eval<X: SimdFloat>(
+        &self,
+        z: &[Complexx<X>],
+        c: Complexx<X>,
+        stack: &mut Vec<Complexx<X>>,
+    ) -> Complexx<X> {
+        stack.clear();
+
+        for &op in self.ops.iter() {
+            match op {
+                Op::Const(re, im) => stack.push(Complexx::splat(re, im)),
+                Op::Z(k) => stack.push(z[k]),
+                Op::C => stack.push(c),
+
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.last_mut().unwrap();
+                    *a = match op {
+                        Op::Add => *a + b,
+                        Op::Sub => *a - b,
+                        Op::Mul => *a * b,
+                        Op::Div => *a / b,
+                        _ => (a.ln() * b).exp(),
+                    };
+                }
+                Op::Neg | Op::PowI(_) | Op::PowF(_) | Op::Call(_) => {
+                    let a = stack.last_mut().unwrap();
+                    *a = match op {
+                        Op::Neg => -*a,
+                        Op::PowI(n) => a.powi(n),
+                        Op::PowF(exp) => a.powf(exp),
+                        Op::Call(f) => match f {
+                            Function::Exp => a.exp(),
+                            Function::Ln => a.ln(),
+                            Function::Sin => a.sin(),
+                            Function::Cos => a.cos(),
+                            Function::Conj => a.conj(),
+                            Function::Abs => Complexx {
+                                re: a.re.abs(),
+                                im: a.im.abs(),
+                            },
+                            Function::Norm => Complexx {
+                                re: a.norm(),
+                                im: X::splat(0.),
+                            },
+                            Function::Re => Complexx {
+                                re: a.re,
+                                im: X::splat(0.),
+                            },
+                            Function::Im => Complexx {
+                                re: a.im,
+                                im: X::splat(0.),
+                            },
+                            Function::Swap => Complexx { re: a.im, im: a.re },
+                        },
+                        _ => unreachable!(),
+                    };
+                }
+            }
+        }
+
+        stack[0]
+    }
+
+    /// Maximum number of values on the stack during evaluation.
+    pub fn stack_size(&self) -> usize {
+        self.stack_size
+    }
+}
+
+/// An error in a formula, `span` is a byte range in `formula`.
+#[derive(Debug, Clone)]
+pub struct FormulaError {
+    pub formula: String,
+    pub span: (usize, usize),
+    pub message: String,
+}
+
+impl FormulaError {
+    fn new<S: ToString>(formula: &str, span: (usize, usize), message: S) -> FormulaError {
+        FormulaError {
+            formula: formula.to_string(),
+            span,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FormulaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (start, end) = self.span;
+        let offset = self.formula[..start].chars().count();
+        let width = self.formula[start..end].chars().count().max(1);
+
+        writeln!(f, "{} (at {}..{})", self.message, start, end)?;
+        writeln!(f, "  {}", self.formula)?;
+        write!(f, "  {}{}", " ".repeat(offset), "^".repeat(width))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(F),
+    Ident(String),
+    Op(char),
+    LParen,
+    RParen,
+}
+
+#[derive(Debug, Clone)]
+struct Spanned {
+    token: Token,
+    span: (usize, usize),
+}
+
+fn tokenize(formula: &str) -> Result<Vec<Spanned>, FormulaError> {
+    let mut tokens = Vec::new();
+    let mut chars = formula.char_indices().peekable();
+
+    while let Some(&(start, ch)) = chars.peek() {
+        if ch.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let token = if ch.is_ascii_digit() || ch == '.' {
+            let mut end = start;
+            let mut prev = ch;
+            while let Some(&(i, ch)) = chars.peek() {
+                // Accept exponents such as `1e-3`.
+                let is_exp_sign = (ch == '-' || ch == '+') && (prev == 'e' || prev == 'E');
+                if ch.is_ascii_digit() || ch == '.' || ch == 'e' || ch == 'E' || is_exp_sign {
+                    end = i + ch.len_utf8();
+                    prev = ch;
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let text = &formula[start..end];
+            Token::Num(text.parse::<F>().map_err(|_| {
+                FormulaError::new(formula, (start, end), format!("invalid number `{}`", text))
+            })?)
+        } else if ch.is_alphabetic() || ch == '_' {
+            let mut end = start;
+            while let Some(&(i, ch)) = chars.peek() {
+                if ch.is_alphanumeric() || ch == '_' {
+                    end = i + ch.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            Token::Ident(formula[start..end].to_string())
+        } else {
+            chars.next();
+            match ch {
+                '+' | '-' | '*' | '/' | '^' => Token::Op(ch),
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                _ => {
+                    return Err(FormulaError::new(
+                        formula,
+                        (start, start + ch.len_utf8()),
+                        format!("unexpected character `{}`", ch),
+                    ))
+                }
+            }
+        };
+
+        let end = chars.peek().map(|&(i, _)| i).unwrap_or(formula.len());
+        tokens.push(Spanned {
+            token,
+            span: (start, end),
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser that directly emits the ops in postfix
+/// order.
+struct Parser<'a> {
+    formula: &'a str,
+    tokens: &'a [Spanned],
+    pos: usize,
+    depth: usize,
+    params: &'a [(String, F, F)],
+    ops: Vec<Op>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Spanned> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Spanned, FormulaError> {
+        let token = self.tokens.get(self.pos).cloned().ok_or_else(|| {
+            let end = self.formula.len();
+            self.error((end, end), "unexpected end of formula")
+        })?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn error<S: ToString>(&self, span: (usize, usize), message: S) -> FormulaError {
+        FormulaError::new(self.formula, span, message)
+    }
+
+    fn peek_op(&self, ops: &[char]) -> Option<char> {
+        match self.peek() {
+            Some(Spanned {
+                token: Token::Op(op),
+                ..
+            }) if ops.contains(op) => Some(*op),
+            _ => None,
+        }
+    }
+
+    // expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<(), FormulaError> {
+        self.term()?;
+        while let Some(op) = self.peek_op(&['+', '-']) {
+            self.pos += 1;
+            self.term()?;
+            self.ops.push(if op == '+' { Op::Add } else { Op::Sub });
+        }
+        Ok(())
+    }
+
+    // term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<(), FormulaError> {
+        self.unary()?;
+        while let Some(op) = self.peek_op(&['*', '/']) {
+            self.pos += 1;
+            self.unary()?;
+            self.ops.push(if op == '*' { Op::Mul } else { Op::Div });
+        }
+        Ok(())
+    }
+
+    // unary := '-' unary | power
+    fn unary(&mut self) -> Result<(), FormulaError> {
+        if self.peek_op(&['-']).is_some() {
+            self.pos += 1;
+            self.unary()?;
+            self.ops.push(Op::Neg);
+            Ok(())
+        } else {
+            self.power()
+        }
+    }
+
+    // power := atom ('^' unary)?
+    fn power(&mut self) -> Result<(), FormulaError> {
+        self.atom()?;
+        if self.peek_op(&['^']).is_some() {
+            self.pos += 1;
+            let start = self.ops.len();
+            self.unary()?;
+
+            // Use cheaper ops when the exponent is a real constant.
+            if self.ops.len() == start + 1 {
+                if let Op::Const(exp, im) = self.ops[start] {
+                    if im == 0. {
+                        self.ops.pop();
+                        self.ops
+                            .push(if exp.fract() == 0. && (0. ..=64.).contains(&exp) {
+                                Op::PowI(exp as u32)
+                            } else {
+                                Op::PowF(exp)
+                            });
+                        return Ok(());
+                    }
+                }
+            }
+            self.ops.push(Op::Pow);
+        }
+        Ok(())
+    }
+
+    // atom := number | variable | function '(' expr ')' | '(' expr ')'
+    fn atom(&mut self) -> Result<(), FormulaError> {
+        let Spanned { token, span } = self.next()?;
+        match token {
+            Token::Num(x) => self.ops.push(Op::Const(x, 0.)),
+            Token::LParen => {
+                self.expr()?;
+                self.expect_rparen(span)?;
+            }
+            Token::Ident(name) => {
+                if let Some(f) = Function::from_name(&name) {
+                    match self.next()? {
+                        Spanned {
+                            token: Token::LParen,
+                            span,
+                        } => {
+                            self.expr()?;
+                            self.expect_rparen(span)?;
+                            self.ops.push(Op::Call(f));
+                        }
+                        Spanned { span, .. } => {
+                            return Err(self.error(span, format!("expected `(` after `{}`", name)))
+                        }
+                    }
+                } else {
+                    let op = self.variable(&name, span)?;
+                    self.ops.push(op);
+                }
+            }
+            Token::Op(_) | Token::RParen => return Err(self.error(span, "expected a value")),
+        }
+        Ok(())
+    }
+
+    fn expect_rparen(&mut self, open_span: (usize, usize)) -> Result<(), FormulaError> {
+        match self.peek() {
+            Some(Spanned {
+                token: Token::RParen,
+                ..
+            }) => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(Spanned { span, .. }) => Err(self.error(*span, "expected `)`")),
+            None => Err(self.error(open_span, "unclosed `(`")),
+        }
+    }
+
+    fn variable(&self, name: &str, span: (usize, usize)) -> Result<Op, FormulaError> {
+        if let Some(&(_, re, im)) = self.params.iter().find(|(n, _, _)| n == name) {
+            return Ok(Op::Const(re, im));
+        }
+
+        match name {
+            "z" => Ok(Op::Z(self.depth - 1)),
+            "c" => Ok(Op::C),
+            "i" => Ok(Op::Const(0., 1.)),
+            _ => {
+                if let Some(k) = name.strip_prefix('z').and_then(|k| k.parse::<usize>().ok()) {
+                    if k < self.depth {
+                        Ok(Op::Z(k))
+                    } else {
+                        Err(self.error(
+                            span,
+                            format!(
+                                "`{}` is out of range, available values are z0 to z{}",
+                                name,
+                                self.depth - 1
+                            ),
+                        ))
+                    }
+                } else {
+                    Err(self.error(span, format!("unknown variable `{}`", name)))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wide::f64x4;
+
+    use super::*;
+
+    /// Evaluates `formula` with the history `z` (oldest first) and returns
+    /// the first lane.
+    fn eval(formula: &str, z: &[(F, F)], c: (F, F), params: &[(String, F, F)]) -> (F, F) {
+        let program = Program::compile(formula, z.len(), params).unwrap();
+        let z: Vec<Complexx<f64x4>> = z.iter().map(|&(re, im)| Complexx::splat(re, im)).collect();
+        let mut stack = Vec::with_capacity(program.stack_size());
+        let result = program.eval(&z, Complexx::splat(c.0, c.1), &mut stack);
+        (result.re.to_array()[0], result.im.to_array()[0])
+    }
+
+    fn assert_close(a: (F, F), b: (F, F)) {
+        assert!(
+            (a.0 - b.0).abs() < 1e-12 && (a.1 - b.1).abs() < 1e-12,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    fn error(formula: &str, depth: usize) -> String {
+        Program::compile(formula, depth, &[]).unwrap_err().message
+    }
+
+    #[test]
+    fn mandelbrot() {
+        // (1 + 2i)^2 + (0.5 - i) = -3 + 4i + 0.5 - i
+        assert_close(eval("z^2 + c", &[(1., 2.)], (0.5, -1.), &[]), (-2.5, 3.));
+        assert_close(eval("z*z + c", &[(1., 2.)], (0.5, -1.), &[]), (-2.5, 3.));
+    }
+
+    #[test]
+    fn precedence() {
+        assert_close(
+            eval("1 + 2 * 3 - 4 / 2", &[(0., 0.)], (0., 0.), &[]),
+            (5., 0.),
+        );
+        assert_close(eval("(1 + 2) * 3", &[(0., 0.)], (0., 0.), &[]), (9., 0.));
+        assert_close(eval("-2^2", &[(0., 0.)], (0., 0.), &[]), (-4., 0.));
+        assert_close(eval("2^-1", &[(0., 0.)], (0., 0.), &[]), (0.5, 0.));
+        assert_close(eval("i*i", &[(0., 0.)], (0., 0.), &[]), (-1., 0.));
+    }
+
+    #[test]
+    fn history_and_params() {
+        let params = [("a".to_string(), 2., 1.)];
+        // z1^2 + a*z0 + c = -1 + (2 + i)*3 + 1
+        let z = [(3., 0.), (0., 1.)];
+        assert_close(eval("z1*z1 + a*z0 + c", &z, (1., 0.), &params), (6., 3.));
+        assert_close(eval("z - z1", &z, (0., 0.), &params), (0., 0.));
+    }
+
+    #[test]
+    fn functions() {
+        let z = [(-3., 4.)];
+        assert_close(eval("conj(z)", &z, (0., 0.), &[]), (-3., -4.));
+        assert_close(eval("abs(z)", &z, (0., 0.), &[]), (3., 4.));
+        assert_close(eval("norm(z)", &z, (0., 0.), &[]), (5., 0.));
+        assert_close(eval("re(z) + im(z)", &z, (0., 0.), &[]), (1., 0.));
+        assert_close(eval("swap(z)", &z, (0., 0.), &[]), (4., -3.));
+        assert_close(
+            eval("exp(i * 3.141592653589793)", &z, (0., 0.), &[]),
+            (-1., 0.),
+        );
+        assert_close(
+            eval("ln(exp(z))", &z, (0., 0.), &[]),
+            (-3., 4. - std::f64::consts::TAU),
+        );
+        assert_close(eval("sin(z)^2 + cos(z)^2", &z, (0., 0.), &[]), (1., 0.));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("z", 0), "depth must be at least 1");
+        assert_eq!(
+            error("z4 + c", 2),
+            "`z4` is out of range, available values are z0 to z1"
+        );
+        assert_eq!(error("foo", 1), "unknown variable `foo`");
+        assert!(error("sin z", 1).contains("expected `(`"));
+        assert_eq!(error("(z + c", 1), "unclosed `(`");
+        assert_eq!(error("z +", 1), "unexpected end of formula");
+    }
+}
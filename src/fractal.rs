@@ -1,453 +1,1763 @@
-use serde::{Deserialize, Serialize};
-use wide::CmpLe;
-
-use crate::{complexx::Complexx, F, FX};
-
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub enum Fractal {
-    Mandelbrot,
-    MandelbrotCustomExp { exp: F },
-    SecondDegreeRecWithGrowingExponent,
-    SecondDegreeRecWithGrowingExponentParam { a_re: F, a_im: F },
-    SecondDegreeRecAlternating1WithGrowingExponent,
-    ThirdDegreeRecWithGrowingExponent,
-    NthDegreeRecWithGrowingExponent(usize),
-    ThirdDegreeRecPairs,
-    SecondDegreeThirtySevenBlend,
-    ComplexLogisticMapLike { a_re: F, a_im: F },
-
-    // This is where I started lacking inspiration for names...
-    Vshqwj,
-    Wmriho { a_re: F, a_im: F },
-    Iigdzh { a_re: F, a_im: F },
-    Fxdicq,
-    Mjygzr,
-    Zqcqvm,
+use std::{fmt, marker::PhantomData};
+
+use serde::{
+    de::{
+        self, value::EnumAccessDeserializer, DeserializeSeed, EnumAccess, IntoDeserializer,
+        MapAccess, SeqAccess, VariantAccess, Visitor,
+    },
+    ser::{SerializeStructVariant, SerializeTupleVariant},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    complexx::Complexx,
+    error::{ErrorKind, Result},
+    formula::Program,
+    lyapunov::{is_valid_sequence, lyapunov_exponent},
+    orbit_average::{AverageSum, OrbitAverage},
+    orbit_trap::OrbitTrap,
+    refill::{iterate_refilling, sample_chunks},
+    registry::{FractalKind, Growth},
+    root_finding::{converge, polynomial_roots, root_index, Polynomial, RootMethod, MAX_DEGREE},
+    simd::{Out, SimdFloat, MAX_LANES},
+    F,
+};
+
+/// Orbits are periodic when they come back to a previous history within
+/// this many times the precision of a lane, relative to the magnitude of
+/// the values (see [`CycleCheck`]).
+const PERIODICITY_ULPS: F = 1024.;
+
+/// A fractal and the values of its parameters. Parameters are `F` to
+/// render a frame and `Vec<RenderStep>` in animations (see
+/// [`crate::params::animation::Fractal`]).
+#[derive(Debug, Clone)]
+pub enum GenericFractal<T> {
+    /// A fractal of the registry, `params` follows the order of
+    /// [`FractalKind::params`].
+    Builtin {
+        kind: FractalKind,
+        params: Vec<T>,
+    },
+
+    /// The recurrence `z_{n+1} = Σ a_k z_{n-k}^{p_k} + c`. `terms` holds
+    /// `(a_k_re, a_k_im, p_k)` and `init` holds the initial values `z_0`,
+    /// `z_{-1}`, ... as `(re, im)`, missing ones are zero.
+    Recurrence {
+        terms: Vec<(T, T, T)>,
+        init: Vec<(T, T)>,
+    },
+
+    /// Switches between `formulas` following `schedule`, they share the
+    /// same history and the first one gives the initial values. Only
+    /// built-in fractals, recurrences and hybrids can be used: the other
+    /// fractals don't iterate a history, and the formula of
+    /// [`Fractal::Custom`] is only compiled for the whole fractal (see
+    /// [`Fractal::compile_formula`]).
+    Hybrid {
+        formulas: Vec<GenericFractal<T>>,
+        schedule: Schedule,
+    },
+
+    /// Finds the roots of the polynomial `Σ a_k z^k` where `coefs` holds
+    /// `(a_k_re, a_k_im)`, lowest degree first, see
+    /// [`crate::root_finding`]. `relaxation` defaults to 1.
+    RootFinding {
+        method: RootMethod,
+        coefs: Vec<(T, T)>,
+        relaxation: Option<T>,
+    },
+
+    /// Lyapunov exponent of the logistic map where `r` follows
+    /// `sequence`, `A` and `B` being the coordinates of the pixel (`x`
+    /// and `y` of the view, i.e. the real part and the opposite of the
+    /// imaginary part), see [`crate::lyapunov`].
+    Lyapunov {
+        sequence: String,
+    },
+
+    /// A user-defined recurrence of the given depth (number of history
+    /// values), see [`crate::formula`] for the syntax. `params` holds
+    /// named parameters as `(name, re, im)`.
+    Custom {
+        formula: String,
+        bailout: F,
+        depth: usize,
+        params: Vec<(String, T, T)>,
+    },
 
     MoireTest,
 }
 
-#[cfg(feature = "force_f32")]
-type Out = [F; 8];
-#[cfg(not(feature = "force_f32"))]
-type Out = [F; 4];
+pub type Fractal = GenericFractal<F>;
+
+/// Picks the formula of a [`Fractal::Hybrid`] used at each iteration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Schedule {
+    /// Letters give the formulas used at each iteration (`A` for the
+    /// first one, `B` for the second one...), the pattern repeats.
+    Pattern(String),
+    /// The first formula every `n` iterations, starting at the first
+    /// one, and the second formula otherwise.
+    Modulus(u32),
+    /// The first formula before the given iteration and the second one
+    /// after.
+    Switch(u32),
+}
 
-impl Fractal {
-    pub fn sample(&self, c: Complexx, max_iter: u32) -> Out {
-        let one = FX::splat(1.0);
-        let zero = FX::splat(0.0);
+impl Schedule {
+    /// Index of the formula used at iteration `i`, out of `count`.
+    #[inline(always)]
+    fn formula_index(&self, i: u32, count: usize) -> usize {
+        let index = match self {
+            Schedule::Pattern(pattern) => {
+                let pattern = pattern.as_bytes();
+                if pattern.is_empty() {
+                    0
+                } else {
+                    let letter = pattern[i as usize % pattern.len()];
+                    letter.to_ascii_uppercase().wrapping_sub(b'A') as usize
+                }
+            }
+            &Schedule::Modulus(n) => !i.is_multiple_of(n) as usize,
+            &Schedule::Switch(n) => (i >= n) as usize,
+        };
+        index.min(count - 1)
+    }
+}
 
-        let (iter, _last_z) = match self {
-            Fractal::Mandelbrot => {
-                const BAILOUT: F = 4.;
-                let bailout_mask = FX::splat(BAILOUT);
+/// Settings shared by every fractal that change the way they are
+/// sampled.
+#[derive(Debug, Clone, Copy)]
+pub struct SampleOptions<'a> {
+    pub max_iter: u32,
+    pub smooth: bool,
+    /// When set, sample the Julia set for this value of `c`.
+    pub julia: Option<(F, F)>,
+    /// Overrides [`Fractal::bailout`].
+    pub bailout: Option<F>,
+    pub escape_metric: EscapeMetric,
+    /// The compiled formula of [`Fractal::Custom`], see
+    /// [`Fractal::compile_formula`].
+    pub formula: Option<&'a Program>,
+    /// See [`Fractal::roots`].
+    pub roots: &'a [(F, F)],
+    /// Also track the derivative of the orbits to estimate the distance
+    /// to the boundary, for fractals that support it (see
+    /// [`Fractal::has_derivative`]).
+    pub distance_estimation: bool,
+    /// When set, the output of [`Fractal::sample`] is the minimum
+    /// distance of the orbits to this trap instead of the iteration
+    /// count, for fractals with orbits (see [`Fractal::has_orbits`]).
+    pub orbit_trap: Option<OrbitTrap>,
+    /// When set, the output of [`Fractal::sample`] is the mean of this
+    /// quantity along the orbits, interpolated using the fractional part
+    /// of the smooth iteration count, for escape-time fractals (see
+    /// [`Fractal::is_escape_time`]). Ignored when `orbit_trap` is set.
+    pub orbit_average: Option<OrbitAverage>,
+    /// Stop iterating orbits that come back to a previous history, they
+    /// are periodic and never escape (see [`CycleCheck`]). Only used for
+    /// fractals whose recurrence doesn't depend on the iteration number
+    /// (see [`Fractal::is_autonomous`]), without `orbit_average` and not
+    /// with perturbation.
+    pub check_periodicity: bool,
+}
 
-                let mut z = Complexx::zeros();
+/// Output of [`Fractal::sample`] for each lane.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub value: Out,
+    /// Index of the root reached by convergent fractals, see
+    /// [`Fractal::roots`].
+    pub root: [Option<u8>; MAX_LANES],
+    /// Estimated distance to the boundary of the set when
+    /// [`SampleOptions::distance_estimation`] is set, zero inside.
+    pub distance: Option<Out>,
+    /// For fractals with orbits (see [`Fractal::has_orbits`]).
+    pub escape: Option<Escape>,
+}
 
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
+impl From<Out> for Sample {
+    fn from(value: Out) -> Sample {
+        Sample {
+            value,
+            root: [None; MAX_LANES],
+            distance: None,
+            escape: None,
+        }
+    }
+}
 
-                    z = z * z + c;
+/// How the orbits of a [`Sample`] ended, for each lane (see
+/// [`crate::rendering::Channel`]).
+#[derive(Debug, Clone, Copy)]
+pub struct Escape {
+    /// Iteration counts, `max_iter` for orbits that didn't escape.
+    pub iter: Out,
+    /// Normalized iteration counts, as when [`SampleOptions::smooth`] is
+    /// set.
+    pub smooth_iter: Out,
+    /// `|z|` for the last value of the orbits.
+    pub modulus: Out,
+    /// `arg(z)` for the last value of the orbits, between -π and π.
+    pub argument: Out,
+    /// Periods of the orbits found periodic, see [`Orbit::period`].
+    pub period: Out,
+}
 
-                    iter += undiverged_mask.blend(one, zero);
-                }
+impl Escape {
+    fn new<X: SimdFloat>(iter: Out, smooth_iter: Out, last_z: Complexx<X>, period: X) -> Escape {
+        Escape {
+            iter,
+            smooth_iter,
+            modulus: last_z.norm().to_array(),
+            argument: last_z.im.atan2(last_z.re).to_array(),
+            period: period.to_array(),
+        }
+    }
+}
+
+/// Result of the iterations of [`iterate`] and its variants for each
+/// lane.
+#[derive(Debug, Clone, Copy)]
+pub struct Orbit<X: SimdFloat> {
+    /// Iteration counts.
+    pub iter: X,
+    /// Values of the orbits when they escaped.
+    pub last_z: Complexx<X>,
+    /// Derivatives of `last_z` with respect to the pixel, zero when they
+    /// are not tracked (see [`iterate_with_derivative`]).
+    pub last_dz: Complexx<X>,
+    /// Minimum distances of the orbits to [`SampleOptions::orbit_trap`],
+    /// infinite without one.
+    pub trap: X,
+    /// Means of the terms of [`SampleOptions::orbit_average`] with and
+    /// without the last one (see [`AverageSum::means`]), zero without
+    /// one.
+    pub averages: (X, X),
+    /// Periods of the orbits found periodic (which count as not
+    /// escaping), zero for the other ones.
+    pub period: X,
+}
+
+impl<X: SimdFloat> Orbit<X> {
+    pub fn new(iter: X, last_z: Complexx<X>) -> Orbit<X> {
+        Orbit {
+            iter,
+            last_z,
+            last_dz: Complexx::zeros(),
+            trap: X::splat(F::INFINITY),
+            averages: (X::splat(0.), X::splat(0.)),
+            period: X::splat(0.),
+        }
+    }
+}
 
-                (iter, z)
+/// The norm used to decide whether an orbit has escaped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EscapeMetric {
+    /// |z|
+    #[default]
+    Euclidean,
+    /// max(|re|, |im|)
+    Max,
+    /// |re| + |im|
+    Manhattan,
+    /// |re|
+    Real,
+}
+
+impl EscapeMetric {
+    /// Squared value of the norm, to be compared with the squared
+    /// escape radius.
+    #[inline(always)]
+    pub fn norm_sqr<X: SimdFloat>(&self, z: Complexx<X>) -> X {
+        match self {
+            EscapeMetric::Euclidean => z.norm_sqr(),
+            EscapeMetric::Max => (z.re * z.re).max(z.im * z.im),
+            EscapeMetric::Manhattan => {
+                let d = z.re.abs() + z.im.abs();
+                d * d
             }
-            &Fractal::MandelbrotCustomExp { exp } => {
-                const BAILOUT: F = 4.;
-                let bailout_mask = FX::splat(BAILOUT);
+            EscapeMetric::Real => z.re * z.re,
+        }
+    }
+}
 
-                let mut z = Complexx::zeros();
+impl Fractal {
+    pub fn sample<X: SimdFloat>(&self, pixel: Complexx<X>, options: SampleOptions) -> Sample {
+        let SampleOptions {
+            max_iter,
+            julia,
+            bailout,
+            formula,
+            roots,
+            orbit_trap,
+            ..
+        } = options;
+
+        let bailout = bailout.unwrap_or(self.bailout());
+
+        // In Julia mode, the pixel is added to the last initial value of
+        // the recurrence and `c` is fixed.
+        let (z_init, c) = match julia {
+            Some((re, im)) => (pixel, Complexx::splat(re, im)),
+            None => (Complexx::zeros(), pixel),
+        };
 
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
+        let options = SampleOptions {
+            check_periodicity: options.check_periodicity
+                && options.orbit_average.is_none()
+                && self.is_autonomous(),
+            ..options
+        };
 
-                    z = z.powf(exp) + c;
+        // Other lanes are stopped by periodicity checking.
+        if self.has_interior_test(options, bailout) {
+            let period = mandelbrot_interior_period(c);
+            if !period.cmp_le(X::splat(0.)).any() {
+                let orbit = Orbit {
+                    period,
+                    ..Orbit::new(X::splat(max_iter as F), Complexx::zeros())
+                };
+                return self.finish_sample(orbit, options);
+            }
+        }
 
-                    iter += undiverged_mask.blend(one, zero);
+        let orbit = if options.distance_estimation && self.has_derivative() {
+            match self {
+                Fractal::Builtin { kind, params } => {
+                    kind.iterate_with_derivative(params, z_init, c, bailout, options)
+                }
+                _ => {
+                    let (dz_init, dc) = pixel_derivatives(options);
+                    let depth = self.depth();
+                    let mut dz = vec![Complexx::zeros(); depth];
+                    dz[depth - 1] = dz_init;
+                    iterate_with_derivative(
+                        self.initial_history(depth, z_init),
+                        dz,
+                        c,
+                        dc,
+                        bailout,
+                        options,
+                        |z, c, i| self.step(z, c, i),
+                        |z, dz, dc, i| self.derivative(z, dz, dc, i),
+                    )
                 }
-
-                (iter, z)
             }
-            Fractal::SecondDegreeRecWithGrowingExponent => {
-                const BAILOUT: F = 4.;
-                let bailout_mask = FX::splat(BAILOUT);
+        } else {
+            match self {
+                Fractal::Builtin { kind, params } => {
+                    kind.iterate(params, z_init, c, bailout, options)
+                }
 
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
+                Fractal::Recurrence { terms, .. } => iterate_from(
+                    self.initial_history(self.depth(), z_init),
+                    c,
+                    bailout,
+                    options,
+                    |z, c, _| recurrence_step(terms, z, c),
+                ),
+
+                Fractal::Hybrid { formulas, schedule } => iterate_from(
+                    self.initial_history(self.depth(), z_init),
+                    c,
+                    bailout,
+                    options,
+                    |z, c, i| formulas[schedule.formula_index(i, formulas.len())].step(z, c, i),
+                ),
+
+                Fractal::RootFinding {
+                    method,
+                    coefs,
+                    relaxation,
+                } => {
+                    let polynomial = Polynomial::new(coefs);
+                    let relaxation = relaxation.unwrap_or(1.);
+                    let z = match (method, julia) {
+                        (RootMethod::Nova, None) => Complexx::splat(1., 0.),
+                        _ => pixel,
+                    };
+                    // Lanes that converged keep stepping from their last
+                    // value, which doesn't move the minimum.
+                    let mut trap = X::splat(F::INFINITY);
+                    let (iter, last_z) = converge(z, max_iter, |z| {
+                        let new_z = polynomial.step(*method, relaxation, z, c);
+                        if let Some(orbit_trap) = &orbit_trap {
+                            trap = trap.min(orbit_trap.distance(new_z));
+                        }
+                        new_z
+                    });
+
+                    let (re, im) = (last_z.re.to_array(), last_z.im.to_array());
+                    let root = std::array::from_fn(|i| root_index((re[i], im[i]), roots));
+                    let iter = iter.to_array();
+                    return Sample {
+                        value: match orbit_trap {
+                            Some(_) => trap.to_array(),
+                            None => iter,
+                        },
+                        root,
+                        distance: None,
+                        escape: Some(Escape::new(iter, iter, last_z, X::splat(0.))),
+                    };
+                }
 
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z1.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
+                Fractal::Lyapunov { sequence } => {
+                    let exponent =
+                        lyapunov_exponent(sequence.as_bytes(), pixel.re, -pixel.im, max_iter);
+                    return exponent.to_array().into();
+                }
 
-                    let new_z1 = z1 * z1 + z0 + c;
-                    z0 = z1;
-                    z1 = new_z1;
+                Fractal::Custom { depth, .. } => {
+                    let program = formula.expect("the formula must be compiled before sampling");
+                    let mut stack = Vec::with_capacity(program.stack_size());
 
-                    iter += undiverged_mask.blend(one, zero);
+                    iterate(*depth, z_init, c, bailout, options, |z, c, _| {
+                        program.eval(z, c, &mut stack)
+                    })
                 }
 
-                (iter, z1)
+                Fractal::MoireTest => {
+                    let Complexx { re: x, im: y } = pixel * 100.;
+                    Orbit::new((x * x + y * y).sin().abs(), Complexx::splat(1., 0.))
+                }
             }
-            &Fractal::SecondDegreeRecWithGrowingExponentParam { a_re, a_im } => {
-                const BAILOUT: F = 4.;
-                let bailout_mask = FX::splat(BAILOUT);
-
-                let a = Complexx::splat(a_re, a_im);
-
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
+        };
 
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z1.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
+        self.finish_sample(orbit, options)
+    }
 
-                    let new_z1 = z1 * z1 + a * z0 + c;
-                    z0 = z1;
-                    z1 = new_z1;
+    /// Samples the points `0..count` like [`Fractal::sample`], refilling
+    /// lanes with the next points as soon as their orbit ends when
+    /// possible (see [`Fractal::can_refill`]). `pixels` gives the pixels
+    /// of the points whose indices are in each lane, `done` gets the index
+    /// of each point, the sample it is part of and its lane.
+    pub fn sample_points<X: SimdFloat>(
+        &self,
+        count: usize,
+        pixels: impl Fn(&[usize; MAX_LANES]) -> Complexx<X>,
+        options: SampleOptions,
+        done: impl FnMut(usize, &Sample, usize),
+    ) {
+        let bailout = options.bailout.unwrap_or(self.bailout());
+
+        match self {
+            _ if !self.can_refill(options) => {
+                sample_chunks(count, pixels, |pixel| self.sample(pixel, options), done)
+            }
 
-                    iter += undiverged_mask.blend(one, zero);
-                }
+            Fractal::Builtin { kind, params } => {
+                kind.iterate_refilling(params, self, count, pixels, bailout, options, done)
+            }
 
-                (iter, z1)
+            Fractal::Recurrence { terms, .. } => iterate_refilling(
+                self,
+                self.depth(),
+                count,
+                pixels,
+                bailout,
+                options,
+                |z, c, _| recurrence_step(terms, z, c),
+                |z, dz, dc, i| self.derivative(z, dz, dc, i),
+                done,
+            ),
+
+            Fractal::Custom { depth, .. } => {
+                let program = options
+                    .formula
+                    .expect("the formula must be compiled before sampling");
+                let mut stack = Vec::with_capacity(program.stack_size());
+
+                iterate_refilling(
+                    self,
+                    *depth,
+                    count,
+                    pixels,
+                    bailout,
+                    options,
+                    |z, c, _| program.eval(z, c, &mut stack),
+                    |_, _, _, _| Complexx::zeros(),
+                    done,
+                )
             }
-            Fractal::SecondDegreeRecAlternating1WithGrowingExponent => {
-                const BAILOUT: F = 4.;
-                let bailout_mask = FX::splat(BAILOUT);
 
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
+            _ => sample_chunks(count, pixels, |pixel| self.sample(pixel, options), done),
+        }
+    }
 
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z1.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
+    /// Whether the derivative of the orbits can be tracked to estimate
+    /// distances (see [`SampleOptions::distance_estimation`]), for
+    /// polynomial recurrences.
+    pub fn has_derivative(&self) -> bool {
+        match self {
+            Fractal::Builtin { kind, .. } => kind.has_derivative(),
+            Fractal::Recurrence { .. } => true,
+            Fractal::Hybrid { formulas, .. } => formulas.iter().all(Fractal::has_derivative),
+            Fractal::RootFinding { .. }
+            | Fractal::Lyapunov { .. }
+            | Fractal::Custom { .. }
+            | Fractal::MoireTest => false,
+        }
+    }
 
-                    let new_z1 = z1 * z1 - z0 + c;
-                    z0 = z1;
-                    z1 = new_z1;
+    /// Whether orbits iterate until they escape past the bailout (see
+    /// [`SampleOptions::orbit_average`]), unlike root finding fractals
+    /// that converge and fractals without orbits.
+    pub fn is_escape_time(&self) -> bool {
+        !matches!(
+            self,
+            Fractal::RootFinding { .. } | Fractal::Lyapunov { .. } | Fractal::MoireTest
+        )
+    }
 
-                    iter += undiverged_mask.blend(one, zero);
+    /// Whether the recurrence doesn't depend on the iteration number, so
+    /// that periodicity can be checked (see
+    /// [`SampleOptions::check_periodicity`]).
+    pub fn is_autonomous(&self) -> bool {
+        match self {
+            Fractal::Builtin { kind, .. } => kind.is_autonomous(),
+            Fractal::Recurrence { .. } | Fractal::Custom { .. } => true,
+            Fractal::Hybrid { .. }
+            | Fractal::RootFinding { .. }
+            | Fractal::Lyapunov { .. }
+            | Fractal::MoireTest => false,
+        }
+    }
+
+    /// Whether points of the main cardioid and of the period-2 bulb of
+    /// the Mandelbrot set can be skipped (see
+    /// [`mandelbrot_interior_period`]): their orbits stay in `|z| <= 2`,
+    /// inside the escape radius for every metric when the squared
+    /// bailout is at least 8.
+    pub fn has_interior_test(&self, options: SampleOptions, bailout: F) -> bool {
+        options.check_periodicity
+            && options.julia.is_none()
+            && options.orbit_trap.is_none()
+            && bailout >= 8.
+            && matches!(
+                self,
+                Fractal::Builtin {
+                    kind: FractalKind::Mandelbrot,
+                    ..
                 }
+            )
+    }
+
+    /// Whether lanes can pick up new points as soon as their orbit ends
+    /// (see [`crate::refill`]): the orbits of autonomous escape-time
+    /// fractals without [`SampleOptions::orbit_average`] only depend on
+    /// their own number of iterations. With several history values,
+    /// orbits may come back below the bailout after escaping and keep
+    /// counting iterations in [`Fractal::sample`], which refilled lanes
+    /// can't do.
+    pub fn can_refill(&self, options: SampleOptions) -> bool {
+        self.is_escape_time()
+            && self.is_autonomous()
+            && self.depth() == 1
+            && options.orbit_average.is_none()
+    }
+
+    /// Whether orbits are sequences of points of the plane that can be
+    /// traced (see [`Fractal::trace`]).
+    pub fn has_orbits(&self) -> bool {
+        !matches!(self, Fractal::Lyapunov { .. } | Fractal::MoireTest)
+    }
 
-                (iter, z1)
+    /// Calls `visit` with the index and the value of the first
+    /// `max_iter` iterations of the orbit of `pixel`, as in
+    /// [`Fractal::sample`]. Iterations don't stop when lanes escape, it
+    /// is up to `visit` to ignore them. Does nothing for fractals
+    /// without orbits (see [`Fractal::has_orbits`]).
+    pub fn trace<X: SimdFloat>(
+        &self,
+        pixel: Complexx<X>,
+        options: SampleOptions,
+        mut visit: impl FnMut(u32, Complexx<X>),
+    ) {
+        let SampleOptions {
+            max_iter,
+            julia,
+            formula,
+            ..
+        } = options;
+
+        let (z_init, c) = match julia {
+            Some((re, im)) => (pixel, Complexx::splat(re, im)),
+            None => (Complexx::zeros(), pixel),
+        };
+
+        match self {
+            Fractal::RootFinding {
+                method,
+                coefs,
+                relaxation,
+            } => {
+                let polynomial = Polynomial::new(coefs);
+                let relaxation = relaxation.unwrap_or(1.);
+                let mut z = match (method, julia) {
+                    (RootMethod::Nova, None) => Complexx::splat(1., 0.),
+                    _ => pixel,
+                };
+                for i in 0..max_iter {
+                    z = polynomial.step(*method, relaxation, z, c);
+                    visit(i, z);
+                }
             }
-            Fractal::ThirdDegreeRecWithGrowingExponent => {
-                const BAILOUT: F = 4.;
-                let bailout_mask = FX::splat(BAILOUT);
-
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
-                let mut z2 = Complexx::zeros();
-
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z2.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
-                    let new_z2 = z2 * z2 * z2 + z1 * z1 + z0 + c;
-                    z0 = z1;
-                    z1 = z2;
-                    z2 = new_z2;
-
-                    iter += undiverged_mask.blend(one, zero);
+
+            Fractal::Lyapunov { .. } | Fractal::MoireTest => {}
+
+            _ => {
+                let mut z = self.initial_history(self.depth(), z_init);
+                let depth = z.len();
+                let mut stack = Vec::new();
+                for i in 0..max_iter {
+                    let new_z = match self {
+                        Fractal::Custom { .. } => formula
+                            .expect("the formula must be compiled before tracing")
+                            .eval(&z, c, &mut stack),
+                        _ => self.step(&z, c, i),
+                    };
+                    z.rotate_left(1);
+                    z[depth - 1] = new_z;
+                    visit(i, new_z);
                 }
+            }
+        }
+    }
+
+    /// Turns the result of the iterations into the output of
+    /// [`Fractal::sample`].
+    pub fn finish_sample<X: SimdFloat>(&self, orbit: Orbit<X>, options: SampleOptions) -> Sample {
+        let iter = orbit.iter.to_array();
+        let smooth_iter = self.smooth_iteration_count(orbit.iter, orbit.last_z, options);
+
+        let value = match (options.orbit_trap, options.orbit_average) {
+            (Some(_), _) => orbit.trap.to_array(),
+            (None, Some(_)) => self.interpolated_average(orbit, options),
+            (None, None) if options.smooth => smooth_iter,
+            (None, None) => iter,
+        };
+        let distance = (options.distance_estimation && self.has_derivative())
+            .then(|| distance_estimate(orbit.iter, orbit.last_z, orbit.last_dz, options.max_iter));
+
+        Sample {
+            value,
+            root: [None; MAX_LANES],
+            distance,
+            escape: Some(Escape::new(iter, smooth_iter, orbit.last_z, orbit.period)),
+        }
+    }
+
+    /// Iteration count normalized using the value of the orbit when it
+    /// escaped (see [`SampleOptions::smooth`]).
+    fn smooth_iteration_count<X: SimdFloat>(
+        &self,
+        iter: X,
+        last_z: Complexx<X>,
+        options: SampleOptions,
+    ) -> Out {
+        let one = X::splat(1.0);
+
+        let Some(frac) = self.escape_fraction(last_z, options) else {
+            return iter.to_array();
+        };
+
+        let escaped = iter.cmp_lt(X::splat(options.max_iter as F));
+        escaped.blend(iter + one - frac, iter).to_array()
+    }
+
+    /// Mean of [`SampleOptions::orbit_average`] along the orbits. For
+    /// escaped orbits, the means with and without the last term are
+    /// interpolated using the fractional part of the smooth iteration
+    /// count so that there are no bands.
+    fn interpolated_average<X: SimdFloat>(&self, orbit: Orbit<X>, options: SampleOptions) -> Out {
+        let one = X::splat(1.0);
+
+        let (mean, previous_mean) = orbit.averages;
+        let Some(frac) = self.escape_fraction(orbit.last_z, options) else {
+            return mean.to_array();
+        };
+
+        let escaped = orbit.iter.cmp_lt(X::splat(options.max_iter as F));
+        escaped
+            .blend(previous_mean + (mean - previous_mean) * (one - frac), mean)
+            .to_array()
+    }
+
+    /// Fractional part of the smooth iteration count, between 0 and 1,
+    /// given by how far past the escape radius the orbit landed relative
+    /// to how fast the recurrence grows once it has escaped. `None` when
+    /// the growth is unknown or the escape radius is not greater than 1.
+    fn escape_fraction<X: SimdFloat>(
+        &self,
+        last_z: Complexx<X>,
+        options: SampleOptions,
+    ) -> Option<X> {
+        let SampleOptions {
+            bailout,
+            escape_metric,
+            ..
+        } = options;
+
+        let one = X::splat(1.0);
+        let zero = X::splat(0.0);
+
+        let bailout = bailout.unwrap_or(self.bailout());
+
+        // The formula below needs an escape radius greater than 1.
+        if bailout <= 1. {
+            return None;
+        }
+
+        let norm_sqr = escape_metric.norm_sqr(last_z);
+        let frac = match self.growth() {
+            Growth::Power(d) => (norm_sqr.ln() / X::splat(bailout.ln())).ln() / X::splat(d.ln()),
+            Growth::Linear(k) => (norm_sqr / X::splat(bailout)).ln() / X::splat(2. * k.ln()),
+            Growth::None => return None,
+        };
+
+        Some(frac.max(zero).min(one))
+    }
+
+    /// Compiles the formula of [`Fractal::Custom`], other fractals don't
+    /// need one.
+    pub fn compile_formula(&self) -> Result<Option<Program>> {
+        if let Fractal::Custom {
+            formula,
+            depth,
+            params,
+            ..
+        } = self
+        {
+            Program::compile(formula, *depth, params)
+                .map(Some)
+                .map_err(ErrorKind::ParseFormula)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Roots that orbits of convergent fractals can reach, for root
+    /// finding fractals that converge to the roots of their polynomial.
+    pub fn roots(&self) -> Vec<(F, F)> {
+        match self {
+            Fractal::RootFinding {
+                method: RootMethod::Newton | RootMethod::Halley,
+                coefs,
+                ..
+            } => polynomial_roots(coefs),
+            _ => Vec::new(),
+        }
+    }
 
-                (iter, z2)
+    /// Number of history values.
+    pub fn depth(&self) -> usize {
+        match self {
+            Fractal::Builtin { kind, params } => kind.depth(params),
+            Fractal::Recurrence { terms, init } => terms.len().max(init.len()).max(1),
+            Fractal::Hybrid { formulas, .. } => {
+                formulas.iter().map(Fractal::depth).max().unwrap_or(1)
             }
-            Fractal::NthDegreeRecWithGrowingExponent(n) => {
-                const BAILOUT: F = 4.;
-                let bailout_mask = FX::splat(BAILOUT);
-
-                let n = *n;
-                let mut z = vec![Complexx::zeros(); n];
-
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z[n - 1].norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
-
-                    let mut new_z = c;
-                    for (k, z_k) in z.iter().enumerate() {
-                        new_z += z_k.powu(k + 1);
-                    }
-                    for k in 0..n - 1 {
-                        z[k] = z[k + 1];
-                    }
-                    z[n - 1] = new_z;
-
-                    iter += undiverged_mask.blend(one, zero);
+            &Fractal::Custom { depth, .. } => depth,
+            Fractal::RootFinding { .. } | Fractal::Lyapunov { .. } | Fractal::MoireTest => 1,
+        }
+    }
+
+    /// Initial history (oldest first) of length `depth`, which must be at
+    /// least [`Fractal::depth`]. `z_init` is the pixel in Julia mode and
+    /// zero otherwise.
+    pub fn initial_history<X: SimdFloat>(
+        &self,
+        depth: usize,
+        z_init: Complexx<X>,
+    ) -> Vec<Complexx<X>> {
+        let mut z = vec![Complexx::zeros(); depth];
+        match self {
+            Fractal::Builtin { kind, params } => z[depth - 1] = kind.init(params, z_init),
+            Fractal::Recurrence { init, .. } => {
+                for (z_k, &(re, im)) in z.iter_mut().rev().zip(init) {
+                    *z_k = Complexx::splat(re, im);
                 }
+                z[depth - 1] += z_init;
+            }
+            Fractal::Hybrid { formulas, .. } => return formulas[0].initial_history(depth, z_init),
+            Fractal::RootFinding { .. }
+            | Fractal::Lyapunov { .. }
+            | Fractal::Custom { .. }
+            | Fractal::MoireTest => z[depth - 1] = z_init,
+        }
+        z
+    }
 
-                (iter, z[n - 1])
+    /// Computes a single step of a formula of a [`Fractal::Hybrid`], `z`
+    /// holds at least [`Fractal::depth`] values.
+    #[inline(always)]
+    fn step<X: SimdFloat>(&self, z: &[Complexx<X>], c: Complexx<X>, i: u32) -> Complexx<X> {
+        match self {
+            Fractal::Builtin { kind, params } => {
+                kind.step(params, &z[z.len() - kind.depth(params)..], c, i)
             }
-            Fractal::ThirdDegreeRecPairs => {
-                const BAILOUT: F = 4.;
-                let bailout_mask = FX::splat(BAILOUT);
-
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
-                let mut z2 = Complexx::zeros();
-
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z2.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
-
-                    let new_z2 = z0 * z1 + z0 * z2 + z1 * z2 + c;
-                    z0 = z1;
-                    z1 = z2;
-                    z2 = new_z2;
-
-                    iter += undiverged_mask.blend(one, zero);
-                }
+            Fractal::Recurrence { terms, .. } => recurrence_step(terms, z, c),
+            Fractal::Hybrid { formulas, schedule } => {
+                formulas[schedule.formula_index(i, formulas.len())].step(z, c, i)
+            }
+            Fractal::RootFinding { .. }
+            | Fractal::Lyapunov { .. }
+            | Fractal::Custom { .. }
+            | Fractal::MoireTest => unreachable!("hybrid fractals can only contain recurrences"),
+        }
+    }
 
-                (iter, z2)
+    /// Derivative of [`Fractal::step`] given the derivatives `dz` of the
+    /// history values and `dc` of `c`, see [`Fractal::has_derivative`].
+    #[inline(always)]
+    fn derivative<X: SimdFloat>(
+        &self,
+        z: &[Complexx<X>],
+        dz: &[Complexx<X>],
+        dc: Complexx<X>,
+        i: u32,
+    ) -> Complexx<X> {
+        match self {
+            Fractal::Builtin { kind, params } => {
+                let depth = kind.depth(params);
+                kind.derivative(
+                    params,
+                    &z[z.len() - depth..],
+                    &dz[dz.len() - depth..],
+                    dc,
+                    i,
+                )
             }
-            Fractal::SecondDegreeThirtySevenBlend => {
-                const BAILOUT: F = 4.;
-                let bailout_mask = FX::splat(BAILOUT);
+            Fractal::Recurrence { terms, .. } => recurrence_derivative(terms, z, dz, dc),
+            Fractal::Hybrid { formulas, schedule } => {
+                formulas[schedule.formula_index(i, formulas.len())].derivative(z, dz, dc, i)
+            }
+            Fractal::RootFinding { .. }
+            | Fractal::Lyapunov { .. }
+            | Fractal::Custom { .. }
+            | Fractal::MoireTest => unreachable!("only recurrences have a derivative"),
+        }
+    }
+
+    /// Exponent of the fractals that can be rendered using perturbation
+    /// (see [`crate::perturbation`]).
+    pub fn perturbation_exponent(&self) -> Option<u32> {
+        match self {
+            Fractal::Builtin { kind, params } => kind.perturbation_exponent(params),
+            _ => None,
+        }
+    }
 
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
+    /// Squared escape radius.
+    pub fn bailout(&self) -> F {
+        match self {
+            Fractal::Builtin { kind, .. } => kind.bailout(),
+            Fractal::Recurrence { .. } => 4.,
+            Fractal::Hybrid { formulas, .. } => formulas[0].bailout(),
+            Fractal::RootFinding { .. } | Fractal::Lyapunov { .. } => 4.,
+            &Fractal::Custom { bailout, .. } => bailout,
+            Fractal::MoireTest => 4.,
+        }
+    }
 
-                let mut iter = FX::splat(0.);
-                for i in 0..max_iter {
-                    let undiverged_mask = z1.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
-
-                    if i % 37 == 0 {
-                        let new_z1 = z1 * z1 - z0 + c;
-                        z0 = z1;
-                        z1 = new_z1;
-                    } else {
-                        let new_z1 = z1 * z1 + z0;
-                        z0 = z1;
-                        z1 = new_z1;
-                    }
-
-                    iter += undiverged_mask.blend(one, zero);
+    fn growth(&self) -> Growth {
+        match self {
+            Fractal::Builtin { kind, params } => kind.growth(params),
+            Fractal::Recurrence { terms, .. } => {
+                // The highest power dominates, this is exact when it is
+                // the one of the most recent value.
+                let power = terms
+                    .iter()
+                    .filter(|&&(re, im, _)| re != 0. || im != 0.)
+                    .map(|&(_, _, p)| p)
+                    .fold(0., F::max);
+                if power > 1. {
+                    Growth::Power(power)
+                } else {
+                    Growth::None
                 }
+            }
+            Fractal::Hybrid { formulas, .. } => formulas
+                .iter()
+                .map(Fractal::growth)
+                .reduce(|a, b| match (a, b) {
+                    // The slowest one gives a lower bound.
+                    (Growth::Power(a), Growth::Power(b)) => Growth::Power(a.min(b)),
+                    _ => Growth::None,
+                })
+                .unwrap_or(Growth::None),
+            Fractal::RootFinding { .. }
+            | Fractal::Lyapunov { .. }
+            | Fractal::Custom { .. }
+            | Fractal::MoireTest => Growth::None,
+        }
+    }
+}
 
-                (iter, z1)
+/// Step of [`Fractal::Recurrence`].
+#[inline(always)]
+fn recurrence_step<X: SimdFloat>(
+    terms: &[(F, F, F)],
+    z: &[Complexx<X>],
+    c: Complexx<X>,
+) -> Complexx<X> {
+    terms
+        .iter()
+        .zip(z.iter().rev())
+        .fold(Complexx::zeros(), |acc, (&(re, im, p), z_k)| {
+            // Integer powers are both faster and exact at 0.
+            let z_k = if p >= 0. && p.fract() == 0. {
+                z_k.powi(p as u32)
+            } else {
+                z_k.powf(p)
+            };
+            if im == 0. {
+                acc + z_k * re
+            } else {
+                acc + Complexx::splat(re, im) * z_k
             }
-            &Fractal::ComplexLogisticMapLike { a_re: re, a_im: im } => {
-                const BAILOUT: F = 50.;
-                let bailout_mask = FX::splat(BAILOUT);
+        })
+        + c
+}
 
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
+/// Derivative of [`recurrence_step`].
+#[inline(always)]
+fn recurrence_derivative<X: SimdFloat>(
+    terms: &[(F, F, F)],
+    z: &[Complexx<X>],
+    dz: &[Complexx<X>],
+    dc: Complexx<X>,
+) -> Complexx<X> {
+    terms.iter().zip(z.iter().rev().zip(dz.iter().rev())).fold(
+        dc,
+        |acc, (&(re, im, p), (z_k, &dz_k))| {
+            if p == 0. {
+                return acc;
+            }
+            // `z_k^(p - 1)`, integer powers are exact at 0.
+            let z_k = if p >= 1. && p.fract() == 0. {
+                z_k.powi(p as u32 - 1)
+            } else {
+                z_k.powf(p - 1.)
+            };
+            acc + Complexx::splat(re, im) * z_k * dz_k * p
+        },
+    )
+}
 
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z1.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
+/// Derivatives of `z_init` and `c` with respect to the pixel: the pixel
+/// is `c`, or `z_init` in Julia mode.
+#[inline(always)]
+pub fn pixel_derivatives<X: SimdFloat>(options: SampleOptions) -> (Complexx<X>, Complexx<X>) {
+    match options.julia {
+        Some(_) => (Complexx::splat(1., 0.), Complexx::zeros()),
+        None => (Complexx::zeros(), Complexx::splat(1., 0.)),
+    }
+}
 
-                    let new_z1 = z1 * (Complexx::splat(re, im) - z0) + c;
-                    z0 = z1;
-                    z1 = new_z1;
+/// Estimated distance from escaped orbits to the boundary of the set,
+/// `|z| ln|z| / |dz|` where `dz` is the derivative of `z` with respect to
+/// the pixel. Orbits that didn't escape are inside (zero).
+#[inline(always)]
+pub fn distance_estimate<X: SimdFloat>(
+    iter: X,
+    last_z: Complexx<X>,
+    last_dz: Complexx<X>,
+    max_iter: u32,
+) -> Out {
+    let norm = last_z.norm();
+    let distance = norm * norm.ln() / last_dz.norm();
+
+    let escaped = iter.cmp_lt(X::splat(max_iter as F));
+    escaped
+        .blend(distance.max(X::splat(0.)), X::splat(0.))
+        .to_array()
+}
 
-                    iter += undiverged_mask.blend(one, zero);
-                }
+/// Period of the attracting cycle of the Mandelbrot set for `c` in the
+/// main cardioid (1) or in the period-2 bulb (2), zero elsewhere.
+#[inline(always)]
+pub fn mandelbrot_interior_period<X: SimdFloat>(c: Complexx<X>) -> X {
+    let zero = X::splat(0.);
+    let y2 = c.im * c.im;
 
-                (iter, z1)
-            }
+    let x = c.re - X::splat(0.25);
+    let q = x * x + y2;
+    let cardioid = (q * (q + x)).cmp_le(X::splat(0.25) * y2);
 
-            Fractal::Vshqwj => {
-                const BAILOUT: F = 4.;
-                let bailout_mask = FX::splat(BAILOUT);
-
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
-                let mut z2 = Complexx::zeros();
-
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z2.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
-                    let new_z2 = (z2 + z1) * (z1 + z0) * (z2 - z0) + c;
-                    z0 = z1;
-                    z1 = z2;
-                    z2 = new_z2;
-
-                    iter += undiverged_mask.blend(one, zero);
-                }
+    let x = c.re + X::splat(1.);
+    let bulb = (x * x + y2).cmp_le(X::splat(1. / 16.));
 
-                (iter, z2)
-            }
-            &Fractal::Wmriho { a_re, a_im } => {
-                const BAILOUT: F = 10.;
-                let bailout_mask = FX::splat(BAILOUT);
-
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
-                let mut z2 = Complexx::splat(a_re, a_im);
-
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z2.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
-                    let new_z2 = z2 * z2
-                        + z1 * z0
-                        + Complexx {
-                            re: z0.im,
-                            im: z0.re,
-                        }
-                        + c;
-                    z0 = z1;
-                    z1 = z2;
-                    z2 = new_z2;
+    cardioid.blend(X::splat(1.), bulb.blend(X::splat(2.), zero))
+}
 
-                    iter += undiverged_mask.blend(one, zero);
-                }
+/// Brent-style cycle detection: the history of the orbits is saved
+/// after every power of two iterations and compared with the following
+/// ones, orbits that come back to the saved history (up to rounding,
+/// relative to the magnitude of the values) are periodic.
+///
+/// Orbits slowly spiraling towards their cycle can match the saved
+/// history after several turns of the cycle, so the period is the number
+/// of iterations of the first return close to it (within the square root
+/// of the tolerance) instead of the one that matched.
+pub struct CycleCheck<X: SimdFloat> {
+    saved: Vec<Complexx<X>>,
+    /// Iterations done by each lane when `saved` was saved, and when it
+    /// will be saved next.
+    saved_at: X,
+    next_save: X,
+    /// Iterations from `saved` to the first close return of each lane,
+    /// zero when there was none.
+    first_return: X,
+    /// All bits set for the lanes found periodic, zero for the other
+    /// ones.
+    pub periodic: X,
+    /// Periods of the lanes found periodic, zero for the other ones.
+    pub period: X,
+}
 
-                (iter, z2)
+impl<X: SimdFloat> CycleCheck<X> {
+    pub fn new(z: &[Complexx<X>]) -> CycleCheck<X> {
+        CycleCheck {
+            saved: z.to_vec(),
+            saved_at: X::splat(0.),
+            next_save: X::splat(1.),
+            first_return: X::splat(0.),
+            periodic: X::splat(0.),
+            period: X::splat(0.),
+        }
+    }
+
+    /// Compares the history `z` of the lanes set in `mask` with the saved
+    /// one, `done` being the number of iterations done by each lane.
+    #[inline(always)]
+    pub fn check(&mut self, mask: X, z: &[Complexx<X>], done: X) {
+        let zero = X::splat(0.);
+        let tolerance = X::splat(PERIODICITY_ULPS * X::EPSILON);
+
+        let (found, close) =
+            z.iter()
+                .zip(&self.saved)
+                .fold((mask, mask), |(found, close), (&z, &saved)| {
+                    // Squared distances are compared with the squared
+                    // magnitude of the values.
+                    let distance = (z - saved).norm_sqr();
+                    let scale = z.norm_sqr().max(saved.norm_sqr());
+                    (
+                        found.blend(distance.cmp_le(tolerance * tolerance * scale), zero),
+                        close.blend(distance.cmp_le(tolerance * scale), zero),
+                    )
+                });
+
+        let first = close.blend(self.first_return.cmp_le(zero), zero);
+        self.first_return = first.blend(done - self.saved_at, self.first_return);
+        self.period = found.blend(self.first_return, self.period);
+        self.periodic = found.blend(found, self.periodic);
+
+        // After powers of two iterations.
+        let save = self.next_save.cmp_le(done);
+        if save.any() {
+            for (saved, &z) in self.saved.iter_mut().zip(z) {
+                *saved = Complexx::select(save, z, *saved);
             }
-            &Fractal::Iigdzh { a_re, a_im } => {
-                const BAILOUT: F = 10.;
-                let bailout_mask = FX::splat(BAILOUT);
-
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
-                let mut z2 = Complexx::splat(a_re, a_im);
-
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z2.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
-                    let new_z2 = z2 * z2
-                        + Complexx {
-                            re: z0.im + z1.re,
-                            im: z2.re,
-                        }
-                        + c;
-                    z0 = z1;
-                    z1 = z2;
-                    z2 = new_z2;
+            self.saved_at = save.blend(done, self.saved_at);
+            self.next_save = save.blend(done + done, self.next_save);
+            self.first_return = save.blend(zero, self.first_return);
+        }
+    }
+
+    /// Starts over from the history `z` for the lanes set in `mask`.
+    #[inline(always)]
+    pub fn reset(&mut self, mask: X, z: &[Complexx<X>]) {
+        let zero = X::splat(0.);
+        for (saved, &z) in self.saved.iter_mut().zip(z) {
+            *saved = Complexx::select(mask, z, *saved);
+        }
+        self.saved_at = mask.blend(zero, self.saved_at);
+        self.next_save = mask.blend(X::splat(1.), self.next_save);
+        self.first_return = mask.blend(zero, self.first_return);
+        self.periodic = mask.blend(zero, self.periodic);
+        self.period = mask.blend(zero, self.period);
+    }
+
+    /// Marks the lanes with a positive `period` as periodic without
+    /// iterating them (see [`mandelbrot_interior_period`]).
+    #[inline(always)]
+    pub fn set_period(&mut self, period: X) {
+        let known = X::splat(0.).cmp_lt(period);
+        self.periodic = known.blend(known, self.periodic);
+        self.period = known.blend(period, self.period);
+    }
+}
+
+/// Starting value of the minimum distance to
+/// [`SampleOptions::orbit_trap`]: orbits that escape before the first
+/// iteration get the distance of their initial value `z`, the other ones
+/// start at infinity.
+#[inline(always)]
+pub fn initial_trap<X: SimdFloat>(options: SampleOptions, z: Complexx<X>, bailout: F) -> X {
+    let infinity = X::splat(F::INFINITY);
+    match options.orbit_trap {
+        Some(orbit_trap) => {
+            let escaped = X::splat(bailout).cmp_lt(options.escape_metric.norm_sqr(z));
+            escaped.blend(orbit_trap.distance(z), infinity)
+        }
+        None => infinity,
+    }
+}
+
+/// Iterates a recurrence of the given depth (number of history values)
+/// until every lane escapes or `max_iter` is reached. The history starts
+/// with zeros and `z_last` as most recent value, `step` gets the history
+/// (oldest first), `c` and the iteration number and returns the next
+/// value.
+///
+/// Also tracks the minimum distance of the orbits to
+/// [`SampleOptions::orbit_trap`].
+#[inline(always)]
+pub fn iterate<X: SimdFloat>(
+    depth: usize,
+    z_last: Complexx<X>,
+    c: Complexx<X>,
+    bailout: F,
+    options: SampleOptions,
+    step: impl FnMut(&[Complexx<X>], Complexx<X>, u32) -> Complexx<X>,
+) -> Orbit<X> {
+    let mut z = vec![Complexx::zeros(); depth];
+    z[depth - 1] = z_last;
+
+    iterate_from(z, c, bailout, options, step)
+}
+
+/// Same as [`iterate`], starting from the given history (oldest first).
+#[inline(always)]
+pub fn iterate_from<X: SimdFloat>(
+    mut z: Vec<Complexx<X>>,
+    c: Complexx<X>,
+    bailout: F,
+    options: SampleOptions,
+    mut step: impl FnMut(&[Complexx<X>], Complexx<X>, u32) -> Complexx<X>,
+) -> Orbit<X> {
+    let SampleOptions {
+        max_iter,
+        escape_metric,
+        orbit_trap,
+        orbit_average,
+        ..
+    } = options;
+
+    let one = X::splat(1.0);
+    let zero = X::splat(0.0);
+
+    let depth = z.len();
+    let bailout_mask = X::splat(bailout);
+
+    let mut last_z = Complexx::zeros();
+    let mut trap = initial_trap(options, z[depth - 1], bailout);
+    let mut average_sum = orbit_average.map(AverageSum::new);
+    let mut cycle_check = options.check_periodicity.then(|| CycleCheck::new(&z));
+    let mut iter = X::splat(0.);
+    for i in 0..max_iter {
+        let mut undiverged_mask = escape_metric.norm_sqr(z[depth - 1]).cmp_le(bailout_mask);
+        if let Some(cycle_check) = &cycle_check {
+            undiverged_mask = cycle_check.periodic.blend(zero, undiverged_mask);
+        }
+        if !undiverged_mask.any() {
+            break;
+        }
+
+        let new_z = step(&z, c, i);
+        z.rotate_left(1);
+        z[depth - 1] = new_z;
+
+        iter += undiverged_mask.blend(one, zero);
+        last_z = Complexx::select(undiverged_mask, new_z, last_z);
+        if let Some(orbit_trap) = &orbit_trap {
+            trap = undiverged_mask.blend(trap.min(orbit_trap.distance(new_z)), trap);
+        }
+        if let Some(average_sum) = &mut average_sum {
+            average_sum.add(undiverged_mask, new_z, c);
+        }
+        if let Some(cycle_check) = &mut cycle_check {
+            cycle_check.check(undiverged_mask, &z, iter);
+        }
+    }
+
+    if let Some(cycle_check) = &cycle_check {
+        iter = cycle_check.periodic.blend(X::splat(max_iter as F), iter);
+    }
+
+    Orbit {
+        iter,
+        last_z,
+        last_dz: Complexx::zeros(),
+        trap,
+        averages: average_sum.map_or((zero, zero), |sum| sum.means()),
+        period: cycle_check.map_or(zero, |cycle_check| cycle_check.period),
+    }
+}
+
+/// Same as [`iterate_from`], also computing the derivatives `dz` of the
+/// history values using `derivative` which gets the history, its
+/// derivatives, `dc` and the iteration number.
+#[allow(clippy::too_many_arguments)]
+#[inline(always)]
+pub fn iterate_with_derivative<X: SimdFloat>(
+    mut z: Vec<Complexx<X>>,
+    mut dz: Vec<Complexx<X>>,
+    c: Complexx<X>,
+    dc: Complexx<X>,
+    bailout: F,
+    options: SampleOptions,
+    mut step: impl FnMut(&[Complexx<X>], Complexx<X>, u32) -> Complexx<X>,
+    mut derivative: impl FnMut(&[Complexx<X>], &[Complexx<X>], Complexx<X>, u32) -> Complexx<X>,
+) -> Orbit<X> {
+    let SampleOptions {
+        max_iter,
+        escape_metric,
+        orbit_trap,
+        orbit_average,
+        ..
+    } = options;
+
+    let one = X::splat(1.0);
+    let zero = X::splat(0.0);
+
+    let depth = z.len();
+    let bailout_mask = X::splat(bailout);
+
+    let mut last_z = Complexx::zeros();
+    let mut last_dz = Complexx::zeros();
+    let mut trap = initial_trap(options, z[depth - 1], bailout);
+    let mut average_sum = orbit_average.map(AverageSum::new);
+    let mut cycle_check = options.check_periodicity.then(|| CycleCheck::new(&z));
+    let mut iter = X::splat(0.);
+    for i in 0..max_iter {
+        let mut undiverged_mask = escape_metric.norm_sqr(z[depth - 1]).cmp_le(bailout_mask);
+        if let Some(cycle_check) = &cycle_check {
+            undiverged_mask = cycle_check.periodic.blend(zero, undiverged_mask);
+        }
+        if !undiverged_mask.any() {
+            break;
+        }
+
+        // The derivative uses the values before the step.
+        let new_dz = derivative(&z, &dz, dc, i);
+        let new_z = step(&z, c, i);
+        z.rotate_left(1);
+        z[depth - 1] = new_z;
+        dz.rotate_left(1);
+        dz[depth - 1] = new_dz;
+
+        iter += undiverged_mask.blend(one, zero);
+        last_z = Complexx::select(undiverged_mask, new_z, last_z);
+        last_dz = Complexx::select(undiverged_mask, new_dz, last_dz);
+        if let Some(orbit_trap) = &orbit_trap {
+            trap = undiverged_mask.blend(trap.min(orbit_trap.distance(new_z)), trap);
+        }
+        if let Some(average_sum) = &mut average_sum {
+            average_sum.add(undiverged_mask, new_z, c);
+        }
+        if let Some(cycle_check) = &mut cycle_check {
+            cycle_check.check(undiverged_mask, &z, iter);
+        }
+    }
+
+    if let Some(cycle_check) = &cycle_check {
+        iter = cycle_check.periodic.blend(X::splat(max_iter as F), iter);
+    }
+
+    Orbit {
+        iter,
+        last_z,
+        last_dz,
+        trap,
+        averages: average_sum.map_or((zero, zero), |sum| sum.means()),
+        period: cycle_check.map_or(zero, |cycle_check| cycle_check.period),
+    }
+}
+
+impl<T> GenericFractal<T> {
+    /// Fractal of the given kind with the default values of its
+    /// parameters.
+    pub fn with_default_params(kind: FractalKind) -> GenericFractal<T>
+    where
+        T: From<F>,
+    {
+        GenericFractal::Builtin {
+            kind,
+            params: kind.params().iter().map(|p| p.default.into()).collect(),
+        }
+    }
+
+    /// Maps the values of every parameter.
+    pub fn map_params<U>(&self, f: &impl Fn(&T) -> U) -> GenericFractal<U> {
+        match self {
+            GenericFractal::Builtin { kind, params } => GenericFractal::Builtin {
+                kind: *kind,
+                params: params.iter().map(f).collect(),
+            },
+            GenericFractal::Recurrence { terms, init } => GenericFractal::Recurrence {
+                terms: terms
+                    .iter()
+                    .map(|(re, im, p)| (f(re), f(im), f(p)))
+                    .collect(),
+                init: init.iter().map(|(re, im)| (f(re), f(im))).collect(),
+            },
+            GenericFractal::Hybrid { formulas, schedule } => GenericFractal::Hybrid {
+                formulas: formulas
+                    .iter()
+                    .map(|formula| formula.map_params(f))
+                    .collect(),
+                schedule: schedule.clone(),
+            },
+            GenericFractal::RootFinding {
+                method,
+                coefs,
+                relaxation,
+            } => GenericFractal::RootFinding {
+                method: *method,
+                coefs: coefs.iter().map(|(re, im)| (f(re), f(im))).collect(),
+                relaxation: relaxation.as_ref().map(f),
+            },
+            GenericFractal::Lyapunov { sequence } => GenericFractal::Lyapunov {
+                sequence: sequence.to_owned(),
+            },
+            GenericFractal::Custom {
+                formula,
+                bailout,
+                depth,
+                params,
+            } => GenericFractal::Custom {
+                formula: formula.to_owned(),
+                bailout: *bailout,
+                depth: *depth,
+                params: params
+                    .iter()
+                    .map(|(name, re, im)| (name.to_owned(), f(re), f(im)))
+                    .collect(),
+            },
+            GenericFractal::MoireTest => GenericFractal::MoireTest,
+        }
+    }
+}
+
+// Fractals are written the same way as if `GenericFractal` was an enum
+// with a variant per fractal of the registry, e.g. `Mandelbrot`,
+// `Wmriho(a_re: 0.1, a_im: 0)` or `NthDegreeRecWithGrowingExponent(4)`,
+// followed by the variants of `FractalRepr`.
+
+/// The fractals that are not built-in, as written in parameter files.
+/// They are checked when turned into a [`GenericFractal`].
+#[derive(Serialize, Deserialize)]
+#[serde(
+    rename = "Fractal",
+    deny_unknown_fields,
+    bound(
+        serialize = "T: Serialize + Clone",
+        deserialize = "T: Deserialize<'de>"
+    )
+)]
+enum FractalRepr<T> {
+    /// Written as a variant of its own, see above.
+    #[serde(skip)]
+    Builtin {
+        kind: FractalKind,
+        params: Vec<T>,
+    },
+    Recurrence {
+        terms: Vec<(T, T, T)>,
+        #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+        init: Vec<(T, T)>,
+    },
+    Hybrid {
+        formulas: Vec<GenericFractal<T>>,
+        schedule: Schedule,
+    },
+    RootFinding {
+        method: RootMethod,
+        coefs: Vec<(T, T)>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        relaxation: Option<T>,
+    },
+    Lyapunov {
+        sequence: String,
+    },
+    Custom {
+        formula: String,
+        bailout: F,
+        depth: usize,
+        #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+        params: Vec<(String, T, T)>,
+    },
+    MoireTest,
+}
 
-                    iter += undiverged_mask.blend(one, zero);
+impl<T> TryFrom<FractalRepr<T>> for GenericFractal<T> {
+    type Error = String;
+
+    fn try_from(repr: FractalRepr<T>) -> std::result::Result<Self, Self::Error> {
+        Ok(match repr {
+            FractalRepr::Builtin { kind, params } => GenericFractal::Builtin { kind, params },
+            FractalRepr::Recurrence { terms, init } => {
+                if terms.is_empty() {
+                    return Err("a recurrence needs at least one term".to_owned());
+                }
+                GenericFractal::Recurrence { terms, init }
+            }
+            FractalRepr::Hybrid { formulas, schedule } => {
+                if formulas.is_empty() {
+                    return Err("a hybrid fractal needs at least one formula".to_owned());
+                }
+                if formulas.iter().any(|formula| {
+                    matches!(
+                        formula,
+                        GenericFractal::RootFinding { .. }
+                            | GenericFractal::Lyapunov { .. }
+                            | GenericFractal::Custom { .. }
+                            | GenericFractal::MoireTest
+                    )
+                }) {
+                    return Err(
+                        "hybrid fractals can only use built-in fractals, recurrences and hybrids, \
+                         not RootFinding, Lyapunov, Custom or MoireTest"
+                            .to_owned(),
+                    );
+                }
+                GenericFractal::Hybrid { formulas, schedule }
+            }
+            FractalRepr::RootFinding {
+                method,
+                coefs,
+                relaxation,
+            } => {
+                // Coefficients may be animated, constant polynomials with
+                // zero leading coefficients are only caught when rendering.
+                if coefs.len() < 2 {
+                    return Err(
+                        "the polynomial of a root finding fractal needs at least two coefficients"
+                            .to_owned(),
+                    );
+                }
+                if coefs.len() > MAX_DEGREE + 1 {
+                    return Err(format!(
+                        "the polynomial of a root finding fractal can have at most {} coefficients",
+                        MAX_DEGREE + 1
+                    ));
+                }
+                GenericFractal::RootFinding {
+                    method,
+                    coefs,
+                    relaxation,
+                }
+            }
+            FractalRepr::Lyapunov { sequence } => {
+                if !is_valid_sequence(&sequence) {
+                    return Err(
+                        "the sequence of a Lyapunov fractal must only contain A and B".to_owned(),
+                    );
+                }
+                GenericFractal::Lyapunov { sequence }
+            }
+            FractalRepr::Custom {
+                formula,
+                bailout,
+                depth,
+                params,
+            } => {
+                if bailout.is_nan() || bailout <= 0. {
+                    return Err("the bailout of a custom formula must be positive".to_owned());
+                }
+                // Only the names of the parameters matter to parse the
+                // formula.
+                let names: Vec<_> = params
+                    .iter()
+                    .map(|(name, _, _)| (name.clone(), 0., 0.))
+                    .collect();
+                Program::compile(&formula, depth, &names).map_err(|e| e.to_string())?;
+
+                GenericFractal::Custom {
+                    formula,
+                    bailout,
+                    depth,
+                    params,
                 }
+            }
+            FractalRepr::MoireTest => GenericFractal::MoireTest,
+        })
+    }
+}
 
-                (iter, z2)
+impl<T> From<GenericFractal<T>> for FractalRepr<T> {
+    fn from(fractal: GenericFractal<T>) -> Self {
+        match fractal {
+            GenericFractal::Builtin { kind, params } => FractalRepr::Builtin { kind, params },
+            GenericFractal::Recurrence { terms, init } => FractalRepr::Recurrence { terms, init },
+            GenericFractal::Hybrid { formulas, schedule } => {
+                FractalRepr::Hybrid { formulas, schedule }
             }
-            Fractal::Fxdicq => {
-                const BAILOUT: F = 10.;
-                let bailout_mask = FX::splat(BAILOUT);
-
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
-                let mut z2 = Complexx::zeros();
-
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z2.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
-                    let new_z2 = z2 * z2
-                        + Complexx {
-                            re: z0.im * z1.re,
-                            im: z2.re,
-                        }
-                        + c;
-                    z0 = z1;
-                    z1 = z2;
-                    z2 = new_z2;
+            GenericFractal::RootFinding {
+                method,
+                coefs,
+                relaxation,
+            } => FractalRepr::RootFinding {
+                method,
+                coefs,
+                relaxation,
+            },
+            GenericFractal::Lyapunov { sequence } => FractalRepr::Lyapunov { sequence },
+            GenericFractal::Custom {
+                formula,
+                bailout,
+                depth,
+                params,
+            } => FractalRepr::Custom {
+                formula,
+                bailout,
+                depth,
+                params,
+            },
+            GenericFractal::MoireTest => FractalRepr::MoireTest,
+        }
+    }
+}
 
-                    iter += undiverged_mask.blend(one, zero);
-                }
+impl<T: Serialize + Clone> Serialize for GenericFractal<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        const NAME: &str = "Fractal";
+
+        let GenericFractal::Builtin { kind, params } = self else {
+            return FractalRepr::from(self.clone()).serialize(serializer);
+        };
 
-                (iter, z2)
+        let index = kind.index() as u32;
+        if params.is_empty() {
+            serializer.serialize_unit_variant(NAME, index, kind.name())
+        } else if kind.unnamed_params() {
+            let mut s =
+                serializer.serialize_tuple_variant(NAME, index, kind.name(), params.len())?;
+            for value in params {
+                s.serialize_field(value)?;
             }
-            Fractal::Mjygzr => {
-                const BAILOUT: F = 5.;
-                let bailout_mask = FX::splat(BAILOUT);
+            s.end()
+        } else {
+            let mut s =
+                serializer.serialize_struct_variant(NAME, index, kind.name(), params.len())?;
+            for (param, value) in kind.params().iter().zip(params) {
+                s.serialize_field(param.name, value)?;
+            }
+            s.end()
+        }
+    }
+}
 
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for GenericFractal<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_enum("Fractal", FractalKind::NAMES, FractalVisitor(PhantomData))
+    }
+}
 
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z1.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
+struct FractalVisitor<T>(PhantomData<T>);
 
-                    let new_z = z1 * z1 * c + z0 + c;
-                    z0 = z1;
-                    z1 = new_z;
+impl<'de, T: Deserialize<'de>> Visitor<'de> for FractalVisitor<T> {
+    type Value = GenericFractal<T>;
 
-                    iter += undiverged_mask.blend(one, zero);
-                }
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a fractal")
+    }
 
-                (iter, z1)
-            }
-            Fractal::Zqcqvm => {
-                const BAILOUT: F = 5.;
-                let bailout_mask = FX::splat(BAILOUT);
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> std::result::Result<Self::Value, A::Error> {
+        let (Identifier(name), variant) = data.variant()?;
 
-                let mut z0 = Complexx::zeros();
-                let mut z1 = Complexx::zeros();
+        let Some(kind) = FractalKind::from_name(&name) else {
+            return FractalRepr::deserialize(EnumAccessDeserializer::new(ReadVariant(
+                name, variant,
+            )))?
+            .try_into()
+            .map_err(de::Error::custom);
+        };
 
-                let mut iter = FX::splat(0.);
-                for _ in 0..max_iter {
-                    let undiverged_mask = z1.norm_sqr().cmp_le(bailout_mask);
-                    if !undiverged_mask.any() {
-                        break;
-                    }
+        let visitor = ParamsVisitor {
+            kind,
+            marker: PhantomData,
+        };
+        let params = if kind.params().is_empty() {
+            variant.unit_variant().map(|_| Vec::new())
+        } else if kind.unnamed_params() {
+            variant.tuple_variant(kind.params().len(), visitor)
+        } else {
+            variant.struct_variant(&[], visitor)
+        }?;
+
+        Ok(GenericFractal::Builtin { kind, params })
+    }
+}
 
-                    let new_z = z1 + z0 + c;
-                    z0 = z1;
-                    z1 = new_z;
+/// Name of a variant or a field.
+struct Identifier(String);
 
-                    iter += undiverged_mask.blend(one, zero);
-                }
+impl<'de> Deserialize<'de> for Identifier {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct IdentifierVisitor;
 
-                (iter, z1)
+        impl Visitor<'_> for IdentifierVisitor {
+            type Value = Identifier;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an identifier")
             }
 
-            Fractal::MoireTest => {
-                let Complexx { re: x, im: y } = c * 100.;
-                ((x * x + y * y).sin().abs(), Complexx::splat(1., 0.))
+            fn visit_str<E: de::Error>(self, name: &str) -> std::result::Result<Identifier, E> {
+                Ok(Identifier(name.to_owned()))
             }
-        };
+        }
+
+        deserializer.deserialize_identifier(IdentifierVisitor)
+    }
+}
+
+/// A variant whose name was already read, handed to [`FractalRepr`].
+struct ReadVariant<A>(String, A);
+
+impl<'de, A: VariantAccess<'de>> EnumAccess<'de> for ReadVariant<A> {
+    type Error = A::Error;
+    type Variant = A;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> std::result::Result<(V::Value, A), A::Error> {
+        let ReadVariant(name, variant) = self;
+        Ok((seed.deserialize(name.into_deserializer())?, variant))
+    }
+}
+
+struct ParamsVisitor<T> {
+    kind: FractalKind,
+    marker: PhantomData<T>,
+}
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for ParamsVisitor<T> {
+    type Value = Vec<T>;
 
-        // let s = _last_z.norm_sqr().ln().ln();
-        // (iter + one - s.min(20. * one)).to_array()
-        // (iter + one - s).to_array()
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the parameters of {}", self.kind.name())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Vec<T>, A::Error> {
+        (0..self.kind.params().len())
+            .map(|i| {
+                seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(i, &self))
+            })
+            .collect()
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Vec<T>, A::Error> {
+        let params = self.kind.params();
+
+        let mut values: Vec<Option<T>> = params.iter().map(|_| None).collect();
+        while let Some(Identifier(key)) = map.next_key()? {
+            let i = params
+                .iter()
+                .position(|param| param.name == key)
+                .ok_or_else(|| de::Error::unknown_field(&key, &[]))?;
+            values[i] = Some(map.next_value()?);
+        }
+
+        values
+            .into_iter()
+            .zip(params)
+            .map(|(value, param)| value.ok_or_else(|| de::Error::missing_field(param.name)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> std::result::Result<Fractal, String> {
+        ron::from_str(s).map_err(|e| e.to_string())
+    }
+
+    fn round_trip(s: &str) {
+        let fractal = parse(s).unwrap();
+        let written = ron::to_string(&fractal).unwrap();
+        let read = parse(&written).unwrap();
+        assert_eq!(ron::to_string(&read).unwrap(), written);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        for &kind in FractalKind::ALL {
+            let fractal = Fractal::with_default_params(kind);
+            round_trip(&ron::to_string(&fractal).unwrap());
+        }
+
+        for s in [
+            "Recurrence(terms: [(1, 0, 2), (0.5, 0, 1)])",
+            "Recurrence(terms: [(1, 0, 2)], init: [(0.5, 0)])",
+            "Hybrid(formulas: [Mandelbrot, BurningShip], schedule: Pattern(\"AAB\"))",
+            "Hybrid(formulas: [Recurrence(terms: [(1, 0, 2)]), Tricorn], schedule: Switch(10))",
+            "RootFinding(method: Newton, coefs: [(-1, 0), (0, 0), (0, 0), (1, 0)])",
+            "RootFinding(method: Halley, coefs: [(-1, 0), (1, 0)], relaxation: Some(1.5))",
+            "Lyapunov(sequence: \"AB\")",
+            "Custom(formula: \"z1*z1 + a*z0 + c\", bailout: 4, depth: 2, params: [(\"a\", 1, 0)])",
+            "Custom(formula: \"z^2 + c\", bailout: 4, depth: 1)",
+            "MoireTest",
+        ] {
+            round_trip(s);
+        }
+    }
 
-        iter.to_array()
+    #[test]
+    fn serde_validation() {
+        for (s, message) in [
+            ("Recurrence(terms: [])", "at least one term"),
+            (
+                "Hybrid(formulas: [], schedule: Modulus(2))",
+                "at least one formula",
+            ),
+            (
+                "Hybrid(formulas: [Mandelbrot, Lyapunov(sequence: \"AB\")], schedule: Modulus(2))",
+                "can only use built-in fractals",
+            ),
+            (
+                "Hybrid(formulas: [Mandelbrot, Custom(formula: \"z^3 + c\", bailout: 4, depth: 1)], \
+                 schedule: Modulus(2))",
+                "not RootFinding, Lyapunov, Custom or MoireTest",
+            ),
+            ("RootFinding(method: Newton, coefs: [(1, 0)])", "at least two coefficients"),
+            ("Lyapunov(sequence: \"ABC\")", "only contain A and B"),
+            (
+                "Custom(formula: \"z + c\", bailout: 0, depth: 1)",
+                "must be positive",
+            ),
+            (
+                "Custom(formula: \"z4 + c\", bailout: 4, depth: 2)",
+                "out of range",
+            ),
+            (
+                "Custom(formula: \"a*z + c\", bailout: 4, depth: 1)",
+                "unknown variable",
+            ),
+            (
+                "Custom(formula: \"z + c\", bailout: 4, depth: 0)",
+                "at least 1",
+            ),
+            ("Lyapunov(sequence: \"AB\", foo: 1)", "foo"),
+            ("NotAFractal", "NotAFractal"),
+        ] {
+            let error = parse(s).unwrap_err();
+            assert!(error.contains(message), "{}: {}", s, error);
+        }
+
+        let coefs = vec!["(1, 0)"; MAX_DEGREE + 2].join(", ");
+        let error = parse(&format!("RootFinding(method: Newton, coefs: [{}])", coefs)).unwrap_err();
+        assert!(error.contains("at most 256 coefficients"), "{}", error);
     }
 }
@@ -5,7 +5,7 @@ use std::{
 };
 
 use eframe::{
-    egui::{self, Color32, ComboBox, DragValue, Image, ProgressBar, ScrollArea, Slider, Vec2},
+    egui::{self, Color32, ComboBox, DragValue, Image, ProgressBar, ScrollArea, Slider, Ui, Vec2},
     App, CreationContext, Frame as EFrame,
 };
 use image::codecs::png::PngEncoder;
@@ -15,12 +15,18 @@ use uni_path::PathBuf;
 use crate::{
     coloring::{color_raw_image, ColoringMode, Extremum, MapValue},
     error::{ErrorKind, Result},
-    fractal::Fractal,
+    fractal::{EscapeMetric, Fractal, Schedule},
+    lyapunov::is_valid_sequence,
+    orbit_average::OrbitAverage,
+    orbit_trap::OrbitTrap,
     params::{FrameParams, ParamsKind},
     presets::PRESETS,
     progress::Progress,
-    rendering::render_raw_image,
+    registry::FractalKind,
+    rendering::{render_raw_image, Channel, RawImage},
+    root_finding::{RootMethod, MAX_DEGREE},
     sampling::{generate_sampling_points, Sampling, SamplingLevel},
+    simd::Precision,
     View, F,
 };
 
@@ -34,6 +40,7 @@ pub struct Gui {
     output_image_path: PathBuf,
     param_file_path: PathBuf,
 
+    preview_raw_image: Option<RawImage>,
     preview_bytes: Option<Vec<u8>>,
     preview_size: Option<Vec2>,
     preview_id: u128,
@@ -62,6 +69,7 @@ impl Gui {
             output_image_path,
             param_file_path,
 
+            preview_raw_image: None,
             preview_bytes: None,
             preview_size: None,
             preview_id: 0,
@@ -79,6 +87,8 @@ impl Gui {
 impl App for Gui {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut EFrame) {
         let mut should_update_preview = false;
+        // Only the coloring changed, the last render can be reused.
+        let mut should_recolor_preview = false;
 
         egui::CentralPanel::default().show(ctx, |ui| {
             const SPACE_SIZE: f32 = 8.;
@@ -94,131 +104,121 @@ impl App for Gui {
                     c1.horizontal(|ui| {
                         ui.label("fractal:");
 
-                        let mut selected_fractal_i = match self.params.fractal {
-                            Fractal::Mandelbrot => 0,
-                            Fractal::MandelbrotCustomExp { .. } => 1,
-                            Fractal::SecondDegreeRecWithGrowingExponent => 2,
-                            Fractal::SecondDegreeRecWithGrowingExponentParam { .. } => 3,
-                            Fractal::SecondDegreeRecAlternating1WithGrowingExponent => 4,
-                            Fractal::ThirdDegreeRecWithGrowingExponent => 5,
-                            Fractal::NthDegreeRecWithGrowingExponent(_) => 6,
-                            Fractal::ThirdDegreeRecPairs => 7,
-                            Fractal::SecondDegreeThirtySevenBlend => 8,
-                            Fractal::ComplexLogisticMapLike { .. } => 9,
-                            Fractal::Vshqwj => 10,
-                            Fractal::Wmriho { .. } => 11,
-                            Fractal::Iigdzh { .. } => 12,
-                            Fractal::Fxdicq => 13,
-                            Fractal::Mjygzr => 14,
-                            Fractal::Zqcqvm => 15,
-                            _ => unimplemented!(),
+                        let kinds = FractalKind::ALL;
+                        let mut selected_fractal_i = match &self.params.fractal {
+                            Fractal::Builtin { kind, .. } => kind.index(),
+                            Fractal::Recurrence { .. } => kinds.len(),
+                            Fractal::Hybrid { .. } => kinds.len() + 1,
+                            Fractal::RootFinding { .. } => kinds.len() + 2,
+                            Fractal::Lyapunov { .. } => kinds.len() + 3,
+                            Fractal::Custom { .. } => kinds.len() + 4,
+                            Fractal::MoireTest => kinds.len() + 5,
                         };
-                        const MODES: &[&str] = &[
-                            "Mandelbrot",
-                            "MandelbrotCustomExp(exp)",
-                            "SecondDegreeRecWithGrowingExponent",
-                            "SecondDegreeRecWithGrowingExponentParam(a_re, a_im)",
-                            "SecondDegreeRecAlternating1WithGrowingExponent",
-                            "ThirdDegreeRecWithGrowingExponent",
-                            "NthDegreeRecWithGrowingExponent(n)",
-                            "ThirdDegreeRecPairs",
-                            "SecondDegreeThirtySevenBlend",
-                            "ComplexLogisticMapLike(a_re, a_im)",
-                            "Vshqwj",
-                            "Wmriho(a_re, a_im)",
-                            "Iigdzh(a_re, a_im)",
-                            "Fxdicq",
-                            "Mjygzr",
-                            "Zqcqvm",
-                        ];
+                        let modes: Vec<String> = kinds
+                            .iter()
+                            .map(|&kind| kind_label(kind))
+                            .chain(
+                                [
+                                    "Recurrence(terms)",
+                                    "Hybrid(formulas, schedule)",
+                                    "RootFinding(method, coefs)",
+                                    "Lyapunov(sequence)",
+                                    "Custom(formula)",
+                                    "MoireTest",
+                                ]
+                                .map(String::from),
+                            )
+                            .collect();
                         let res = ComboBox::from_id_salt("fractal").show_index(
                             ui,
                             &mut selected_fractal_i,
-                            MODES.len(),
-                            |i| MODES[i],
+                            modes.len(),
+                            |i| &modes[i],
                         );
 
                         if res.changed() {
-                            self.params.fractal = match selected_fractal_i {
-                                0 => Fractal::Mandelbrot,
-                                1 => Fractal::MandelbrotCustomExp {
-                                    exp: if let Fractal::MandelbrotCustomExp { exp } =
-                                        self.init_params.fractal
-                                    {
-                                        exp
-                                    } else {
-                                        2.
-                                    },
+                            self.params.fractal = match kinds.get(selected_fractal_i) {
+                                Some(&kind) => match &self.init_params.fractal {
+                                    Fractal::Builtin {
+                                        kind: init_kind, ..
+                                    } if *init_kind == kind => self.init_params.fractal.clone(),
+                                    _ => Fractal::with_default_params(kind),
                                 },
-                                2 => Fractal::SecondDegreeRecWithGrowingExponent,
-                                3 => {
-                                    let (a_re, a_im) =
-                                        if let Fractal::SecondDegreeRecWithGrowingExponentParam {
-                                            a_re,
-                                            a_im,
-                                        } = self.init_params.fractal
-                                        {
-                                            (a_re, a_im)
-                                        } else {
-                                            (1., 0.)
-                                        };
-                                    Fractal::SecondDegreeRecWithGrowingExponentParam { a_re, a_im }
+                                None if selected_fractal_i == kinds.len() => {
+                                    if let Fractal::Recurrence { .. } = self.init_params.fractal {
+                                        self.init_params.fractal.clone()
+                                    } else {
+                                        // Same as SecondDegreeRecWithGrowingExponent
+                                        Fractal::Recurrence {
+                                            terms: vec![(1., 0., 2.), (1., 0., 1.)],
+                                            init: Vec::new(),
+                                        }
+                                    }
                                 }
-                                4 => Fractal::SecondDegreeRecAlternating1WithGrowingExponent,
-                                5 => Fractal::ThirdDegreeRecWithGrowingExponent,
-                                6 => Fractal::NthDegreeRecWithGrowingExponent(
-                                    if let Fractal::NthDegreeRecWithGrowingExponent(n) =
-                                        self.init_params.fractal
-                                    {
-                                        n
+                                None if selected_fractal_i == kinds.len() + 1 => {
+                                    if let Fractal::Hybrid { .. } = self.init_params.fractal {
+                                        self.init_params.fractal.clone()
                                     } else {
-                                        4
-                                    },
-                                ),
-                                7 => Fractal::ThirdDegreeRecPairs,
-                                8 => Fractal::SecondDegreeThirtySevenBlend,
-                                9 => {
-                                    let (a_re, a_im) =
-                                        if let Fractal::ComplexLogisticMapLike { a_re, a_im } =
-                                            self.init_params.fractal
-                                        {
-                                            (a_re, a_im)
-                                        } else {
-                                            (1., 0.)
-                                        };
-                                    Fractal::ComplexLogisticMapLike { a_re, a_im }
+                                        Fractal::Hybrid {
+                                            formulas: vec![
+                                                Fractal::with_default_params(
+                                                    FractalKind::SecondDegreeRecWithGrowingExponent,
+                                                ),
+                                                Fractal::with_default_params(
+                                                    FractalKind::SecondDegreeRecAlternating1WithGrowingExponent,
+                                                ),
+                                            ],
+                                            schedule: Schedule::Pattern("AAB".to_string()),
+                                        }
+                                    }
                                 }
-                                10 => Fractal::Vshqwj,
-                                11 => {
-                                    let (a_re, a_im) = if let Fractal::Wmriho { a_re, a_im } =
-                                        self.init_params.fractal
-                                    {
-                                        (a_re, a_im)
+                                None if selected_fractal_i == kinds.len() + 2 => {
+                                    if let Fractal::RootFinding { .. } = self.init_params.fractal {
+                                        self.init_params.fractal.clone()
                                     } else {
-                                        (0., 0.)
-                                    };
-                                    Fractal::Wmriho { a_re, a_im }
+                                        // z^3 - 1
+                                        Fractal::RootFinding {
+                                            method: RootMethod::Newton,
+                                            coefs: vec![(-1., 0.), (0., 0.), (0., 0.), (1., 0.)],
+                                            relaxation: None,
+                                        }
+                                    }
                                 }
-                                12 => {
-                                    let (a_re, a_im) = if let Fractal::Iigdzh { a_re, a_im } =
-                                        self.init_params.fractal
-                                    {
-                                        (a_re, a_im)
+                                None if selected_fractal_i == kinds.len() + 3 => {
+                                    if let Fractal::Lyapunov { .. } = self.init_params.fractal {
+                                        self.init_params.fractal.clone()
                                     } else {
-                                        (0., 0.)
-                                    };
-                                    Fractal::Iigdzh { a_re, a_im }
+                                        Fractal::Lyapunov {
+                                            sequence: "AB".to_string(),
+                                        }
+                                    }
                                 }
-                                13 => Fractal::Fxdicq,
-                                14 => Fractal::Mjygzr,
-                                15 => Fractal::Zqcqvm,
-                                _ => unreachable!(),
+                                None if selected_fractal_i == kinds.len() + 4 => {
+                                    if let Fractal::Custom { .. } = self.init_params.fractal {
+                                        self.init_params.fractal.clone()
+                                    } else {
+                                        Fractal::Custom {
+                                            formula: "z*z + c".to_string(),
+                                            bailout: 4.,
+                                            depth: 1,
+                                            params: Vec::new(),
+                                        }
+                                    }
+                                }
+                                None => Fractal::MoireTest,
                             };
 
-                            // Reset view
-                            self.params.center_x = 0.;
-                            self.params.center_y = 0.;
-                            self.params.zoom = DEFAULT_ZOOM;
+                            // Reset view, the interesting part of Lyapunov
+                            // fractals is around [2, 4]².
+                            if let Fractal::Lyapunov { .. } = self.params.fractal {
+                                self.params.center_x = 3.;
+                                self.params.center_y = 3.;
+                                self.params.zoom = 4.;
+                            } else {
+                                self.params.center_x = 0.;
+                                self.params.center_y = 0.;
+                                self.params.zoom = DEFAULT_ZOOM;
+                            }
 
                             should_update_preview = true;
                         }
@@ -227,45 +227,284 @@ impl App for Gui {
                     {
                         const SPEED: f64 = 0.0001;
 
-                        if let Fractal::MandelbrotCustomExp { exp } = &mut self.params.fractal {
+                        if let Fractal::Builtin { kind, params } = &mut self.params.fractal {
+                            if edit_builtin_params(c1, *kind, params) {
+                                should_update_preview = true;
+                            }
+                        }
+
+                        if let Fractal::Hybrid { formulas, schedule } = &mut self.params.fractal {
+                            let mut removed = None;
+                            for (k, formula) in formulas.iter_mut().enumerate() {
+                                c1.horizontal(|ui| {
+                                    ui.label(format!("{}:", (b'A' + k as u8) as char));
+                                    if let Fractal::Builtin { kind, .. } = formula {
+                                        let mut selected_kind_i = kind.index();
+                                        let res = ComboBox::from_id_salt(("hybrid formula", k))
+                                            .show_index(ui, &mut selected_kind_i, FractalKind::ALL.len(), |i| {
+                                                FractalKind::ALL[i].name()
+                                            });
+                                        if res.changed() {
+                                            *formula = Fractal::with_default_params(
+                                                FractalKind::ALL[selected_kind_i],
+                                            );
+                                            should_update_preview = true;
+                                        }
+                                    } else {
+                                        // Only editable in the parameter file
+                                        ui.label("(nested formula)");
+                                    }
+                                    if ui.button("-").clicked() {
+                                        removed = Some(k);
+                                    }
+                                });
+                                if let Fractal::Builtin { kind, params } = formula {
+                                    if edit_builtin_params(c1, *kind, params) {
+                                        should_update_preview = true;
+                                    }
+                                }
+                            }
+                            if let Some(k) = removed.filter(|_| formulas.len() > 1) {
+                                formulas.remove(k);
+                                should_update_preview = true;
+                            }
+                            if formulas.len() < 26 && c1.button("add formula").clicked() {
+                                formulas.push(Fractal::with_default_params(FractalKind::Mandelbrot));
+                                should_update_preview = true;
+                            }
+
                             c1.horizontal(|ui| {
-                                ui.label("exp:");
-                                let res =
-                                    ui.add(DragValue::new(exp).speed(SPEED).range(0.001..=20.));
+                                ui.label("schedule:");
+                                let mut selected_schedule_i = match schedule {
+                                    Schedule::Pattern(_) => 0,
+                                    Schedule::Modulus(_) => 1,
+                                    Schedule::Switch(_) => 2,
+                                };
+                                const SCHEDULES: &[&str] = &["Pattern", "Modulus", "Switch"];
+                                let res = ComboBox::from_id_salt("schedule").show_index(
+                                    ui,
+                                    &mut selected_schedule_i,
+                                    SCHEDULES.len(),
+                                    |i| SCHEDULES[i],
+                                );
+                                if res.changed() {
+                                    *schedule = match selected_schedule_i {
+                                        0 => Schedule::Pattern("AB".to_string()),
+                                        1 => Schedule::Modulus(2),
+                                        2 => Schedule::Switch(10),
+                                        _ => unreachable!(),
+                                    };
+                                    should_update_preview = true;
+                                }
+
+                                let res = match schedule {
+                                    Schedule::Pattern(pattern) => ui.text_edit_singleline(pattern),
+                                    Schedule::Modulus(n) => ui.add(DragValue::new(n).range(1..=1000)),
+                                    Schedule::Switch(n) => ui.add(DragValue::new(n)),
+                                };
                                 if res.changed() {
                                     should_update_preview = true;
                                 }
                             });
                         }
 
-                        if let Fractal::SecondDegreeRecWithGrowingExponentParam { a_re, a_im }
-                        | Fractal::ComplexLogisticMapLike { a_re, a_im }
-                        | Fractal::Wmriho { a_re, a_im }
-                        | Fractal::Iigdzh { a_re, a_im } = &mut self.params.fractal
+                        if let Fractal::Recurrence { terms, init } = &mut self.params.fractal {
+                            let mut removed = None;
+                            for (k, (a_re, a_im, p)) in terms.iter_mut().enumerate() {
+                                c1.horizontal(|ui| {
+                                    ui.label(format!("a{k}_re:"));
+                                    let res1 = ui.add(DragValue::new(a_re).speed(SPEED));
+                                    ui.label(format!("a{k}_im:"));
+                                    let res2 = ui.add(DragValue::new(a_im).speed(SPEED));
+                                    ui.label(format!("p{k}:"));
+                                    let res3 =
+                                        ui.add(DragValue::new(p).speed(0.01).range(0.0..=20.));
+                                    if ui.button("-").clicked() {
+                                        removed = Some(k);
+                                    }
+
+                                    if res1.changed() || res2.changed() || res3.changed() {
+                                        should_update_preview = true;
+                                    }
+                                });
+                            }
+                            if let Some(k) = removed.filter(|_| terms.len() > 1) {
+                                terms.remove(k);
+                                should_update_preview = true;
+                            }
+
+                            let mut removed = None;
+                            for (k, (re, im)) in init.iter_mut().enumerate() {
+                                c1.horizontal(|ui| {
+                                    ui.label(format!("z(-{k})_re:"));
+                                    let res1 = ui.add(DragValue::new(re).speed(SPEED));
+                                    ui.label(format!("z(-{k})_im:"));
+                                    let res2 = ui.add(DragValue::new(im).speed(SPEED));
+                                    if ui.button("-").clicked() {
+                                        removed = Some(k);
+                                    }
+
+                                    if res1.changed() || res2.changed() {
+                                        should_update_preview = true;
+                                    }
+                                });
+                            }
+                            if let Some(k) = removed {
+                                init.remove(k);
+                                should_update_preview = true;
+                            }
+
+                            c1.horizontal(|ui| {
+                                if ui.button("add term").clicked() {
+                                    terms.push((1., 0., 1.));
+                                    should_update_preview = true;
+                                }
+                                if ui.button("add initial value").clicked() {
+                                    init.push((0., 0.));
+                                    should_update_preview = true;
+                                }
+                            });
+                        }
+
+                        if let Fractal::RootFinding {
+                            method,
+                            coefs,
+                            relaxation,
+                        } = &mut self.params.fractal
                         {
                             c1.horizontal(|ui| {
-                                ui.label("a_re:");
-                                let res1 = ui.add(DragValue::new(a_re).speed(SPEED));
-                                ui.label("a_im:");
-                                let res2 = ui.add(DragValue::new(a_im).speed(SPEED));
+                                ui.label("method:");
+                                const METHODS: [RootMethod; 3] =
+                                    [RootMethod::Newton, RootMethod::Nova, RootMethod::Halley];
+                                let mut selected_method_i =
+                                    METHODS.iter().position(|m| m == method).unwrap();
+                                let res = ComboBox::from_id_salt("root method").show_index(
+                                    ui,
+                                    &mut selected_method_i,
+                                    METHODS.len(),
+                                    |i| format!("{:?}", METHODS[i]),
+                                );
+                                if res.changed() {
+                                    *method = METHODS[selected_method_i];
+                                    should_update_preview = true;
+                                }
 
-                                if res1.changed() || res2.changed() {
+                                ui.label("relaxation:");
+                                let mut value = relaxation.unwrap_or(1.);
+                                let res = ui.add(DragValue::new(&mut value).speed(SPEED));
+                                if res.changed() {
+                                    *relaxation = Some(value);
                                     should_update_preview = true;
                                 }
                             });
+
+                            let mut removed = None;
+                            for (k, (re, im)) in coefs.iter_mut().enumerate() {
+                                c1.horizontal(|ui| {
+                                    ui.label(format!("a{k}_re:"));
+                                    let res1 = ui.add(DragValue::new(re).speed(SPEED));
+                                    ui.label(format!("a{k}_im:"));
+                                    let res2 = ui.add(DragValue::new(im).speed(SPEED));
+                                    if ui.button("-").clicked() {
+                                        removed = Some(k);
+                                    }
+
+                                    if res1.changed() || res2.changed() {
+                                        should_update_preview = true;
+                                    }
+                                });
+                            }
+                            if let Some(k) = removed.filter(|_| coefs.len() > 2) {
+                                coefs.remove(k);
+                                should_update_preview = true;
+                            }
+                            if coefs.len() <= MAX_DEGREE && c1.button("add coefficient").clicked() {
+                                coefs.push((0., 0.));
+                                should_update_preview = true;
+                            }
                         }
 
-                        if let Fractal::NthDegreeRecWithGrowingExponent(n) =
-                            &mut self.params.fractal
+                        if let Fractal::Lyapunov { sequence } = &mut self.params.fractal {
+                            c1.horizontal(|ui| {
+                                ui.label("sequence:");
+                                let mut edited = sequence.clone();
+                                let res = ui.text_edit_singleline(&mut edited);
+                                if res.changed() && is_valid_sequence(&edited) {
+                                    *sequence = edited;
+                                    should_update_preview = true;
+                                }
+                            });
+                        }
+
+                        if let Fractal::Custom {
+                            formula,
+                            bailout,
+                            depth,
+                            params,
+                        } = &mut self.params.fractal
                         {
                             c1.horizontal(|ui| {
-                                ui.label("n:");
-                                let res = ui.add(Slider::new(n, 2..=20));
+                                ui.label("formula:");
+                                let res = ui.text_edit_singleline(formula);
                                 if res.changed() {
                                     should_update_preview = true;
                                 }
                             });
+                            c1.horizontal(|ui| {
+                                ui.label("depth:");
+                                let res1 = ui.add(Slider::new(depth, 1..=10));
+                                ui.label("bailout:");
+                                let res2 =
+                                    ui.add(DragValue::new(bailout).speed(0.1).range(0.1..=1000.));
+                                if res1.changed() || res2.changed() {
+                                    should_update_preview = true;
+                                }
+                            });
+                            for (name, re, im) in params.iter_mut() {
+                                c1.horizontal(|ui| {
+                                    ui.label(format!("{}_re:", name));
+                                    let res1 = ui.add(DragValue::new(re).speed(SPEED));
+                                    ui.label(format!("{}_im:", name));
+                                    let res2 = ui.add(DragValue::new(im).speed(SPEED));
+
+                                    if res1.changed() || res2.changed() {
+                                        should_update_preview = true;
+                                    }
+                                });
+                            }
                         }
+
+                        c1.horizontal(|ui| {
+                            let mut julia = self.params.julia.is_some();
+                            let res = ui.checkbox(&mut julia, "julia");
+                            if res.changed() {
+                                // The point at the center of the view becomes `c`
+                                // and the other way around.
+                                if let Some((re, im)) = self.params.julia {
+                                    self.params.julia = None;
+                                    self.params.center_x = re;
+                                    self.params.center_y = -im;
+                                } else {
+                                    self.params.julia =
+                                        Some((self.params.center_x, -self.params.center_y));
+                                    self.params.center_x = 0.;
+                                    self.params.center_y = 0.;
+                                }
+                                self.params.zoom = DEFAULT_ZOOM;
+                                should_update_preview = true;
+                            }
+
+                            if let Some((re, im)) = &mut self.params.julia {
+                                ui.label("c_re:");
+                                let res1 = ui.add(DragValue::new(re).speed(SPEED));
+                                ui.label("c_im:");
+                                let res2 = ui.add(DragValue::new(im).speed(SPEED));
+
+                                if res1.changed() || res2.changed() {
+                                    should_update_preview = true;
+                                }
+                            }
+                        });
                     }
 
                     c1.horizontal(|ui| {
@@ -278,6 +517,264 @@ impl App for Gui {
                         }
                     });
 
+                    c1.horizontal(|ui| {
+                        ui.label("bailout:");
+
+                        let mut custom = self.params.bailout.is_some();
+                        let res = ui.checkbox(&mut custom, "custom");
+                        if res.changed() {
+                            self.params.bailout = custom.then_some(self.params.fractal.bailout());
+                            should_update_preview = true;
+                        }
+
+                        if let Some(bailout) = &mut self.params.bailout {
+                            let res =
+                                ui.add(DragValue::new(bailout).speed(0.1).range(0.01..=10000.));
+                            if res.changed() {
+                                should_update_preview = true;
+                            }
+                        }
+                    });
+
+                    c1.horizontal(|ui| {
+                        ui.label("escape metric:");
+
+                        let mut selected_metric_i =
+                            match self.params.escape_metric.unwrap_or_default() {
+                                EscapeMetric::Euclidean => 0,
+                                EscapeMetric::Max => 1,
+                                EscapeMetric::Manhattan => 2,
+                                EscapeMetric::Real => 3,
+                            };
+                        const METRICS: &[&str] = &[
+                            "Euclidean |z|",
+                            "Max max(|re|, |im|)",
+                            "Manhattan |re| + |im|",
+                            "Real |re|",
+                        ];
+                        let res = ComboBox::from_id_salt("escape_metric").show_index(
+                            ui,
+                            &mut selected_metric_i,
+                            METRICS.len(),
+                            |i| METRICS[i],
+                        );
+
+                        if res.changed() {
+                            self.params.escape_metric = Some(match selected_metric_i {
+                                0 => EscapeMetric::Euclidean,
+                                1 => EscapeMetric::Max,
+                                2 => EscapeMetric::Manhattan,
+                                3 => EscapeMetric::Real,
+                                _ => unreachable!(),
+                            });
+                            should_update_preview = true;
+                        }
+                    });
+
+                    c1.horizontal(|ui| {
+                        ui.label("precision:");
+
+                        let mut selected_precision_i =
+                            match self.params.precision.unwrap_or_default() {
+                                Precision::Auto => 0,
+                                Precision::F32 => 1,
+                                Precision::F64 => 2,
+                                Precision::DoubleDouble => 3,
+                            };
+                        const PRECISIONS: &[&str] = &["Auto", "f32", "f64", "double-double"];
+                        let res = ComboBox::from_id_salt("precision").show_index(
+                            ui,
+                            &mut selected_precision_i,
+                            PRECISIONS.len(),
+                            |i| PRECISIONS[i],
+                        );
+
+                        if res.changed() {
+                            self.params.precision = Some(match selected_precision_i {
+                                0 => Precision::Auto,
+                                1 => Precision::F32,
+                                2 => Precision::F64,
+                                3 => Precision::DoubleDouble,
+                                _ => unreachable!(),
+                            });
+                            should_update_preview = true;
+                        }
+                    });
+
+                    c1.horizontal(|ui| {
+                        let mut enabled = self.params.orbit_density.is_some();
+                        let res = ui.checkbox(&mut enabled, "orbit density");
+                        if res.changed() {
+                            self.params.orbit_density = enabled.then(|| {
+                                self.init_params.orbit_density.unwrap_or_default()
+                            });
+                            should_update_preview = true;
+                        }
+
+                        if let Some(density) = &mut self.params.orbit_density {
+                            ui.label("samples per pixel:");
+                            let res1 = ui.add(
+                                Slider::new(&mut density.samples_per_pixel, 1..=10000)
+                                    .logarithmic(true),
+                            );
+                            let mut trapped = density.trapped.unwrap_or(false);
+                            let res2 = ui.checkbox(&mut trapped, "trapped");
+                            if res2.changed() {
+                                density.trapped = Some(trapped);
+                            }
+
+                            if res1.changed() || res2.changed() {
+                                should_update_preview = true;
+                            }
+                        }
+                    });
+
+                    if let Some(density) = &mut self.params.orbit_density {
+                        c1.horizontal(|ui| {
+                            let mut nebulabrot = density.channels.is_some();
+                            let res = ui.checkbox(&mut nebulabrot, "channels (r, g, b):");
+                            if res.changed() {
+                                let max_iter = self.params.max_iter;
+                                density.channels =
+                                    nebulabrot.then_some([max_iter, max_iter / 10, max_iter / 100]);
+                                should_update_preview = true;
+                            }
+
+                            if let Some(channels) = &mut density.channels {
+                                for limit in channels {
+                                    let res = ui.add(DragValue::new(limit).range(1..=200000));
+                                    if res.changed() {
+                                        should_update_preview = true;
+                                    }
+                                }
+                            }
+                        });
+                    }
+
+                    c1.horizontal(|ui| {
+                        let mut enabled = self.params.orbit_trap.is_some();
+                        let res = ui.checkbox(&mut enabled, "orbit trap");
+                        if res.changed() {
+                            self.params.orbit_trap = enabled.then(|| {
+                                self.init_params
+                                    .orbit_trap
+                                    .unwrap_or(OrbitTrap::Point { re: 0., im: 0. })
+                            });
+                            should_update_preview = true;
+                        }
+
+                        if let Some(trap) = &mut self.params.orbit_trap {
+                            let mut selected_trap_i = match trap {
+                                OrbitTrap::Point { .. } => 0,
+                                OrbitTrap::Line { .. } => 1,
+                                OrbitTrap::Cross { .. } => 2,
+                                OrbitTrap::Circle { .. } => 3,
+                                OrbitTrap::Axes => 4,
+                            };
+                            const TRAPS: &[&str] = &["Point", "Line", "Cross", "Circle", "Axes"];
+                            let res = ComboBox::from_id_salt("orbit_trap").show_index(
+                                ui,
+                                &mut selected_trap_i,
+                                TRAPS.len(),
+                                |i| TRAPS[i],
+                            );
+
+                            if res.changed() {
+                                *trap = match selected_trap_i {
+                                    0 => OrbitTrap::Point { re: 0., im: 0. },
+                                    1 => OrbitTrap::Line {
+                                        re: 0.,
+                                        im: 0.,
+                                        angle: 0.,
+                                    },
+                                    2 => OrbitTrap::Cross {
+                                        re: 0.,
+                                        im: 0.,
+                                        angle: 0.,
+                                    },
+                                    3 => OrbitTrap::Circle {
+                                        re: 0.,
+                                        im: 0.,
+                                        radius: 1.,
+                                    },
+                                    4 => OrbitTrap::Axes,
+                                    _ => unreachable!(),
+                                };
+                                should_update_preview = true;
+                            }
+                        }
+                    });
+
+                    if let Some(trap) = &mut self.params.orbit_trap {
+                        c1.horizontal(|ui| {
+                            let mut drag = |ui: &mut Ui, label: &str, value: &mut F| {
+                                ui.label(label);
+                                if ui.add(DragValue::new(value).speed(0.01)).changed() {
+                                    should_update_preview = true;
+                                }
+                            };
+
+                            match trap {
+                                OrbitTrap::Point { re, im } => {
+                                    drag(ui, "re:", re);
+                                    drag(ui, "im:", im);
+                                }
+                                OrbitTrap::Line { re, im, angle }
+                                | OrbitTrap::Cross { re, im, angle } => {
+                                    drag(ui, "re:", re);
+                                    drag(ui, "im:", im);
+                                    drag(ui, "angle:", angle);
+                                }
+                                OrbitTrap::Circle { re, im, radius } => {
+                                    drag(ui, "re:", re);
+                                    drag(ui, "im:", im);
+                                    drag(ui, "radius:", radius);
+                                }
+                                OrbitTrap::Axes => {}
+                            }
+                        });
+                    }
+
+                    c1.horizontal(|ui| {
+                        ui.label("orbit average:");
+
+                        let mut selected_average_i = match self.params.orbit_average {
+                            None => 0,
+                            Some(OrbitAverage::TriangleInequality) => 1,
+                            Some(OrbitAverage::Stripe { .. }) => 2,
+                            Some(OrbitAverage::Curvature) => 3,
+                        };
+                        const AVERAGES: &[&str] =
+                            &["None", "Triangle inequality", "Stripe", "Curvature"];
+                        let res = ComboBox::from_id_salt("orbit_average").show_index(
+                            ui,
+                            &mut selected_average_i,
+                            AVERAGES.len(),
+                            |i| AVERAGES[i],
+                        );
+
+                        if res.changed() {
+                            self.params.orbit_average = match selected_average_i {
+                                0 => None,
+                                1 => Some(OrbitAverage::TriangleInequality),
+                                2 => Some(OrbitAverage::Stripe { density: 5. }),
+                                3 => Some(OrbitAverage::Curvature),
+                                _ => unreachable!(),
+                            };
+                            should_update_preview = true;
+                        }
+
+                        if let Some(OrbitAverage::Stripe { density }) =
+                            &mut self.params.orbit_average
+                        {
+                            ui.label("density:");
+                            let res = ui.add(DragValue::new(density).speed(0.1).range(0.0..=50.));
+                            if res.changed() {
+                                should_update_preview = true;
+                            }
+                        }
+                    });
+
                     c1.add_space(SPACE_SIZE);
                     c1.heading("Controls");
                     c1.separator();
@@ -287,8 +784,7 @@ impl App for Gui {
                         ui.horizontal(|ui| {
                             ui.label("zoom:");
                             let res = ui.add(
-                                Slider::new(&mut self.params.zoom, 0.000000000001..=50.)
-                                    .logarithmic(true),
+                                Slider::new(&mut self.params.zoom, 1e-30..=50.).logarithmic(true),
                             );
                             if res.changed() {
                                 should_update_preview = true;
@@ -319,7 +815,7 @@ impl App for Gui {
                         c1.horizontal(|ui| {
                             ui.label("rotate:");
                             let mut rotate = rotate;
-                            const TAU: F = 6.283185307179586;
+                            const TAU: F = std::f64::consts::TAU as F;
                             let res =
                                 ui.add(DragValue::new(&mut rotate).speed(0.1).range(-TAU..=TAU));
                             if res.changed() {
@@ -339,10 +835,21 @@ impl App for Gui {
                         let mut selected_mode_i = match self.params.coloring_mode {
                             ColoringMode::CumulativeHistogram { .. } => 0,
                             ColoringMode::MinMaxNorm { .. } => 1,
-                            ColoringMode::BlackAndWhite { .. } => 2,
+                            ColoringMode::BlackAndWhite => 2,
+                            ColoringMode::RootBasins { .. } => 3,
+                            ColoringMode::Lyapunov { .. } => 4,
+                            ColoringMode::Nebulabrot { .. } => 5,
+                            ColoringMode::DistanceEstimate { .. } => 6,
                         };
-                        const MODES: &[&str] =
-                            &["CumulativeHistogram", "MinMaxNorm", "BlackAndWhite"];
+                        const MODES: &[&str] = &[
+                            "CumulativeHistogram",
+                            "MinMaxNorm",
+                            "BlackAndWhite",
+                            "RootBasins",
+                            "Lyapunov",
+                            "Nebulabrot",
+                            "DistanceEstimate",
+                        ];
                         let res = ComboBox::from_id_salt("coloring_mode").show_index(
                             ui,
                             &mut selected_mode_i,
@@ -354,6 +861,7 @@ impl App for Gui {
                             self.params.coloring_mode = match selected_mode_i {
                                 0 => ColoringMode::CumulativeHistogram {
                                     map: MapValue::Linear,
+                                    channel: None,
                                 },
                                 1 => {
                                     let (init_min, init_max) =
@@ -368,18 +876,97 @@ impl App for Gui {
                                         min: init_min,
                                         max: init_max,
                                         map: MapValue::Linear,
+                                        channel: None,
                                     }
                                 }
                                 2 => ColoringMode::BlackAndWhite,
+                                3 => ColoringMode::RootBasins {
+                                    map: MapValue::Linear,
+                                },
+                                4 => ColoringMode::Lyapunov {
+                                    map: MapValue::Linear,
+                                },
+                                5 => ColoringMode::Nebulabrot {
+                                    map: MapValue::Linear,
+                                },
+                                6 => ColoringMode::DistanceEstimate { thickness: 1. },
                                 _ => unreachable!(),
                             };
                             should_update_preview = true;
                         }
                     });
 
+                    if let ColoringMode::CumulativeHistogram { channel, .. }
+                    | ColoringMode::MinMaxNorm { channel, .. } = &mut self.params.coloring_mode
+                    {
+                        c1.horizontal(|ui| {
+                            ui.label("channel:");
+
+                            let mut selected_channel_i = match channel {
+                                None => 0,
+                                Some(channel) => *channel as usize + 1,
+                            };
+                            const CHANNELS: &[&str] = &[
+                                "Value",
+                                "Iteration",
+                                "SmoothIteration",
+                                "Modulus",
+                                "Argument",
+                                "Escaped",
+                                "Distance",
+                                "Period",
+                            ];
+                            let res = ComboBox::from_id_salt("channel").show_index(
+                                ui,
+                                &mut selected_channel_i,
+                                CHANNELS.len(),
+                                |i| CHANNELS[i],
+                            );
+
+                            if res.changed() {
+                                *channel = selected_channel_i
+                                    .checked_sub(1)
+                                    .map(|i| Channel::ALL[i]);
+                                // The distance estimate is only rendered when
+                                // it is needed.
+                                if self
+                                    .preview_raw_image
+                                    .as_ref()
+                                    .is_some_and(|raw_image| raw_image.has_channel(*channel))
+                                {
+                                    should_recolor_preview = true;
+                                } else {
+                                    should_update_preview = true;
+                                }
+                            }
+                        });
+                    }
+
+                    c1.horizontal(|ui| {
+                        let mut smooth = self.params.smooth.unwrap_or(false);
+                        let res = ui.checkbox(&mut smooth, "smooth iteration count");
+                        if res.changed() {
+                            self.params.smooth = Some(smooth);
+                            should_update_preview = true;
+                        }
+                    });
+
+                    c1.horizontal(|ui| {
+                        let mut check_periodicity =
+                            self.params.check_periodicity.unwrap_or(false);
+                        let res = ui.checkbox(&mut check_periodicity, "periodicity checking");
+                        if res.changed() {
+                            self.params.check_periodicity = Some(check_periodicity);
+                            should_update_preview = true;
+                        }
+                    });
+
                     match &mut self.params.coloring_mode {
-                        ColoringMode::CumulativeHistogram { map }
-                        | ColoringMode::MinMaxNorm { map, .. } => {
+                        ColoringMode::CumulativeHistogram { map, .. }
+                        | ColoringMode::MinMaxNorm { map, .. }
+                        | ColoringMode::RootBasins { map }
+                        | ColoringMode::Lyapunov { map }
+                        | ColoringMode::Nebulabrot { map } => {
                             c1.horizontal(|ui| {
                                 ui.label("map value:");
 
@@ -403,14 +990,14 @@ impl App for Gui {
                                         2 => MapValue::Powf(1.),
                                         _ => unimplemented!(),
                                     };
-                                    should_update_preview = true;
+                                    should_recolor_preview = true;
                                 }
 
                                 if let MapValue::Powf(exp) = map {
                                     let res =
                                         ui.add(Slider::new(exp, 0.01..=20.).logarithmic(true));
                                     if res.changed() {
-                                        should_update_preview = true;
+                                        should_recolor_preview = true;
                                     }
                                 }
                             });
@@ -418,6 +1005,19 @@ impl App for Gui {
                         _ => (),
                     }
 
+                    if let ColoringMode::DistanceEstimate { thickness } =
+                        &mut self.params.coloring_mode
+                    {
+                        c1.horizontal(|ui| {
+                            ui.label("thickness (pixels):");
+                            let res =
+                                ui.add(Slider::new(thickness, 0.1..=20.).logarithmic(true));
+                            if res.changed() {
+                                should_recolor_preview = true;
+                            }
+                        });
+                    }
+
                     if let ColoringMode::MinMaxNorm { min, max, .. } =
                         &mut self.params.coloring_mode
                     {
@@ -441,13 +1041,13 @@ impl App for Gui {
                                 } else {
                                     Extremum::Custom(init_min.unwrap_custom_or(0.))
                                 };
-                                should_update_preview = true;
+                                should_recolor_preview = true;
                             }
 
                             if let Extremum::Custom(min) = min {
                                 let res = ui.add(Slider::new(min, 0. ..=self.params.max_iter as F));
                                 if res.changed() {
-                                    should_update_preview = true;
+                                    should_recolor_preview = true;
                                 }
                             }
                         });
@@ -464,13 +1064,13 @@ impl App for Gui {
                                         init_max.unwrap_custom_or(self.params.max_iter as F),
                                     )
                                 };
-                                should_update_preview = true;
+                                should_recolor_preview = true;
                             }
 
                             if let Extremum::Custom(max) = max {
                                 let res = ui.add(Slider::new(max, 0. ..=self.params.max_iter as F));
                                 if res.changed() {
-                                    should_update_preview = true;
+                                    should_recolor_preview = true;
                                 }
                             }
                         });
@@ -497,14 +1097,14 @@ impl App for Gui {
                                 .max_width(200.)
                                 .max_height(100.)
                                 .show(ui, |ui| {
-                                    for p in 0..PRESETS.len() {
+                                    for &(name, preset) in PRESETS {
                                         if let ParamsKind::Frame(params) =
-                                            ron::from_str(PRESETS[p].1).unwrap()
+                                            ron::from_str(preset).unwrap()
                                         {
-                                            if ui.button(PRESETS[p].0).clicked() {
+                                            if ui.button(name).clicked() {
                                                 self.params = params;
                                                 should_update_preview = true;
-                                                self.notify(format!("loaded {}", PRESETS[p].0));
+                                                self.notify(format!("loaded {}", name));
                                                 ui.close_menu();
                                             };
                                         }
@@ -550,6 +1150,8 @@ impl App for Gui {
                             SamplingLevel::Extreme1 => 6,
                             SamplingLevel::Extreme2 => 7,
                             SamplingLevel::Extreme3 => 8,
+                            SamplingLevel::Adaptive { .. } => 9,
+                            SamplingLevel::Samples(_) => 10,
                         };
                         const SAMPLING_LEVEL: &[&str] = &[
                             "Exploration",
@@ -561,6 +1163,8 @@ impl App for Gui {
                             "Extreme1",
                             "Extreme2",
                             "Extreme3",
+                            "Adaptive",
+                            "Samples",
                         ];
                         let res = ComboBox::from_id_salt("sampling_level").show_index(
                             ui,
@@ -580,6 +1184,11 @@ impl App for Gui {
                                 6 => SamplingLevel::Extreme1,
                                 7 => SamplingLevel::Extreme2,
                                 8 => SamplingLevel::Extreme3,
+                                9 => SamplingLevel::Adaptive {
+                                    max_samples: 610,
+                                    threshold: 0.003,
+                                },
+                                10 => SamplingLevel::Samples(256),
                                 _ => unimplemented!(),
                             }
                         }
@@ -651,6 +1260,8 @@ impl App for Gui {
         if should_update_preview {
             self.update_view();
             self.update_preview();
+        } else if should_recolor_preview {
+            self.recolor_preview();
         }
     }
 }
@@ -688,13 +1299,13 @@ impl Gui {
             progress.clone(),
             thread::spawn(move || {
                 let raw_image =
-                    render_raw_image(&params_clone, &view, &sampling_points_clone, Some(progress));
+                    render_raw_image(&params_clone, &view, &sampling_points_clone, Some(progress))?;
 
                 let output_image = color_raw_image(
                     &params_clone,
                     params_clone.coloring_mode,
                     params_clone.custom_gradient.as_ref(),
-                    raw_image,
+                    &raw_image,
                 );
 
                 output_image
@@ -739,13 +1350,37 @@ impl Gui {
             sampling: Sampling {
                 level: crate::sampling::SamplingLevel::Exploration,
                 random_offsets: true,
+                ..self.params.sampling
             },
             ..self.params.clone()
         };
 
         let sampling_points = generate_sampling_points(preview_params.sampling.level);
 
-        let raw_image = render_raw_image(&preview_params, &self.view, &sampling_points, None);
+        let raw_image = match render_raw_image(&preview_params, &self.view, &sampling_points, None)
+        {
+            Ok(raw_image) => raw_image,
+            Err(e) => {
+                self.notify(format!("{:?}", e));
+                return;
+            }
+        };
+
+        self.preview_raw_image = Some(raw_image);
+        self.recolor_preview();
+    }
+
+    /// Colors the last rendered preview with the current parameters.
+    fn recolor_preview(&mut self) {
+        let Some(raw_image) = &self.preview_raw_image else {
+            return;
+        };
+
+        let preview_params = FrameParams {
+            img_width: raw_image.values.width as u32,
+            img_height: raw_image.values.height as u32,
+            ..self.params.clone()
+        };
 
         let output_image = color_raw_image(
             &preview_params,
@@ -763,3 +1398,37 @@ impl Gui {
         self.preview_bytes = Some(buf);
     }
 }
+
+/// Name of the fractal followed by the names of its parameters.
+fn kind_label(kind: FractalKind) -> String {
+    let names: Vec<&str> = kind.params().iter().map(|param| param.name).collect();
+    if names.is_empty() {
+        kind.name().to_string()
+    } else {
+        format!("{}({})", kind.name(), names.join(", "))
+    }
+}
+
+/// Adds a row per parameter of a built-in fractal, returns whether one
+/// of them changed.
+fn edit_builtin_params(ui: &mut Ui, kind: FractalKind, params: &mut [F]) -> bool {
+    const SPEED: f64 = 0.0001;
+
+    let mut changed = false;
+    for (param, value) in kind.params().iter().zip(params) {
+        ui.horizontal(|ui| {
+            ui.label(format!("{}:", param.name));
+            let res = if param.integer {
+                ui.add(Slider::new(value, param.range.clone()).step_by(1.))
+            } else {
+                ui.add(
+                    DragValue::new(value)
+                        .speed(SPEED)
+                        .range(param.range.clone()),
+                )
+            };
+            changed |= res.changed();
+        });
+    }
+    changed
+}
@@ -0,0 +1,60 @@
+//! Lyapunov fractals (see [`Fractal::Lyapunov`]): the exponent of the
+//! logistic map `x ← r x (1 - x)` where `r` alternates between the two
+//! coordinates of the pixel following a sequence of `A` and `B`.
+//!
+//! Negative exponents are stable regions and positive ones are chaotic.
+//!
+//! [`Fractal::Lyapunov`]: crate::fractal::Fractal::Lyapunov
+
+use crate::{simd::SimdFloat, F};
+
+/// Starting value of the orbits, the critical point of the logistic
+/// map.
+const X_0: F = 0.5;
+
+/// One in this many iterations lets orbits settle before the exponent is
+/// computed (at least the first one, whose derivative is zero at the
+/// critical point).
+const TRANSIENT_FRACTION: u32 = 4;
+
+/// Lower bound of `|r (1 - 2 x)|` so that superstable orbits don't give
+/// an infinite exponent (still representable in `f32`).
+const MIN_DERIVATIVE: F = 1e-30;
+
+/// Whether `sequence` is a valid sequence: non-empty and only made of
+/// `A` and `B` (in any case).
+pub fn is_valid_sequence(sequence: &str) -> bool {
+    !sequence.is_empty()
+        && sequence
+            .bytes()
+            .all(|letter| matches!(letter.to_ascii_uppercase(), b'A' | b'B'))
+}
+
+/// Lyapunov exponent after `max_iter` iterations where `r` is `a` or `b`
+/// depending on the letters of `sequence` (which repeats). The first
+/// `max_iter / TRANSIENT_FRACTION` iterations (and at least one) are a
+/// transient that isn't counted.
+#[inline(always)]
+pub fn lyapunov_exponent<X: SimdFloat>(sequence: &[u8], a: X, b: X, max_iter: u32) -> X {
+    let one = X::splat(1.0);
+    let two = X::splat(2.0);
+    let min_derivative = X::splat(MIN_DERIVATIVE);
+
+    let transient = (max_iter / TRANSIENT_FRACTION).max(1);
+
+    let mut x = X::splat(X_0);
+    let mut sum = X::splat(0.0);
+    for i in 0..max_iter {
+        let r = if sequence[i as usize % sequence.len()].eq_ignore_ascii_case(&b'B') {
+            b
+        } else {
+            a
+        };
+        if i >= transient {
+            sum += (r * (one - two * x)).abs().max(min_derivative).ln();
+        }
+        x = r * x * (one - x);
+    }
+
+    sum * X::splat(1. / max_iter.saturating_sub(transient).max(1) as F)
+}
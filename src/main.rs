@@ -1,16 +1,30 @@
+mod bignum;
 mod cli;
 mod coloring;
 mod complexx;
+mod density;
+mod double_double;
 mod error;
+mod flame;
+mod formula;
 mod fractal;
 mod gui;
+mod lyapunov;
 mod mat;
+mod orbit_average;
+mod orbit_trap;
 mod params;
+mod perturbation;
 #[allow(dead_code)]
 mod presets;
 mod progress;
+mod refill;
+mod registry;
 mod rendering;
+mod root_finding;
 mod sampling;
+mod scene;
+mod simd;
 
 use std::{
     fs,
@@ -21,7 +35,11 @@ use std::{
 
 use eframe::egui::vec2;
 use gui::Gui;
-use params::{AnimationParams, FrameParams};
+use image::RgbImage;
+use params::{
+    AnimationParams, FlameAnimationParams, FlameParams, FrameParams, SceneAnimationParams,
+    SceneParams,
+};
 use ron::ser::PrettyConfig;
 use uni_path::PathBuf;
 
@@ -29,26 +47,16 @@ use crate::{
     cli::get_args_and_options,
     coloring::{color_mapping, color_raw_image},
     error::{ErrorKind, Result},
+    flame::{color_flame, render_flame},
     params::{DevOptions, ParamsKind},
     progress::Progress,
     rendering::render_raw_image,
     sampling::preview_sampling_points,
     sampling::{generate_sampling_points, Sampling},
+    scene::render_scene,
 };
 
-#[cfg(feature = "force_f32")]
-type F = f32;
-#[cfg(feature = "force_f32")]
-use wide::f32x8;
-#[cfg(feature = "force_f32")]
-type FX = f32x8;
-
-#[cfg(not(feature = "force_f32"))]
 type F = f64;
-#[cfg(not(feature = "force_f32"))]
-use wide::f64x4;
-#[cfg(not(feature = "force_f32"))]
-type FX = f64x4;
 
 #[derive(Debug, Clone)]
 pub struct RenderCtx {
@@ -107,6 +115,38 @@ fn main() -> Result<()> {
 
                     render_animation(animation_params, output_image_path)?;
                 }
+                ParamsKind::Flame(flame_params) => {
+                    if options.contains_key("gui") {
+                        println!("gui is not supported for flames. exiting...");
+                        return Ok(());
+                    }
+
+                    render_flame_frame(flame_params, output_image_path)?;
+                }
+                ParamsKind::FlameAnimation(flame_animation_params) => {
+                    if options.contains_key("gui") {
+                        println!("gui is not supported for animations. exiting...");
+                        return Ok(());
+                    }
+
+                    render_flame_animation(flame_animation_params, output_image_path)?;
+                }
+                ParamsKind::Scene(scene_params) => {
+                    if options.contains_key("gui") {
+                        println!("gui is not supported for 3d scenes. exiting...");
+                        return Ok(());
+                    }
+
+                    render_scene_frame(scene_params, output_image_path)?;
+                }
+                ParamsKind::SceneAnimation(scene_animation_params) => {
+                    if options.contains_key("gui") {
+                        println!("gui is not supported for animations. exiting...");
+                        return Ok(());
+                    }
+
+                    render_scene_animation(scene_animation_params, output_image_path)?;
+                }
             }
         }
         _ => {
@@ -142,7 +182,7 @@ fn render_frame(params: FrameParams, output_image_path: PathBuf) -> Result<()> {
         ..
     }) = params.dev_options
     {
-        preview_sampling_points(&sampling_points)?;
+        preview_sampling_points(&sampling, &sampling_points)?;
     }
 
     let progress = Progress::new((img_width * img_height) as usize);
@@ -172,38 +212,31 @@ fn render_frame(params: FrameParams, output_image_path: PathBuf) -> Result<()> {
         thread::sleep(Duration::from_millis(50));
     }
 
-    let raw_image = handle.join().unwrap(); // TODO replace unwrap
+    let raw_image = handle.join().unwrap()?; // TODO replace unwrap
 
     println!();
+    println!(
+        " samples: {} ({:.1} per pixel)",
+        raw_image.samples,
+        raw_image.samples as F / (img_width * img_height) as F,
+    );
 
     let output_image = color_raw_image(
         &params,
         params.coloring_mode,
         params.custom_gradient.as_ref(),
-        raw_image,
+        &raw_image,
     );
 
     output_image
         .save(output_image_path.as_str())
         .map_err(ErrorKind::SaveImage)?;
 
-    let image_size = fs::metadata(output_image_path.as_str()).unwrap().len();
     println!(
-        " output image: {}x{} - {} {}",
+        " output image: {}x{} - {}",
         img_width,
         img_height,
-        if image_size / 1_000_000 != 0 {
-            format!("{:.1}mb", image_size as f32 / 1_000_000.)
-        } else if image_size / 1_000 != 0 {
-            format!("{:.1}kb", image_size as f32 / 1_000.)
-        } else {
-            format!("{}b", image_size)
-        },
-        if let Some(ext) = output_image_path.extension() {
-            format!("- {} ", ext)
-        } else {
-            "".to_string()
-        }
+        describe_image_file(&output_image_path),
     );
 
     Ok(())
@@ -271,7 +304,7 @@ fn render_animation(params: AnimationParams, output_image_path: PathBuf) -> Resu
             thread::sleep(Duration::from_millis(50));
         }
 
-        let raw_image = handle.join().unwrap(); // TODO replace unwrap
+        let raw_image = handle.join().unwrap()?; // TODO replace unwrap
 
         println!();
 
@@ -279,7 +312,7 @@ fn render_animation(params: AnimationParams, output_image_path: PathBuf) -> Resu
             &params,
             params.coloring_mode,
             params.custom_gradient.as_ref(),
-            raw_image,
+            &raw_image,
         );
 
         if let Some(DevOptions {
@@ -305,38 +338,172 @@ fn render_animation(params: AnimationParams, output_image_path: PathBuf) -> Resu
             }
         }
 
-        let output_image_path = PathBuf::from(
-            output_image_path.parent().unwrap().to_string()
-                + "/"
-                + output_image_path.file_stem().unwrap()
-                + "_"
-                + &format!("{:06}", frame_i)
-                + "."
-                + output_image_path.extension().unwrap(),
-        );
+        let output_image_path = frame_image_path(&output_image_path, frame_i);
 
         output_image
             .save(output_image_path.as_str())
             .map_err(ErrorKind::SaveImage)?;
 
-        let image_size = fs::metadata(output_image_path.as_str()).unwrap().len();
         println!(
-            " frame {}: {}x{} - {} {}",
+            " frame {}: {}x{} - {}",
             frame_i + 1,
             img_width,
             img_height,
-            if image_size / 1_000_000 != 0 {
-                format!("{:.1}mb", image_size as f32 / 1_000_000.)
-            } else if image_size / 1_000 != 0 {
-                format!("{:.1}kb", image_size as f32 / 1_000.)
-            } else {
-                format!("{}b", image_size)
-            },
-            if let Some(ext) = output_image_path.extension() {
-                format!("- {} ", ext)
-            } else {
-                "".to_string()
-            }
+            describe_image_file(&output_image_path),
+        );
+        println!();
+    }
+
+    println!(
+        "{} frames - {:.1}s elapsed",
+        frame_count,
+        global_start.elapsed().as_secs_f32()
+    );
+
+    Ok(())
+}
+
+fn render_flame_frame(params: FlameParams, output_image_path: PathBuf) -> Result<()> {
+    let output_image = render_flame_image(&params);
+
+    output_image
+        .save(output_image_path.as_str())
+        .map_err(ErrorKind::SaveImage)?;
+
+    println!(
+        " output image: {}x{} - {}",
+        params.img_width,
+        params.img_height,
+        describe_image_file(&output_image_path),
+    );
+
+    Ok(())
+}
+
+fn render_flame_animation(params: FlameAnimationParams, output_image_path: PathBuf) -> Result<()> {
+    let frame_count = (params.duration * params.fps) as usize;
+
+    println!("frame count: {}", frame_count);
+    println!();
+
+    let global_start = Instant::now();
+
+    for frame_i in 0..frame_count {
+        let t = frame_i as f32 / params.fps;
+
+        let params = params.get_flame_params(t);
+        let output_image = render_flame_image(&params);
+
+        let output_image_path = frame_image_path(&output_image_path, frame_i);
+
+        output_image
+            .save(output_image_path.as_str())
+            .map_err(ErrorKind::SaveImage)?;
+
+        println!(
+            " frame {}: {}x{} - {}",
+            frame_i + 1,
+            params.img_width,
+            params.img_height,
+            describe_image_file(&output_image_path),
+        );
+        println!();
+    }
+
+    println!(
+        "{} frames - {:.1}s elapsed",
+        frame_count,
+        global_start.elapsed().as_secs_f32()
+    );
+
+    Ok(())
+}
+
+/// Runs the chaos game while displaying the progress and colors the
+/// result.
+fn render_flame_image(params: &FlameParams) -> RgbImage {
+    let FlameParams {
+        img_width,
+        img_height,
+
+        zoom,
+        center_x,
+        center_y,
+        rotate,
+        ..
+    } = *params;
+
+    let view = View::new(img_width, img_height, zoom, center_x, center_y, rotate);
+
+    let progress = Progress::new((img_width * img_height) as usize);
+
+    let start = Instant::now();
+
+    let params_clone = params.clone();
+    let progress_clone = progress.clone();
+    let handle = thread::spawn(move || render_flame(&params_clone, &view, Some(progress_clone)));
+
+    while !handle.is_finished() {
+        print!(
+            "\r {:.1}% - {:.1}s elapsed",
+            100. * progress.get_progress(),
+            start.elapsed().as_secs_f32(),
+        );
+        std::io::stdout().flush().unwrap();
+
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let histogram = handle.join().unwrap(); // TODO replace unwrap
+
+    println!();
+
+    color_flame(params, histogram)
+}
+
+fn render_scene_frame(params: SceneParams, output_image_path: PathBuf) -> Result<()> {
+    let output_image = render_scene_image(&params);
+
+    output_image
+        .save(output_image_path.as_str())
+        .map_err(ErrorKind::SaveImage)?;
+
+    println!(
+        " output image: {}x{} - {}",
+        params.img_width,
+        params.img_height,
+        describe_image_file(&output_image_path),
+    );
+
+    Ok(())
+}
+
+fn render_scene_animation(params: SceneAnimationParams, output_image_path: PathBuf) -> Result<()> {
+    let frame_count = (params.duration * params.fps) as usize;
+
+    println!("frame count: {}", frame_count);
+    println!();
+
+    let global_start = Instant::now();
+
+    for frame_i in 0..frame_count {
+        let t = frame_i as f32 / params.fps;
+
+        let params = params.get_scene_params(t);
+        let output_image = render_scene_image(&params);
+
+        let output_image_path = frame_image_path(&output_image_path, frame_i);
+
+        output_image
+            .save(output_image_path.as_str())
+            .map_err(ErrorKind::SaveImage)?;
+
+        println!(
+            " frame {}: {}x{} - {}",
+            frame_i + 1,
+            params.img_width,
+            params.img_height,
+            describe_image_file(&output_image_path),
         );
         println!();
     }
@@ -350,6 +517,71 @@ fn render_animation(params: AnimationParams, output_image_path: PathBuf) -> Resu
     Ok(())
 }
 
+/// Ray marches the scene while displaying the progress.
+fn render_scene_image(params: &SceneParams) -> RgbImage {
+    let sampling_points = generate_sampling_points(params.sampling.level);
+
+    let progress = Progress::new((params.img_width * params.img_height) as usize);
+
+    let start = Instant::now();
+
+    let params_clone = params.clone();
+    let progress_clone = progress.clone();
+    let handle =
+        thread::spawn(move || render_scene(&params_clone, &sampling_points, Some(progress_clone)));
+
+    while !handle.is_finished() {
+        print!(
+            "\r {:.1}% - {:.1}s elapsed",
+            100. * progress.get_progress(),
+            start.elapsed().as_secs_f32(),
+        );
+        std::io::stdout().flush().unwrap();
+
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let output_image = handle.join().unwrap(); // TODO replace unwrap
+
+    println!();
+
+    output_image
+}
+
+/// Path of a frame of an animation: `<stem>_<frame index>.<extension>`
+/// next to `output_image_path`.
+fn frame_image_path(output_image_path: &PathBuf, frame_i: usize) -> PathBuf {
+    PathBuf::from(
+        output_image_path.parent().unwrap().to_string()
+            + "/"
+            + output_image_path.file_stem().unwrap()
+            + "_"
+            + &format!("{:06}", frame_i)
+            + "."
+            + output_image_path.extension().unwrap(),
+    )
+}
+
+/// Size and extension of a saved image, e.g. `12.3kb - png`.
+fn describe_image_file(path: &PathBuf) -> String {
+    let image_size = fs::metadata(path.as_str()).unwrap().len();
+    format!(
+        "{} {}",
+        if image_size / 1_000_000 != 0 {
+            format!("{:.1}mb", image_size as f32 / 1_000_000.)
+        } else if image_size / 1_000 != 0 {
+            format!("{:.1}kb", image_size as f32 / 1_000.)
+        } else {
+            format!("{}b", image_size)
+        },
+        if let Some(ext) = path.extension() {
+            format!("- {} ", ext)
+        } else {
+            "".to_string()
+        }
+    )
+}
+
 fn start_gui(
     params: FrameParams,
     param_file_path: PathBuf,
@@ -417,4 +649,38 @@ impl View {
             rotate: rotate.unwrap_or(0.),
         }
     }
+
+    /// Distance between the centers of two neighboring pixels.
+    pub fn pixel_size(&self, img_width: u32) -> F {
+        0.5 * self.width / img_width as F
+    }
+
+    /// Inverse of the transform used in `render_raw_image`: returns the
+    /// index (`i + j * img_width`) of the pixel containing a point, if
+    /// it is in the view.
+    pub fn pixel_index(
+        &self,
+        img_width: usize,
+        img_height: usize,
+    ) -> impl Fn(F, F) -> Option<usize> + Sync {
+        let View {
+            width,
+            height,
+            cx,
+            cy,
+            rotate,
+        } = *self;
+        let (sin, cos) = rotate.sin_cos();
+
+        move |re, im| {
+            let (dx, dy) = (re - cx, im - cy);
+            let x = (dx * cos + dy * sin) / (0.5 * width) + 0.5;
+            let y = (dy * cos - dx * sin) / (0.5 * height) + 0.5;
+            ((0. ..1.).contains(&x) && (0. ..1.).contains(&y)).then(|| {
+                let i = ((x * img_width as F) as usize).min(img_width - 1);
+                let j = ((y * img_height as F) as usize).min(img_height - 1);
+                i + j * img_width
+            })
+        }
+    }
 }
@@ -37,6 +37,12 @@ impl<T> Mat2D<T> {
         }
     }
 
+    /// Splits the matrix into tiles of `size`×`size` (smaller along the
+    /// right and bottom edges), see [`split_tiles`].
+    pub fn tiles_mut(&mut self, size: usize) -> Vec<Vec<&mut [T]>> {
+        split_tiles(&mut self.vec, self.width, size)
+    }
+
     #[inline]
     fn map_index(&self, index: (usize, usize)) -> usize {
         index.0 + index.1 * self.width
@@ -58,48 +64,118 @@ impl<T> IndexMut<(usize, usize)> for Mat2D<T> {
     }
 }
 
-// #[derive(Clone, PartialEq, Eq, Debug)]
-// pub struct Mat3D<T> {
-//     pub width: usize,
-//     pub height: usize,
-//     pub depth: usize,
-//     pub vec: Vec<T>,
-// }
-
-// impl<T> Mat3D<T>
-// where
-//     T: Clone,
-// {
-//     pub fn filled_with(value: T, width: usize, height: usize, depth: usize) -> Self {
-//         Self {
-//             width,
-//             height,
-//             depth,
-//             vec: vec![value; height * width * depth],
-//         }
-//     }
-
-//     pub fn get(&self, index: (usize, usize, usize)) -> Option<&T> {
-//         let index = self.map_index(index);
-//         if index < self.vec.len() {
-//             Some(&self.vec[index])
-//         } else {
-//             None
-//         }
-//     }
-
-//     pub fn set(&mut self, index: (usize, usize, usize), v: T) -> Result<(), ()> {
-//         let index = self.map_index(index);
-//         if index < self.vec.len() {
-//             self.vec[index] = v;
-//             Ok(())
-//         } else {
-//             Err(())
-//         }
-//     }
-
-//     #[inline]
-//     fn map_index(&self, index: (usize, usize, usize)) -> usize {
-//         index.0 + index.1 * self.width + index.2 * self.width * self.height
-//     }
-// }
+/// A stack of `depth` matrices of the same size, such as the channels
+/// of an image.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Mat3D<T> {
+    pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+    pub vec: Vec<T>,
+}
+
+impl<T> Mat3D<T> {
+    pub fn filled_with(value: T, width: usize, height: usize, depth: usize) -> Self
+    where
+        T: Clone,
+    {
+        Self {
+            width,
+            height,
+            depth,
+            vec: vec![value; height * width * depth],
+        }
+    }
+
+    pub fn get(&self, index: (usize, usize, usize)) -> Option<&T> {
+        let index = self.map_index(index);
+        if index < self.vec.len() {
+            Some(&self.vec[index])
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, index: (usize, usize, usize)) -> Option<&mut T> {
+        let index = self.map_index(index);
+        if index < self.vec.len() {
+            Some(&mut self.vec[index])
+        } else {
+            None
+        }
+    }
+
+    /// Copy of the matrix at the given depth.
+    pub fn layer(&self, k: usize) -> Mat2D<T>
+    where
+        T: Clone,
+    {
+        let len = self.width * self.height;
+        Mat2D {
+            width: self.width,
+            height: self.height,
+            vec: self.vec[k * len..(k + 1) * len].to_vec(),
+        }
+    }
+
+    /// Splits every matrix of the stack into tiles as with
+    /// [`Mat2D::tiles_mut`], each tile holding its rows in every matrix.
+    pub fn tiles_mut(&mut self, size: usize) -> Vec<Vec<Vec<&mut [T]>>> {
+        let len = self.width * self.height;
+        if len == 0 {
+            return Vec::new();
+        }
+
+        let mut tiles: Vec<Vec<_>> = Vec::new();
+        for layer in self.vec.chunks_mut(len) {
+            let layer_tiles = split_tiles(layer, self.width, size);
+            tiles.resize_with(layer_tiles.len(), || Vec::with_capacity(self.depth));
+            for (tile, rows) in tiles.iter_mut().zip(layer_tiles) {
+                tile.push(rows);
+            }
+        }
+        tiles
+    }
+
+    #[inline]
+    fn map_index(&self, index: (usize, usize, usize)) -> usize {
+        index.0 + index.1 * self.width + index.2 * self.width * self.height
+    }
+}
+
+impl<T> Index<(usize, usize, usize)> for Mat3D<T> {
+    type Output = T;
+
+    fn index(&self, index: (usize, usize, usize)) -> &Self::Output {
+        self.get(index)
+            .unwrap_or_else(|| panic!("index {:?} out of bounds", index))
+    }
+}
+impl<T> IndexMut<(usize, usize, usize)> for Mat3D<T> {
+    fn index_mut(&mut self, index: (usize, usize, usize)) -> &mut Self::Output {
+        self.get_mut(index)
+            .unwrap_or_else(|| panic!("index {:?} out of bounds", index))
+    }
+}
+
+/// Splits the rows of `width` values of `vec` into tiles of `size`×`size`
+/// (smaller along the right and bottom edges). Tiles are in row-major
+/// order, each one given as its rows.
+fn split_tiles<T>(vec: &mut [T], width: usize, size: usize) -> Vec<Vec<&mut [T]>> {
+    if width == 0 {
+        return Vec::new();
+    }
+
+    let tiles_x = width.div_ceil(size);
+    let mut tiles = Vec::new();
+    for (j, row) in vec.chunks_mut(width).enumerate() {
+        if j % size == 0 {
+            tiles.extend((0..tiles_x).map(|_| Vec::with_capacity(size)));
+        }
+        let first = tiles.len() - tiles_x;
+        for (tile, part) in tiles[first..].iter_mut().zip(row.chunks_mut(size)) {
+            tile.push(part);
+        }
+    }
+    tiles
+}
@@ -0,0 +1,114 @@
+//! Orbit averages: instead of the escape time, pixels get the mean of a
+//! quantity computed at each iteration of their orbit (see
+//! [`crate::fractal::SampleOptions::orbit_average`]). They give smooth
+//! textures outside the set once interpolated using the fractional part
+//! of the smooth iteration count.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{complexx::Complexx, simd::SimdFloat, F};
+
+/// Steps of orbits shorter than this many times the precision of their
+/// values are rounding noise (orbits that reached a cycle), their
+/// curvature is skipped.
+const MIN_STEP_ULPS: F = 1024.;
+
+/// The first value of the orbits (`z_1`, which is `c` for the Mandelbrot
+/// set) is skipped.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum OrbitAverage {
+    /// Triangle inequality average: where `|z_n|` lies between the bounds
+    /// `||z_n - c| - |c||` and `|z_n - c| + |c|`, from 0 to 1.
+    TriangleInequality,
+    /// `(sin(density arg(z_n)) + 1) / 2`
+    Stripe { density: F },
+    /// `|arg((z_n - z_{n-1}) / (z_{n-1} - z_{n-2}))| / π`, how sharply
+    /// the orbit turns.
+    Curvature,
+}
+
+/// Running sums of the terms of an [`OrbitAverage`] along orbits.
+#[derive(Debug, Clone, Copy)]
+pub struct AverageSum<X: SimdFloat> {
+    average: OrbitAverage,
+    /// Number of values seen so far (escaped lanes included).
+    steps: u32,
+    sum: X,
+    last_term: X,
+    count: X,
+    /// The two previous values of the orbits, for the curvature.
+    previous: [Complexx<X>; 2],
+}
+
+impl<X: SimdFloat> AverageSum<X> {
+    pub fn new(average: OrbitAverage) -> AverageSum<X> {
+        AverageSum {
+            average,
+            steps: 0,
+            sum: X::splat(0.),
+            last_term: X::splat(0.),
+            count: X::splat(0.),
+            previous: [Complexx::zeros(); 2],
+        }
+    }
+
+    /// Adds the term of the new value `z` of the orbits for the lanes
+    /// set in `mask`, `c` being the parameter of the recurrence. Terms
+    /// that are not defined (degenerate bounds, steps too short to have a
+    /// direction) are skipped.
+    #[inline(always)]
+    pub fn add(&mut self, mask: X, z: Complexx<X>, c: Complexx<X>) {
+        let zero = X::splat(0.);
+        let one = X::splat(1.);
+
+        let [z_2, z_1] = self.previous;
+        self.previous = [z_1, z];
+        self.steps += 1;
+
+        let (term, valid) = match self.average {
+            OrbitAverage::TriangleInequality if self.steps >= 2 => {
+                let m = (z - c).norm();
+                let c_norm = c.norm();
+                let low = (m - c_norm).abs();
+                let range = m + c_norm - low;
+                ((z.norm() - low) / range, zero.cmp_lt(range))
+            }
+            OrbitAverage::Stripe { density } if self.steps >= 2 => {
+                let arg = z.im.atan2(z.re);
+                let term = X::splat(0.5) * (X::splat(density) * arg).sin() + X::splat(0.5);
+                (term, zero.cmp_le(zero))
+            }
+            OrbitAverage::Curvature if self.steps >= 3 => {
+                // arg(a / b) = arg(a conj(b)), without dividing by zero.
+                let (a, b) = (z - z_1, z_1 - z_2);
+                let dot = a.re * b.re + a.im * b.im;
+                let cross = a.im * b.re - a.re * b.im;
+                let term = cross.atan2(dot).abs() * X::splat(std::f64::consts::FRAC_1_PI as F);
+
+                let min_step = X::splat(MIN_STEP_ULPS * X::EPSILON) * z_1.norm();
+                let valid = (min_step * min_step).cmp_lt(a.norm_sqr().min(b.norm_sqr()));
+                (term, valid)
+            }
+            _ => return,
+        };
+
+        let mask = mask.blend(valid, zero);
+        self.sum += mask.blend(term, zero);
+        self.last_term = mask.blend(term, self.last_term);
+        self.count += mask.blend(one, zero);
+    }
+
+    /// Means of the terms with and without the last one.
+    #[inline(always)]
+    pub fn means(&self) -> (X, X) {
+        let zero = X::splat(0.);
+        let one = X::splat(1.);
+
+        let mean = zero.cmp_lt(self.count).blend(self.sum / self.count, zero);
+        let previous_count = self.count - one;
+        let previous_mean = zero
+            .cmp_lt(previous_count)
+            .blend((self.sum - self.last_term) / previous_count, mean);
+        (mean, previous_mean)
+    }
+}
@@ -0,0 +1,94 @@
+//! Orbit traps: instead of the escape time, pixels get the minimum
+//! distance from their orbit to a shape of the plane (see
+//! [`crate::fractal::SampleOptions::orbit_trap`]).
+
+use serde::{Deserialize, Serialize};
+
+use crate::{complexx::Complexx, simd::SimdFloat, F};
+
+/// Shapes orbits are compared to, in the coordinates of the plane (`re`
+/// and `im` as for `c`). Angles are in radians.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum GenericOrbitTrap<T> {
+    Point {
+        re: T,
+        im: T,
+    },
+    /// The line through `(re, im)` with the given angle to the real
+    /// axis.
+    Line {
+        re: T,
+        im: T,
+        angle: T,
+    },
+    /// Two perpendicular lines crossing at `(re, im)`, the first one
+    /// having the given angle to the real axis.
+    Cross {
+        re: T,
+        im: T,
+        angle: T,
+    },
+    Circle {
+        re: T,
+        im: T,
+        radius: T,
+    },
+    /// The real and imaginary axes.
+    Axes,
+}
+
+pub type OrbitTrap = GenericOrbitTrap<F>;
+
+impl<T> GenericOrbitTrap<T> {
+    /// Maps the values of every parameter.
+    pub fn map_params<U>(&self, f: &impl Fn(&T) -> U) -> GenericOrbitTrap<U> {
+        match self {
+            GenericOrbitTrap::Point { re, im } => GenericOrbitTrap::Point {
+                re: f(re),
+                im: f(im),
+            },
+            GenericOrbitTrap::Line { re, im, angle } => GenericOrbitTrap::Line {
+                re: f(re),
+                im: f(im),
+                angle: f(angle),
+            },
+            GenericOrbitTrap::Cross { re, im, angle } => GenericOrbitTrap::Cross {
+                re: f(re),
+                im: f(im),
+                angle: f(angle),
+            },
+            GenericOrbitTrap::Circle { re, im, radius } => GenericOrbitTrap::Circle {
+                re: f(re),
+                im: f(im),
+                radius: f(radius),
+            },
+            GenericOrbitTrap::Axes => GenericOrbitTrap::Axes,
+        }
+    }
+}
+
+impl OrbitTrap {
+    /// Distance from `z` to the trap.
+    #[inline(always)]
+    pub fn distance<X: SimdFloat>(&self, z: Complexx<X>) -> X {
+        match *self {
+            OrbitTrap::Point { re, im } => (z - Complexx::splat(re, im)).norm(),
+            OrbitTrap::Line { re, im, angle } => {
+                let (sin, cos) = angle.sin_cos();
+                let Complexx { re: x, im: y } = z - Complexx::splat(re, im);
+                (y * X::splat(cos) - x * X::splat(sin)).abs()
+            }
+            OrbitTrap::Cross { re, im, angle } => {
+                let (sin, cos) = angle.sin_cos();
+                let Complexx { re: x, im: y } = z - Complexx::splat(re, im);
+                let along = (x * X::splat(cos) + y * X::splat(sin)).abs();
+                let across = (y * X::splat(cos) - x * X::splat(sin)).abs();
+                along.min(across)
+            }
+            OrbitTrap::Circle { re, im, radius } => {
+                ((z - Complexx::splat(re, im)).norm() - X::splat(radius)).abs()
+            }
+            OrbitTrap::Axes => z.re.abs().min(z.im.abs()),
+        }
+    }
+}
@@ -1,12 +1,28 @@
 use animation::RenderStep;
 use serde::{Deserialize, Serialize};
 
-use crate::{coloring::ColoringMode, fractal::Fractal, presets, sampling::Sampling, F};
+use crate::{
+    coloring::ColoringMode,
+    density::OrbitDensity,
+    flame::Xform,
+    fractal::{EscapeMetric, Fractal},
+    orbit_average::OrbitAverage,
+    orbit_trap::OrbitTrap,
+    presets,
+    sampling::Sampling,
+    scene::{Camera, Fractal3D},
+    simd::Precision,
+    F,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ParamsKind {
     Frame(FrameParams),
     Animation(AnimationParams),
+    Flame(FlameParams),
+    FlameAnimation(FlameAnimationParams),
+    Scene(SceneParams),
+    SceneAnimation(SceneAnimationParams),
 }
 
 impl Default for ParamsKind {
@@ -25,10 +41,42 @@ pub struct FrameParams {
     pub center_y: F,
     pub rotate: Option<F>,
     pub fractal: Fractal,
+    /// Render the Julia set associated with this value of `c` (re, im)
+    /// instead of the parameter plane.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub julia: Option<(F, F)>,
 
     pub max_iter: u32,
+    /// Overrides the squared escape radius of the fractal.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bailout: Option<F>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub escape_metric: Option<EscapeMetric>,
+    /// Float type used to render, defaults to [`Precision::Auto`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precision: Option<Precision>,
+    /// Render the density of the orbits of random points instead of
+    /// the escape time of each pixel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orbit_density: Option<OrbitDensity>,
+    /// Color pixels using the minimum distance of their orbit to this
+    /// trap instead of the escape time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orbit_trap: Option<OrbitTrap>,
+    /// Color pixels using the mean of a quantity along their orbit
+    /// instead of the escape time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orbit_average: Option<OrbitAverage>,
+    /// Stop orbits that come back to a previous value and count them as
+    /// not escaping (see [`crate::fractal::SampleOptions::check_periodicity`]).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_periodicity: Option<bool>,
 
     pub coloring_mode: ColoringMode,
+    /// Use smooth (continuous) iteration counts instead of integer
+    /// ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smooth: Option<bool>,
     pub sampling: Sampling,
 
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -48,13 +96,31 @@ pub struct AnimationParams {
     pub center_y: Vec<RenderStep>,
     pub rotate: Option<Vec<RenderStep>>,
     pub fractal: animation::Fractal,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub julia: Option<(Vec<RenderStep>, Vec<RenderStep>)>,
 
     pub max_iter: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bailout: Option<Vec<RenderStep>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub escape_metric: Option<EscapeMetric>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precision: Option<Precision>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orbit_density: Option<OrbitDensity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orbit_trap: Option<animation::OrbitTrap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orbit_average: Option<OrbitAverage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_periodicity: Option<bool>,
 
     pub duration: f32,
     pub fps: f32,
 
     pub coloring_mode: ColoringMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smooth: Option<bool>,
     pub sampling: Sampling,
 
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -79,8 +145,27 @@ impl AnimationParams {
                 .clone()
                 .map(|v| v[RenderStep::get_current_step_index(&v, t)].get_value(t)),
             fractal: self.fractal.get_fractal(t),
+            julia: self.julia.as_ref().map(|(re, im)| {
+                (
+                    re[RenderStep::get_current_step_index(re, t)].get_value(t),
+                    im[RenderStep::get_current_step_index(im, t)].get_value(t),
+                )
+            }),
             max_iter: self.max_iter,
+            bailout: self
+                .bailout
+                .as_ref()
+                .map(|v| v[RenderStep::get_current_step_index(v, t)].get_value(t)),
+            escape_metric: self.escape_metric,
+            precision: self.precision,
+            orbit_density: self.orbit_density,
+            orbit_trap: self.orbit_trap.as_ref().map(|trap| {
+                trap.map_params(&|v| v[RenderStep::get_current_step_index(v, t)].get_value(t))
+            }),
+            orbit_average: self.orbit_average,
+            check_periodicity: self.check_periodicity,
             coloring_mode: self.coloring_mode,
+            smooth: self.smooth,
             sampling: self.sampling,
             custom_gradient: self.custom_gradient.to_owned(),
             dev_options: self.dev_options,
@@ -88,6 +173,142 @@ impl AnimationParams {
     }
 }
 
+/// A fractal flame, see [`crate::flame`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlameParams {
+    pub img_width: u32,
+    pub img_height: u32,
+
+    pub zoom: F,
+    pub center_x: F,
+    pub center_y: F,
+    pub rotate: Option<F>,
+    pub xforms: Vec<Xform>,
+
+    /// Number of points of the chaos game per pixel of the image.
+    pub samples_per_pixel: u32,
+    /// Brightness curve applied to the log-density, defaults to 2.2.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gamma: Option<F>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_gradient: Option<Vec<(f32, [u8; 3])>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlameAnimationParams {
+    pub img_width: u32,
+    pub img_height: u32,
+
+    pub zoom: Vec<RenderStep>,
+    pub center_x: Vec<RenderStep>,
+    pub center_y: Vec<RenderStep>,
+    pub rotate: Option<Vec<RenderStep>>,
+    pub xforms: Vec<animation::Xform>,
+
+    pub samples_per_pixel: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gamma: Option<F>,
+
+    pub duration: f32,
+    pub fps: f32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_gradient: Option<Vec<(f32, [u8; 3])>>,
+}
+
+impl FlameAnimationParams {
+    pub fn get_flame_params(&self, t: f32) -> FlameParams {
+        FlameParams {
+            img_width: self.img_width,
+            img_height: self.img_height,
+            zoom: self.zoom[RenderStep::get_current_step_index(&self.zoom, t)].get_value(t),
+            center_x: self.center_x[RenderStep::get_current_step_index(&self.center_x, t)]
+                .get_value(t),
+            center_y: self.center_y[RenderStep::get_current_step_index(&self.center_y, t)]
+                .get_value(t),
+            rotate: self
+                .rotate
+                .clone()
+                .map(|v| v[RenderStep::get_current_step_index(&v, t)].get_value(t)),
+            xforms: self
+                .xforms
+                .iter()
+                .map(|xform| {
+                    xform.map_params(&|v| v[RenderStep::get_current_step_index(v, t)].get_value(t))
+                })
+                .collect(),
+            samples_per_pixel: self.samples_per_pixel,
+            gamma: self.gamma,
+            custom_gradient: self.custom_gradient.to_owned(),
+        }
+    }
+}
+
+/// A 3D fractal rendered by ray marching, see [`crate::scene`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneParams {
+    pub img_width: u32,
+    pub img_height: u32,
+
+    pub camera: Camera,
+    pub fractal: Fractal3D,
+
+    /// Iterations of the distance estimate.
+    pub max_iter: u32,
+    /// Steps of a ray before it is considered to have missed the
+    /// fractal, defaults to 256.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_steps: Option<u32>,
+    /// Direction towards the light.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub light: Option<(F, F, F)>,
+    pub sampling: Sampling,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_gradient: Option<Vec<(f32, [u8; 3])>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneAnimationParams {
+    pub img_width: u32,
+    pub img_height: u32,
+
+    pub camera: animation::Camera,
+    pub fractal: Fractal3D,
+
+    pub max_iter: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_steps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub light: Option<(F, F, F)>,
+    pub sampling: Sampling,
+
+    pub duration: f32,
+    pub fps: f32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_gradient: Option<Vec<(f32, [u8; 3])>>,
+}
+
+impl SceneAnimationParams {
+    pub fn get_scene_params(&self, t: f32) -> SceneParams {
+        SceneParams {
+            img_width: self.img_width,
+            img_height: self.img_height,
+            camera: self
+                .camera
+                .map_params(&|v| v[RenderStep::get_current_step_index(v, t)].get_value(t)),
+            fractal: self.fractal,
+            max_iter: self.max_iter,
+            max_steps: self.max_steps,
+            light: self.light,
+            sampling: self.sampling,
+            custom_gradient: self.custom_gradient.to_owned(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct DevOptions {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -143,88 +364,21 @@ pub mod animation {
         }
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub enum Fractal {
-        Mandelbrot,
-        MandelbrotCustomExp {
-            exp: Vec<RenderStep>,
-        },
-        SecondDegreeRecWithGrowingExponent,
-        SecondDegreeRecWithGrowingExponentParam {
-            a_re: Vec<RenderStep>,
-            a_im: Vec<RenderStep>,
-        },
-        SecondDegreeRecAlternating1WithGrowingExponent,
-        ThirdDegreeRecWithGrowingExponent,
-        NthDegreeRecWithGrowingExponent(usize),
-        ThirdDegreeRecPairs,
-        SecondDegreeThirtySevenBlend,
-
-        Vshqwj,
-        Wmriho {
-            a_re: Vec<RenderStep>,
-            a_im: Vec<RenderStep>,
-        },
-        Iigdzh {
-            a_re: Vec<RenderStep>,
-            a_im: Vec<RenderStep>,
-        },
-        Mjygzr,
-
-        ComplexLogisticMapLike {
-            a_re: Vec<RenderStep>,
-            a_im: Vec<RenderStep>,
-        },
-    }
+    /// A fractal whose parameters are animated.
+    pub type Fractal = crate::fractal::GenericFractal<Vec<RenderStep>>;
+
+    /// A transform of a fractal flame whose parameters are animated.
+    pub type Xform = crate::flame::GenericXform<Vec<RenderStep>>;
+
+    /// A camera whose position, target and field of view are animated.
+    pub type Camera = crate::scene::GenericCamera<Vec<RenderStep>>;
+
+    /// An orbit trap whose position and shape are animated.
+    pub type OrbitTrap = crate::orbit_trap::GenericOrbitTrap<Vec<RenderStep>>;
 
     impl Fractal {
         pub fn get_fractal(&self, t: f32) -> crate::fractal::Fractal {
-            match self {
-                Self::Mandelbrot => crate::fractal::Fractal::Mandelbrot,
-                Self::MandelbrotCustomExp { exp } => crate::fractal::Fractal::MandelbrotCustomExp {
-                    exp: exp[RenderStep::get_current_step_index(exp, t)].get_value(t),
-                },
-                Self::SecondDegreeRecWithGrowingExponent => {
-                    crate::fractal::Fractal::SecondDegreeRecWithGrowingExponent
-                }
-                Self::SecondDegreeRecWithGrowingExponentParam { a_re, a_im } => {
-                    crate::fractal::Fractal::SecondDegreeRecWithGrowingExponentParam {
-                        a_re: a_re[RenderStep::get_current_step_index(a_re, t)].get_value(t),
-                        a_im: a_im[RenderStep::get_current_step_index(a_im, t)].get_value(t),
-                    }
-                }
-                Self::SecondDegreeRecAlternating1WithGrowingExponent => {
-                    crate::fractal::Fractal::SecondDegreeRecAlternating1WithGrowingExponent
-                }
-                Self::ThirdDegreeRecWithGrowingExponent => {
-                    crate::fractal::Fractal::ThirdDegreeRecWithGrowingExponent
-                }
-                &Self::NthDegreeRecWithGrowingExponent(n) => {
-                    crate::fractal::Fractal::NthDegreeRecWithGrowingExponent(n)
-                }
-                Self::ThirdDegreeRecPairs => crate::fractal::Fractal::ThirdDegreeRecPairs,
-                Self::SecondDegreeThirtySevenBlend => {
-                    crate::fractal::Fractal::SecondDegreeThirtySevenBlend
-                }
-
-                Self::Vshqwj => crate::fractal::Fractal::Vshqwj,
-                Self::Wmriho { a_re, a_im } => crate::fractal::Fractal::Wmriho {
-                    a_re: a_re[RenderStep::get_current_step_index(a_re, t)].get_value(t),
-                    a_im: a_im[RenderStep::get_current_step_index(a_im, t)].get_value(t),
-                },
-                Self::Iigdzh { a_re, a_im } => crate::fractal::Fractal::Iigdzh {
-                    a_re: a_re[RenderStep::get_current_step_index(a_re, t)].get_value(t),
-                    a_im: a_im[RenderStep::get_current_step_index(a_im, t)].get_value(t),
-                },
-                Self::Mjygzr => crate::fractal::Fractal::Mjygzr,
-
-                Self::ComplexLogisticMapLike { a_re, a_im } => {
-                    crate::fractal::Fractal::ComplexLogisticMapLike {
-                        a_re: a_re[RenderStep::get_current_step_index(a_re, t)].get_value(t),
-                        a_im: a_im[RenderStep::get_current_step_index(a_im, t)].get_value(t),
-                    }
-                }
-            }
+            self.map_params(&|v| v[RenderStep::get_current_step_index(v, t)].get_value(t))
         }
     }
 }
@@ -0,0 +1,244 @@
+//! Deep zoom rendering using perturbation theory.
+//!
+//! Past a certain zoom, `F` can't tell neighboring pixels apart anymore.
+//! Instead, a single reference orbit `Z_n` is computed at the center of
+//! the view using arbitrary precision, and each pixel only iterates its
+//! (small) difference `δ_n = z_n - Z_n` to the reference:
+//!
+//! `δ_{n+1} = (Z_n + δ_n)^p - Z_n^p + δc`
+//!
+//! When `|z_n| < |δ_n|` (the reference is not a good approximation
+//! anymore, this causes glitches) or when the reference orbit ends, the
+//! pixel is rebased on the start of the reference orbit.
+//!
+//! The derivative used for distance estimation doesn't need perturbation,
+//! it is computed from the full values `Z_n + δ_n`.
+
+use wide::f64x4;
+
+use crate::{
+    bignum::{BigComplex, BigFixed},
+    complexx::Complexx,
+    fractal::{initial_trap, pixel_derivatives, Fractal, Orbit, Sample, SampleOptions},
+    orbit_average::AverageSum,
+    simd::{SimdFloat, MAX_LANES},
+    F,
+};
+
+/// Extra bits of precision used for the reference orbit on top of what
+/// is needed to tell pixels apart.
+const EXTRA_BITS: u32 = 64;
+
+#[derive(Debug, Clone)]
+pub struct ReferenceOrbit {
+    re: Vec<F>,
+    im: Vec<F>,
+    exp: u32,
+    /// `c` of the reference orbit (rounded).
+    c: (F, F),
+    /// `binomials[k]` is `exp` choose `k`.
+    binomials: Vec<F>,
+}
+
+/// Whether pixels of the given size can't be rendered accurately using
+/// `X` around `center`.
+pub fn needs_perturbation<X: SimdFloat>(center: (F, F), pixel_size: F) -> bool {
+    // Keep about 10 bits to tell apart sampling points inside a pixel.
+    let scale = center.0.abs().max(center.1.abs()).max(2.);
+    pixel_size < 1024. * X::EPSILON * scale
+}
+
+impl ReferenceOrbit {
+    /// Computes the reference orbit at `center` for the fractals that
+    /// support perturbation (see [`Fractal::perturbation_exponent`]).
+    pub fn new(
+        fractal: &Fractal,
+        center: (F, F),
+        pixel_size: F,
+        options: SampleOptions,
+    ) -> Option<ReferenceOrbit> {
+        let exp = fractal.perturbation_exponent()?;
+
+        let bits = (-pixel_size.log2()).max(0.) as u32 + EXTRA_BITS;
+        let frac = BigFixed::frac_limbs_for(bits);
+
+        let center = BigComplex::from_f64(center.0, center.1, frac);
+        let (mut z, c) = match options.julia {
+            Some((re, im)) => (center, BigComplex::from_f64(re, im, frac)),
+            None => (BigComplex::from_f64(0., 0., frac), center),
+        };
+
+        let bailout = options.bailout.unwrap_or(fractal.bailout());
+
+        let mut re = Vec::new();
+        let mut im = Vec::new();
+        for _ in 0..=options.max_iter {
+            let (z_re, z_im) = z.to_f64();
+            re.push(z_re);
+            im.push(z_im);
+
+            let norm_sqr = options
+                .escape_metric
+                .norm_sqr(Complexx::<f64x4>::splat(z_re, z_im))
+                .to_array()[0];
+            // Pixels need at least one step of the reference orbit.
+            if norm_sqr > bailout && re.len() > 1 {
+                break;
+            }
+
+            let next_z = z.powi(exp).add(&c);
+            if next_z.overflowed() {
+                // The orbit is stopped as if it escaped, its last value is
+                // only precise enough to tell that it is large.
+                let (c_re, c_im) = c.to_f64();
+                let last_z =
+                    Complexx::<f64x4>::splat(z_re, z_im).powi(exp) + Complexx::splat(c_re, c_im);
+                re.push(last_z.re.to_array()[0]);
+                im.push(last_z.im.to_array()[0]);
+                break;
+            }
+            z = next_z;
+        }
+
+        let binomials = (0..=exp)
+            .scan(1., |binomial: &mut F, k| {
+                let value = *binomial;
+                *binomial = *binomial * (exp - k) as F / (k + 1) as F;
+                Some(value)
+            })
+            .collect();
+
+        Some(ReferenceOrbit {
+            re,
+            im,
+            c: c.to_f64(),
+            exp,
+            binomials,
+        })
+    }
+
+    #[inline]
+    fn gather<X: SimdFloat>(&self, m: &[usize; MAX_LANES]) -> Complexx<X> {
+        Complexx {
+            re: X::from_fn(|k| self.re[m[k]]),
+            im: X::from_fn(|k| self.im[m[k]]),
+        }
+    }
+
+    /// Computes `(z + dz)^p - z^p` without cancellation.
+    #[inline]
+    fn delta_step<X: SimdFloat>(&self, z: Complexx<X>, dz: Complexx<X>) -> Complexx<X> {
+        if self.exp == 2 {
+            return (z * 2. + dz) * dz;
+        }
+
+        // Horner scheme on sum_{k=1}^{p} binomial(p, k) z^(p-k) dz^k
+        let mut acc = Complexx::splat(1., 0.);
+        let mut z_pow = Complexx::splat(1., 0.);
+        for k in (1..self.exp as usize).rev() {
+            z_pow = z_pow * z;
+            acc = acc * dz + z_pow * self.binomials[k];
+        }
+        acc * dz
+    }
+
+    /// Samples the fractal at `offset` from the center of the view (the
+    /// start of the reference orbit).
+    pub fn sample<X: SimdFloat>(
+        &self,
+        fractal: &Fractal,
+        offset: Complexx<X>,
+        options: SampleOptions,
+    ) -> Sample {
+        let SampleOptions {
+            max_iter,
+            julia,
+            bailout,
+            escape_metric,
+            distance_estimation,
+            orbit_trap,
+            orbit_average,
+            ..
+        } = options;
+
+        let one = X::splat(1.0);
+        let zero = X::splat(0.0);
+
+        let bailout = bailout.unwrap_or(fractal.bailout());
+        let bailout_mask = X::splat(bailout);
+
+        let (mut dz, dc) = match julia {
+            Some(_) => (offset, Complexx::zeros()),
+            None => (Complexx::zeros(), offset),
+        };
+
+        // Derivative of `z` with respect to the pixel.
+        let (mut der, der_c) = pixel_derivatives(options);
+        let mut last_der = Complexx::zeros();
+
+        let len = self.re.len();
+        let mut m = [0; MAX_LANES];
+
+        let mut last_z = Complexx::zeros();
+        let mut trap = initial_trap(options, self.gather(&m) + dz, bailout);
+        let mut average_sum = orbit_average.map(AverageSum::new);
+        let mut iter = X::splat(0.);
+        for _ in 0..max_iter {
+            let mut zm = self.gather(&m);
+            let z = zm + dz;
+
+            let undiverged_mask = escape_metric.norm_sqr(z).cmp_le(bailout_mask);
+            if !undiverged_mask.any() {
+                break;
+            }
+
+            let glitched = z.norm_sqr().cmp_lt(dz.norm_sqr()).move_mask();
+            if glitched != 0 || m[..X::LANES].iter().any(|&m| m + 1 >= len) {
+                let (z_re, z_im) = (z.re.to_array(), z.im.to_array());
+                let (mut dz_re, mut dz_im) = (dz.re.to_array(), dz.im.to_array());
+                for k in 0..X::LANES {
+                    if glitched >> k & 1 == 1 || m[k] + 1 >= len {
+                        dz_re[k] = z_re[k] - self.re[0];
+                        dz_im[k] = z_im[k] - self.im[0];
+                        m[k] = 0;
+                    }
+                }
+                dz = Complexx {
+                    re: X::from_fn(|k| dz_re[k]),
+                    im: X::from_fn(|k| dz_im[k]),
+                };
+                zm = self.gather(&m);
+            }
+
+            if distance_estimation {
+                der = z.powi(self.exp - 1) * der * self.exp as F + der_c;
+                last_der = Complexx::select(undiverged_mask, der, last_der);
+            }
+
+            dz = self.delta_step(zm, dz) + dc;
+            m.iter_mut().for_each(|m| *m += 1);
+
+            let new_z = self.gather(&m) + dz;
+            iter += undiverged_mask.blend(one, zero);
+            last_z = Complexx::select(undiverged_mask, new_z, last_z);
+            if let Some(orbit_trap) = &orbit_trap {
+                trap = undiverged_mask.blend(trap.min(orbit_trap.distance(new_z)), trap);
+            }
+            if let Some(average_sum) = &mut average_sum {
+                let c = Complexx::splat(self.c.0, self.c.1) + dc;
+                average_sum.add(undiverged_mask, new_z, c);
+            }
+        }
+
+        let orbit = Orbit {
+            iter,
+            last_z,
+            last_dz: last_der,
+            trap,
+            averages: average_sum.map_or((zero, zero), |sum| sum.means()),
+            // Deltas to the reference are not checked for periodicity.
+            period: zero,
+        };
+        fractal.finish_sample(orbit, options)
+    }
+}
@@ -24,10 +24,12 @@ pub const PHGZBZ: &str = include_str!("../presets/phgzbz.ron");
 pub const QBQNHN: &str = include_str!("../presets/qbqnhn.ron");
 pub const QRBCWF: &str = include_str!("../presets/qrbcwf.ron");
 pub const QUHUAP: &str = include_str!("../presets/quhuap.ron");
+pub const TXKTFJ: &str = include_str!("../presets/txktfj.ron");
 pub const UKHBRP: &str = include_str!("../presets/ukhbrp.ron");
 pub const UNJRYJ: &str = include_str!("../presets/unjryj.ron");
 pub const WZTPFT: &str = include_str!("../presets/wztpft.ron");
 pub const XVEBHD: &str = include_str!("../presets/xvebhd.ron");
+pub const XEZZPC: &str = include_str!("../presets/xezzpc.ron");
 pub const ZTKHKY: &str = include_str!("../presets/ztkhky.ron");
 pub const ZXKREW: &str = include_str!("../presets/zxkrew.ron");
 
@@ -58,10 +60,12 @@ pub const PRESETS: &[(&str, &str)] = &[
     ("qbqnhn", QBQNHN),
     ("qrbcwf", QRBCWF),
     ("quhuap", QUHUAP),
+    ("txktfj", TXKTFJ),
     ("ukhbrp", UKHBRP),
     ("unjryj", UNJRYJ),
     ("wztpft", WZTPFT),
     ("xvebhd", XVEBHD),
+    ("xezzpc", XEZZPC),
     ("ztkhky", ZTKHKY),
     ("zxkrew", ZXKREW),
 ];
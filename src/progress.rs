@@ -18,10 +18,16 @@ impl Progress {
     }
 
     pub fn incr(&self) {
+        self.add(1);
+    }
+
+    /// Counts several steps at once, to update the shared counter less
+    /// often.
+    pub fn add(&self, count: usize) {
         // Using atomic::Ordering::Relaxed because we don't really
         // care about the order `progress` is updated. As long as it
         // is updated it should be fine :>
-        self.progress.fetch_add(1, Ordering::Relaxed);
+        self.progress.fetch_add(count, Ordering::Relaxed);
     }
 
     pub fn get(&self) -> usize {
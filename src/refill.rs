@@ -0,0 +1,367 @@
+//! Lane refilling: instead of sampling points by chunks of
+//! [`SimdFloat::LANES`] and waiting for the slowest orbit of each chunk,
+//! each lane picks up the next pending point as soon as its orbit ends,
+//! so that lanes don't stay idle behind slow orbits.
+
+use crate::{
+    complexx::Complexx,
+    fractal::{
+        initial_trap, mandelbrot_interior_period, pixel_derivatives, CycleCheck, Fractal, Orbit,
+        Sample, SampleOptions,
+    },
+    simd::{SimdFloat, MAX_LANES},
+    F,
+};
+
+/// Samples the points `0..count` by chunks of lanes, `pixels` gives the
+/// pixels of the points whose indices are in each lane and `sample`
+/// samples them. `done` gets the index of each point, the sample it is
+/// part of and its lane.
+pub fn sample_chunks<X: SimdFloat>(
+    count: usize,
+    pixels: impl Fn(&[usize; MAX_LANES]) -> Complexx<X>,
+    sample: impl Fn(Complexx<X>) -> Sample,
+    mut done: impl FnMut(usize, &Sample, usize),
+) {
+    for start in (0..count).step_by(X::LANES) {
+        let l = X::LANES.min(count - start);
+        // The last chunk repeats its points in the remaining lanes, the
+        // cost is the same whether they are computed or not.
+        let indices = std::array::from_fn(|lane| start + lane % l);
+        let sample = sample(pixels(&indices));
+        for lane in 0..l {
+            done(start + lane, &sample, lane);
+        }
+    }
+}
+
+/// Orbits running in the lanes.
+struct Lanes<X: SimdFloat> {
+    z: Vec<Complexx<X>>,
+    /// Derivatives of the history, for distance estimation.
+    dz: Option<Vec<Complexx<X>>>,
+    c: Complexx<X>,
+    iter: X,
+    last_z: Complexx<X>,
+    last_dz: Complexx<X>,
+    trap: X,
+    cycle_check: Option<CycleCheck<X>>,
+}
+
+impl<X: SimdFloat> Lanes<X> {
+    /// Result of the orbits, see [`Fractal::finish_sample`].
+    #[inline(always)]
+    fn orbit(&self, max_iter: u32) -> Orbit<X> {
+        let zero = X::splat(0.);
+        let (periodic, period) = self
+            .cycle_check
+            .as_ref()
+            .map_or((zero, zero), |cycle_check| {
+                (cycle_check.periodic, cycle_check.period)
+            });
+        Orbit {
+            iter: periodic.blend(X::splat(max_iter as F), self.iter),
+            last_z: self.last_z,
+            last_dz: self.last_dz,
+            trap: self.trap,
+            averages: (zero, zero),
+            period,
+        }
+    }
+
+    /// Starts new orbits from the history `z` and `c` in the lanes set in
+    /// `mask`.
+    #[inline(always)]
+    fn restart(
+        &mut self,
+        mask: X,
+        z: &[Complexx<X>],
+        c: Complexx<X>,
+        dz_init: Complexx<X>,
+        trap: X,
+    ) {
+        let zero = X::splat(0.);
+        for (z, &new_z) in self.z.iter_mut().zip(z) {
+            *z = Complexx::select(mask, new_z, *z);
+        }
+        if let Some(dz) = &mut self.dz {
+            let depth = dz.len();
+            for dz in dz.iter_mut() {
+                *dz = Complexx::select(mask, Complexx::zeros(), *dz);
+            }
+            dz[depth - 1] = Complexx::select(mask, dz_init, dz[depth - 1]);
+        }
+        self.c = Complexx::select(mask, c, self.c);
+        self.iter = mask.blend(zero, self.iter);
+        self.last_z = Complexx::select(mask, Complexx::zeros(), self.last_z);
+        self.last_dz = Complexx::select(mask, Complexx::zeros(), self.last_dz);
+        self.trap = mask.blend(trap, self.trap);
+        if let Some(cycle_check) = &mut self.cycle_check {
+            cycle_check.reset(mask, z);
+        }
+    }
+}
+
+/// Iterates the orbits of the points `0..count` as in [`Fractal::sample`]
+/// (see [`crate::fractal::iterate_with_derivative`] for `step` and
+/// `derivative`, which get zero as iteration number), refilling lanes
+/// whose orbit stopped with the next points. `pixels` and `done` are as
+/// in [`sample_chunks`].
+///
+/// The fractal must be able to refill lanes (see
+/// [`Fractal::can_refill`]), the derivative is only tracked for distance
+/// estimation. Orbits stop for good the first time they escape.
+#[allow(clippy::too_many_arguments)]
+#[inline(always)]
+pub fn iterate_refilling<X: SimdFloat>(
+    fractal: &Fractal,
+    depth: usize,
+    count: usize,
+    pixels: impl Fn(&[usize; MAX_LANES]) -> Complexx<X>,
+    bailout: F,
+    options: SampleOptions,
+    mut step: impl FnMut(&[Complexx<X>], Complexx<X>, u32) -> Complexx<X>,
+    mut derivative: impl FnMut(&[Complexx<X>], &[Complexx<X>], Complexx<X>, u32) -> Complexx<X>,
+    mut done: impl FnMut(usize, &Sample, usize),
+) {
+    let SampleOptions {
+        max_iter,
+        julia,
+        escape_metric,
+        orbit_trap,
+        ..
+    } = options;
+
+    let one = X::splat(1.0);
+    let zero = X::splat(0.0);
+    let all = zero.cmp_le(zero);
+
+    let bailout_mask = X::splat(bailout);
+    let max_iter_mask = X::splat(max_iter as F);
+    let interior_test = fractal.has_interior_test(options, bailout);
+    let (dz_init, dc) = pixel_derivatives(options);
+
+    // Outside of Julia mode, every orbit starts from the same history.
+    let shared_history = julia
+        .is_none()
+        .then(|| fractal.initial_history(depth, Complexx::zeros()));
+
+    // Lanes are refilled once enough of them are idle, as refilling
+    // costs several iterations.
+    let min_idle = (X::LANES / 2).max(1) as u32;
+
+    // Index of the point of each lane, lanes whose orbit is running and
+    // lanes whose orbit stopped but wasn't passed to `done` yet.
+    let mut owners = [0; MAX_LANES];
+    let mut active = zero;
+    let mut pending = zero;
+    let mut next = 0;
+
+    let z = vec![Complexx::zeros(); depth];
+    let mut lanes = Lanes {
+        dz: (options.distance_estimation && fractal.has_derivative()).then(|| z.clone()),
+        cycle_check: options.check_periodicity.then(|| CycleCheck::new(&z)),
+        z,
+        c: Complexx::zeros(),
+        iter: zero,
+        last_z: Complexx::zeros(),
+        last_dz: Complexx::zeros(),
+        trap: zero,
+    };
+    loop {
+        let mut undiverged_mask = active.blend(
+            escape_metric
+                .norm_sqr(lanes.z[depth - 1])
+                .cmp_le(bailout_mask),
+            zero,
+        );
+        undiverged_mask = lanes
+            .iter
+            .cmp_lt(max_iter_mask)
+            .blend(undiverged_mask, zero);
+        if let Some(cycle_check) = &lanes.cycle_check {
+            undiverged_mask = cycle_check.periodic.blend(zero, undiverged_mask);
+        }
+
+        let stopped = undiverged_mask.blend(zero, active);
+        if stopped.any() || !active.any() {
+            // The output of the lanes that stopped doesn't change anymore
+            // (only their history does).
+            pending = stopped.blend(all, pending);
+            active = undiverged_mask;
+
+            let idle_bits = active.blend(zero, all).move_mask() & ((1 << X::LANES) - 1);
+            let refill_now = idle_bits.count_ones() >= min_idle || !active.any();
+
+            if refill_now && pending.any() {
+                let sample = fractal.finish_sample(lanes.orbit(max_iter), options);
+                let bits = pending.move_mask();
+                for (lane, &owner) in owners.iter().enumerate().take(X::LANES) {
+                    if bits & (1 << lane) != 0 {
+                        done(owner, &sample, lane);
+                    }
+                }
+                pending = zero;
+            }
+
+            if refill_now && next < count {
+                let mut refilled = [false; MAX_LANES];
+                for lane in 0..X::LANES {
+                    if idle_bits & (1 << lane) != 0 && next < count {
+                        owners[lane] = next;
+                        refilled[lane] = true;
+                        next += 1;
+                    }
+                }
+                let refill = zero.cmp_lt(X::from_fn(|lane| refilled[lane] as u8 as F));
+
+                let pixel = pixels(&owners);
+                let (z_init, c) = match julia {
+                    Some((re, im)) => (pixel, Complexx::splat(re, im)),
+                    None => (Complexx::zeros(), pixel),
+                };
+                let history;
+                let history = match &shared_history {
+                    Some(history) => history,
+                    None => {
+                        history = fractal.initial_history(depth, z_init);
+                        &history
+                    }
+                };
+                let trap = initial_trap(options, history[depth - 1], bailout);
+                lanes.restart(refill, history, c, dz_init, trap);
+                if let (true, Some(cycle_check)) = (interior_test, &mut lanes.cycle_check) {
+                    cycle_check.set_period(refill.blend(mandelbrot_interior_period(c), zero));
+                }
+
+                active = refill.blend(all, active);
+                // The new points may stop right away.
+                continue;
+            }
+
+            if !active.any() {
+                break;
+            }
+        }
+
+        let Lanes {
+            z,
+            dz,
+            c,
+            iter,
+            last_z,
+            last_dz,
+            trap,
+            cycle_check,
+        } = &mut lanes;
+
+        // The derivative uses the values before the step.
+        if let Some(dz) = dz {
+            let new_dz = derivative(z, dz, dc, 0);
+            dz.rotate_left(1);
+            dz[depth - 1] = new_dz;
+            *last_dz = Complexx::select(undiverged_mask, new_dz, *last_dz);
+        }
+        let new_z = step(z, *c, 0);
+        z.rotate_left(1);
+        z[depth - 1] = new_z;
+
+        *iter += undiverged_mask.blend(one, zero);
+        *last_z = Complexx::select(undiverged_mask, new_z, *last_z);
+        if let Some(orbit_trap) = &orbit_trap {
+            *trap = undiverged_mask.blend(trap.min(orbit_trap.distance(new_z)), *trap);
+        }
+        if let Some(cycle_check) = cycle_check {
+            cycle_check.check(undiverged_mask, z, *iter);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wide::f64x4;
+
+    use super::*;
+    use crate::{fractal::EscapeMetric, registry::FractalKind};
+
+    /// Values, iteration counts and distances of the points of a grid,
+    /// sampled with `sample`.
+    fn sample_grid(
+        sample: impl FnOnce(
+            usize,
+            &dyn Fn(&[usize; MAX_LANES]) -> Complexx<f64x4>,
+            &mut dyn FnMut(usize, &Sample, usize),
+        ),
+    ) -> Vec<(F, F, F)> {
+        const WIDTH: usize = 23;
+        const HEIGHT: usize = 17;
+        let point = |i: usize| {
+            let (x, y) = ((i % WIDTH) as F, (i / WIDTH) as F);
+            (-2.2 + 3. * x / WIDTH as F, -1.5 + 3. * y / HEIGHT as F)
+        };
+        let pixels = |indices: &[usize; MAX_LANES]| Complexx {
+            re: f64x4::from_fn(|lane| point(indices[lane]).0),
+            im: f64x4::from_fn(|lane| point(indices[lane]).1),
+        };
+
+        let mut values = vec![(F::NAN, F::NAN, F::NAN); WIDTH * HEIGHT];
+        sample(WIDTH * HEIGHT, &pixels, &mut |i, sample, lane| {
+            values[i] = (
+                sample.value[lane],
+                sample.escape.unwrap().iter[lane],
+                sample.distance.map_or(0., |distance| distance[lane]),
+            )
+        });
+        values
+    }
+
+    #[test]
+    fn refilling_matches_chunks() {
+        for &kind in FractalKind::ALL {
+            let fractal = Fractal::with_default_params(kind);
+            for (julia, check_periodicity) in [
+                (None, false),
+                (None, true),
+                (Some((-0.8, 0.156)), false),
+                (Some((-0.8, 0.156)), true),
+            ] {
+                let options = SampleOptions {
+                    max_iter: 300,
+                    smooth: true,
+                    julia,
+                    bailout: None,
+                    escape_metric: EscapeMetric::Euclidean,
+                    formula: None,
+                    roots: &[],
+                    distance_estimation: fractal.has_derivative(),
+                    orbit_trap: None,
+                    orbit_average: None,
+                    check_periodicity,
+                };
+                let refilled = sample_grid(|count, pixels, done| {
+                    fractal.sample_points(count, pixels, options, done)
+                });
+                let chunks = sample_grid(|count, pixels, done| {
+                    sample_chunks(count, pixels, |pixel| fractal.sample(pixel, options), done)
+                });
+                for (i, (a, b)) in refilled.iter().zip(&chunks).enumerate() {
+                    let close = |a: F, b: F| {
+                        a == b
+                            || (a - b).abs() <= 1e-9 * a.abs().max(1.)
+                            || a.is_nan() && b.is_nan()
+                    };
+                    assert!(
+                        close(a.0, b.0) && a.1 == b.1 && close(a.2, b.2),
+                        "{:?} (Julia: {:?}, periodicity checking: {}), point {}: {:?} != {:?}",
+                        kind,
+                        julia,
+                        check_periodicity,
+                        i,
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+}
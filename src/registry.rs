@@ -0,0 +1,900 @@
+//! Built-in fractals. Each one implements [`FractalFormula`] and is listed
+//! once in the registry at the bottom of this file, which generates
+//! [`FractalKind`]. The renderer, animations and the gui only go through
+//! [`FractalKind`] so they pick up new fractals automatically.
+
+use std::ops::RangeInclusive;
+
+use crate::{
+    complexx::Complexx,
+    fractal::{
+        iterate, iterate_with_derivative, pixel_derivatives, Fractal, Orbit, Sample, SampleOptions,
+    },
+    refill::iterate_refilling,
+    simd::{SimdFloat, MAX_LANES},
+    F,
+};
+
+const PHI: F = 1.618033988749895;
+const SILVER_RATIO: F = 2.414213562373095;
+
+/// A parameter of a fractal.
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: &'static str,
+    pub default: F,
+    pub range: RangeInclusive<F>,
+    /// Values are rounded to the nearest integer.
+    pub integer: bool,
+}
+
+impl Param {
+    const fn real(name: &'static str, default: F) -> Param {
+        Param {
+            name,
+            default,
+            range: F::NEG_INFINITY..=F::INFINITY,
+            integer: false,
+        }
+    }
+
+    const fn ranged(name: &'static str, default: F, range: RangeInclusive<F>) -> Param {
+        Param {
+            name,
+            default,
+            range,
+            integer: false,
+        }
+    }
+
+    const fn integer(name: &'static str, default: F, range: RangeInclusive<F>) -> Param {
+        Param {
+            name,
+            default,
+            range,
+            integer: true,
+        }
+    }
+}
+
+/// How fast an orbit grows once it has escaped, this is used to compute
+/// smooth iteration counts.
+#[derive(Debug, Clone, Copy)]
+pub enum Growth {
+    /// |z_{n+1}| ~ |z_n|^d
+    Power(F),
+    /// |z_{n+1}| ~ k |z_n|
+    Linear(F),
+    None,
+}
+
+/// Describes a recurrence `z_{n+1} = step(z_{n-depth+1}, ..., z_n, c)`.
+///
+/// All the functions get the values of the parameters in the order of
+/// [`FractalFormula::PARAMS`].
+pub trait FractalFormula {
+    /// Name used in parameter files.
+    const NAME: &'static str;
+    const PARAMS: &'static [Param] = &[];
+    /// Whether parameters are written without their names in parameter
+    /// files, as in `NthDegreeRecWithGrowingExponent(4)`.
+    const UNNAMED_PARAMS: bool = false;
+    /// Squared escape radius.
+    const BAILOUT: F = 4.;
+
+    /// Number of history values used by [`FractalFormula::step`].
+    fn depth(_params: &[F]) -> usize {
+        1
+    }
+
+    fn growth(params: &[F]) -> Growth;
+
+    /// Exponent `p` when the recurrence is `z^p + c`, see
+    /// [`crate::perturbation`].
+    fn perturbation_exponent(_params: &[F]) -> Option<u32> {
+        None
+    }
+
+    /// Initial value of the most recent history value, the other ones
+    /// start at zero. `z_init` is the pixel in Julia mode and zero
+    /// otherwise.
+    fn init<X: SimdFloat>(_params: &[F], z_init: Complexx<X>) -> Complexx<X> {
+        z_init
+    }
+
+    /// Computes the next value from the history values `z` (oldest
+    /// first) at iteration `i`.
+    fn step<X: SimdFloat>(params: &[F], z: &[Complexx<X>], c: Complexx<X>, i: u32) -> Complexx<X>;
+
+    /// Whether [`FractalFormula::derivative`] is implemented, which is
+    /// needed for distance estimation.
+    const HAS_DERIVATIVE: bool = false;
+
+    /// Whether [`FractalFormula::step`] doesn't depend on the iteration
+    /// number, so that orbits coming back to a previous history are
+    /// periodic (see [`SampleOptions::check_periodicity`]).
+    const AUTONOMOUS: bool = true;
+
+    /// Derivative of [`FractalFormula::step`] with respect to the pixel,
+    /// `dz` holds the derivatives of the history values and `dc` the one
+    /// of `c` (one, or zero in Julia mode).
+    fn derivative<X: SimdFloat>(
+        _params: &[F],
+        _z: &[Complexx<X>],
+        _dz: &[Complexx<X>],
+        _dc: Complexx<X>,
+        _i: u32,
+    ) -> Complexx<X> {
+        unreachable!("{} has no derivative", Self::NAME)
+    }
+}
+
+pub struct Mandelbrot;
+impl FractalFormula for Mandelbrot {
+    const NAME: &'static str = "Mandelbrot";
+
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    fn perturbation_exponent(_: &[F]) -> Option<u32> {
+        Some(2)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[0] * z[0] + c
+    }
+    const HAS_DERIVATIVE: bool = true;
+    #[inline(always)]
+    fn derivative<X: SimdFloat>(
+        _: &[F],
+        z: &[Complexx<X>],
+        dz: &[Complexx<X>],
+        dc: Complexx<X>,
+        _: u32,
+    ) -> Complexx<X> {
+        z[0] * dz[0] * 2. + dc
+    }
+}
+
+pub struct MandelbrotCustomExp;
+impl FractalFormula for MandelbrotCustomExp {
+    const NAME: &'static str = "MandelbrotCustomExp";
+    const PARAMS: &'static [Param] = &[Param::ranged("exp", 2., 0.001..=20.)];
+
+    fn growth(params: &[F]) -> Growth {
+        match params[0] {
+            exp if exp > 1. => Growth::Power(exp),
+            _ => Growth::None,
+        }
+    }
+    fn perturbation_exponent(params: &[F]) -> Option<u32> {
+        let exp = params[0];
+        (exp >= 2. && exp.fract() == 0.).then_some(exp as u32)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(params: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[0].powf(params[0]) + c
+    }
+    const HAS_DERIVATIVE: bool = true;
+    #[inline(always)]
+    fn derivative<X: SimdFloat>(
+        params: &[F],
+        z: &[Complexx<X>],
+        dz: &[Complexx<X>],
+        dc: Complexx<X>,
+        _: u32,
+    ) -> Complexx<X> {
+        z[0].powf(params[0] - 1.) * dz[0] * params[0] + dc
+    }
+}
+
+pub struct SecondDegreeRecWithGrowingExponent;
+impl FractalFormula for SecondDegreeRecWithGrowingExponent {
+    const NAME: &'static str = "SecondDegreeRecWithGrowingExponent";
+
+    fn depth(_: &[F]) -> usize {
+        2
+    }
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[1] * z[1] + z[0] + c
+    }
+    const HAS_DERIVATIVE: bool = true;
+    #[inline(always)]
+    fn derivative<X: SimdFloat>(
+        _: &[F],
+        z: &[Complexx<X>],
+        dz: &[Complexx<X>],
+        dc: Complexx<X>,
+        _: u32,
+    ) -> Complexx<X> {
+        z[1] * dz[1] * 2. + dz[0] + dc
+    }
+}
+
+pub struct SecondDegreeRecWithGrowingExponentParam;
+impl FractalFormula for SecondDegreeRecWithGrowingExponentParam {
+    const NAME: &'static str = "SecondDegreeRecWithGrowingExponentParam";
+    const PARAMS: &'static [Param] = &[Param::real("a_re", 1.), Param::real("a_im", 0.)];
+
+    fn depth(_: &[F]) -> usize {
+        2
+    }
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(params: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        let a = Complexx::splat(params[0], params[1]);
+        z[1] * z[1] + a * z[0] + c
+    }
+    const HAS_DERIVATIVE: bool = true;
+    #[inline(always)]
+    fn derivative<X: SimdFloat>(
+        params: &[F],
+        z: &[Complexx<X>],
+        dz: &[Complexx<X>],
+        dc: Complexx<X>,
+        _: u32,
+    ) -> Complexx<X> {
+        let a = Complexx::splat(params[0], params[1]);
+        z[1] * dz[1] * 2. + a * dz[0] + dc
+    }
+}
+
+pub struct SecondDegreeRecAlternating1WithGrowingExponent;
+impl FractalFormula for SecondDegreeRecAlternating1WithGrowingExponent {
+    const NAME: &'static str = "SecondDegreeRecAlternating1WithGrowingExponent";
+
+    fn depth(_: &[F]) -> usize {
+        2
+    }
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[1] * z[1] - z[0] + c
+    }
+    const HAS_DERIVATIVE: bool = true;
+    #[inline(always)]
+    fn derivative<X: SimdFloat>(
+        _: &[F],
+        z: &[Complexx<X>],
+        dz: &[Complexx<X>],
+        dc: Complexx<X>,
+        _: u32,
+    ) -> Complexx<X> {
+        z[1] * dz[1] * 2. - dz[0] + dc
+    }
+}
+
+pub struct ThirdDegreeRecWithGrowingExponent;
+impl FractalFormula for ThirdDegreeRecWithGrowingExponent {
+    const NAME: &'static str = "ThirdDegreeRecWithGrowingExponent";
+
+    fn depth(_: &[F]) -> usize {
+        3
+    }
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(3.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[2] * z[2] * z[2] + z[1] * z[1] + z[0] + c
+    }
+    const HAS_DERIVATIVE: bool = true;
+    #[inline(always)]
+    fn derivative<X: SimdFloat>(
+        _: &[F],
+        z: &[Complexx<X>],
+        dz: &[Complexx<X>],
+        dc: Complexx<X>,
+        _: u32,
+    ) -> Complexx<X> {
+        z[2] * z[2] * dz[2] * 3. + z[1] * dz[1] * 2. + dz[0] + dc
+    }
+}
+
+pub struct NthDegreeRecWithGrowingExponent;
+impl FractalFormula for NthDegreeRecWithGrowingExponent {
+    const NAME: &'static str = "NthDegreeRecWithGrowingExponent";
+    const PARAMS: &'static [Param] = &[Param::integer("n", 4., 2.0..=20.)];
+    const UNNAMED_PARAMS: bool = true;
+
+    fn depth(params: &[F]) -> usize {
+        (params[0].round() as usize).max(1)
+    }
+    fn growth(params: &[F]) -> Growth {
+        // The last term is z_{n-1}^(2^n).
+        Growth::Power((2. as F).powi(Self::depth(params) as i32))
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        let mut new_z = c;
+        for (k, z_k) in z.iter().enumerate() {
+            new_z += z_k.powu(k + 1);
+        }
+        new_z
+    }
+    const HAS_DERIVATIVE: bool = true;
+    #[inline(always)]
+    fn derivative<X: SimdFloat>(
+        _: &[F],
+        z: &[Complexx<X>],
+        dz: &[Complexx<X>],
+        dc: Complexx<X>,
+        _: u32,
+    ) -> Complexx<X> {
+        let mut new_dz = dc;
+        for (k, (z_k, &dz_k)) in z.iter().zip(dz).enumerate() {
+            // `powu(k + 1)` is the power 2^(k + 1).
+            let p = 1 << (k + 1);
+            new_dz += z_k.powi(p - 1) * dz_k * p as F;
+        }
+        new_dz
+    }
+}
+
+pub struct ThirdDegreeRecPairs;
+impl FractalFormula for ThirdDegreeRecPairs {
+    const NAME: &'static str = "ThirdDegreeRecPairs";
+
+    fn depth(_: &[F]) -> usize {
+        3
+    }
+    fn growth(_: &[F]) -> Growth {
+        // Products of the two last values: ln|z| grows like the fibonacci
+        // sequence.
+        Growth::Power(PHI)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[0] * z[1] + z[0] * z[2] + z[1] * z[2] + c
+    }
+    const HAS_DERIVATIVE: bool = true;
+    #[inline(always)]
+    fn derivative<X: SimdFloat>(
+        _: &[F],
+        z: &[Complexx<X>],
+        dz: &[Complexx<X>],
+        dc: Complexx<X>,
+        _: u32,
+    ) -> Complexx<X> {
+        dz[0] * (z[1] + z[2]) + dz[1] * (z[0] + z[2]) + dz[2] * (z[0] + z[1]) + dc
+    }
+}
+
+pub struct SecondDegreeThirtySevenBlend;
+impl FractalFormula for SecondDegreeThirtySevenBlend {
+    const NAME: &'static str = "SecondDegreeThirtySevenBlend";
+    const AUTONOMOUS: bool = false;
+
+    fn depth(_: &[F]) -> usize {
+        2
+    }
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, i: u32) -> Complexx<X> {
+        if i.is_multiple_of(37) {
+            z[1] * z[1] - z[0] + c
+        } else {
+            z[1] * z[1] + z[0]
+        }
+    }
+}
+
+pub struct ComplexLogisticMapLike;
+impl FractalFormula for ComplexLogisticMapLike {
+    const NAME: &'static str = "ComplexLogisticMapLike";
+    const PARAMS: &'static [Param] = &[Param::real("a_re", 1.), Param::real("a_im", 0.)];
+    const BAILOUT: F = 50.;
+
+    fn depth(_: &[F]) -> usize {
+        2
+    }
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(PHI)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(params: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[1] * (Complexx::splat(params[0], params[1]) - z[0]) + c
+    }
+}
+
+// This is where I started lacking inspiration for names...
+
+pub struct Vshqwj;
+impl FractalFormula for Vshqwj {
+    const NAME: &'static str = "Vshqwj";
+
+    fn depth(_: &[F]) -> usize {
+        3
+    }
+    fn growth(_: &[F]) -> Growth {
+        // Dominated by z2 * z2 * z1.
+        Growth::Power(SILVER_RATIO)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        (z[2] + z[1]) * (z[1] + z[0]) * (z[2] - z[0]) + c
+    }
+}
+
+pub struct Wmriho;
+impl FractalFormula for Wmriho {
+    const NAME: &'static str = "Wmriho";
+    const PARAMS: &'static [Param] = &[Param::real("a_re", 0.), Param::real("a_im", 0.)];
+    const BAILOUT: F = 10.;
+
+    fn depth(_: &[F]) -> usize {
+        3
+    }
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    fn init<X: SimdFloat>(params: &[F], z_init: Complexx<X>) -> Complexx<X> {
+        Complexx::splat(params[0], params[1]) + z_init
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[2] * z[2]
+            + z[1] * z[0]
+            + Complexx {
+                re: z[0].im,
+                im: z[0].re,
+            }
+            + c
+    }
+}
+
+pub struct Iigdzh;
+impl FractalFormula for Iigdzh {
+    const NAME: &'static str = "Iigdzh";
+    const PARAMS: &'static [Param] = &[Param::real("a_re", 0.), Param::real("a_im", 0.)];
+    const BAILOUT: F = 10.;
+
+    fn depth(_: &[F]) -> usize {
+        3
+    }
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    fn init<X: SimdFloat>(params: &[F], z_init: Complexx<X>) -> Complexx<X> {
+        Complexx::splat(params[0], params[1]) + z_init
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[2] * z[2]
+            + Complexx {
+                re: z[0].im + z[1].re,
+                im: z[2].re,
+            }
+            + c
+    }
+}
+
+pub struct Fxdicq;
+impl FractalFormula for Fxdicq {
+    const NAME: &'static str = "Fxdicq";
+    const BAILOUT: F = 10.;
+
+    fn depth(_: &[F]) -> usize {
+        3
+    }
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[2] * z[2]
+            + Complexx {
+                re: z[0].im * z[1].re,
+                im: z[2].re,
+            }
+            + c
+    }
+}
+
+pub struct Mjygzr;
+impl FractalFormula for Mjygzr {
+    const NAME: &'static str = "Mjygzr";
+    const BAILOUT: F = 5.;
+
+    fn depth(_: &[F]) -> usize {
+        2
+    }
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[1] * z[1] * c + z[0] + c
+    }
+}
+
+pub struct Zqcqvm;
+impl FractalFormula for Zqcqvm {
+    const NAME: &'static str = "Zqcqvm";
+    const BAILOUT: F = 5.;
+
+    fn depth(_: &[F]) -> usize {
+        2
+    }
+    fn growth(_: &[F]) -> Growth {
+        // Linear recurrence: |z| itself grows like the fibonacci sequence.
+        Growth::Linear(PHI)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[1] + z[0] + c
+    }
+}
+
+// Classic fractals, mostly for comparison.
+
+pub struct BurningShip;
+impl FractalFormula for BurningShip {
+    const NAME: &'static str = "BurningShip";
+
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        let z = Complexx {
+            re: z[0].re.abs(),
+            im: z[0].im.abs(),
+        };
+        z * z + c
+    }
+}
+
+/// Also known as Mandelbar.
+pub struct Tricorn;
+impl FractalFormula for Tricorn {
+    const NAME: &'static str = "Tricorn";
+
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        let z = z[0].conj();
+        z * z + c
+    }
+}
+
+pub struct Celtic;
+impl FractalFormula for Celtic {
+    const NAME: &'static str = "Celtic";
+
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        let Complexx { re, im } = z[0];
+        Complexx {
+            re: (re * re - im * im).abs(),
+            im: X::splat(2.) * re * im,
+        } + c
+    }
+}
+
+pub struct Perpendicular;
+impl FractalFormula for Perpendicular {
+    const NAME: &'static str = "Perpendicular";
+
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        // (|re| - i im)^2 + c
+        let Complexx { re, im } = z[0];
+        Complexx {
+            re: re * re - im * im,
+            im: X::splat(-2.) * re.abs() * im,
+        } + c
+    }
+}
+
+/// The classic Phoenix Julia set is at `c = 0.5667` with the default `p`.
+pub struct Phoenix;
+impl FractalFormula for Phoenix {
+    const NAME: &'static str = "Phoenix";
+    const PARAMS: &'static [Param] = &[Param::real("p_re", -0.5), Param::real("p_im", 0.)];
+
+    fn depth(_: &[F]) -> usize {
+        2
+    }
+    fn growth(_: &[F]) -> Growth {
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(params: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        z[1] * z[1] + c + Complexx::splat(params[0], params[1]) * z[0]
+    }
+    const HAS_DERIVATIVE: bool = true;
+    #[inline(always)]
+    fn derivative<X: SimdFloat>(
+        params: &[F],
+        z: &[Complexx<X>],
+        dz: &[Complexx<X>],
+        dc: Complexx<X>,
+        _: u32,
+    ) -> Complexx<X> {
+        z[1] * dz[1] * 2. + dc + Complexx::splat(params[0], params[1]) * dz[0]
+    }
+}
+
+// Magnet fractals come from models of magnetic phase transitions, orbits
+// either escape or converge to 1. Only escaping ones are counted.
+
+pub struct MagnetI;
+impl FractalFormula for MagnetI {
+    const NAME: &'static str = "MagnetI";
+    const BAILOUT: F = 100.;
+
+    fn growth(_: &[F]) -> Growth {
+        // ~ (z / 2)^2
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        let one = Complexx::splat(1., 0.);
+        let two = Complexx::splat(2., 0.);
+        let w = (z[0] * z[0] + c - one) / (z[0] * 2. + c - two);
+        w * w
+    }
+}
+
+pub struct MagnetII;
+impl FractalFormula for MagnetII {
+    const NAME: &'static str = "MagnetII";
+    const BAILOUT: F = 100.;
+
+    fn growth(_: &[F]) -> Growth {
+        // ~ (z / 3)^2
+        Growth::Power(2.)
+    }
+    #[inline(always)]
+    fn step<X: SimdFloat>(_: &[F], z: &[Complexx<X>], c: Complexx<X>, _: u32) -> Complexx<X> {
+        let one = Complexx::splat(1., 0.);
+        let two = Complexx::splat(2., 0.);
+        let c1 = c - one;
+        let c2 = c - two;
+        let z = z[0];
+        let w = (z * z * z + c1 * z * 3. + c1 * c2) / (z * z * 3. + c2 * z * 3. + c1 * c2 + one);
+        w * w
+    }
+}
+
+macro_rules! registry {
+    ($($name:ident),* $(,)?) => {
+        /// Every built-in fractal, see [`FractalFormula`].
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum FractalKind {
+            $($name),*
+        }
+
+        impl FractalKind {
+            pub const ALL: &'static [FractalKind] = &[$(FractalKind::$name),*];
+            pub const NAMES: &'static [&'static str] = &[$($name::NAME),*];
+
+            pub fn from_name(name: &str) -> Option<FractalKind> {
+                FractalKind::ALL.iter().copied().find(|kind| kind.name() == name)
+            }
+
+            /// Position in [`FractalKind::ALL`].
+            pub fn index(self) -> usize {
+                self as usize
+            }
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(FractalKind::$name => $name::NAME),*
+                }
+            }
+
+            pub fn params(self) -> &'static [Param] {
+                match self {
+                    $(FractalKind::$name => $name::PARAMS),*
+                }
+            }
+
+            pub fn unnamed_params(self) -> bool {
+                match self {
+                    $(FractalKind::$name => $name::UNNAMED_PARAMS),*
+                }
+            }
+
+            pub fn bailout(self) -> F {
+                match self {
+                    $(FractalKind::$name => $name::BAILOUT),*
+                }
+            }
+
+            pub fn growth(self, params: &[F]) -> Growth {
+                match self {
+                    $(FractalKind::$name => $name::growth(params)),*
+                }
+            }
+
+            pub fn perturbation_exponent(self, params: &[F]) -> Option<u32> {
+                match self {
+                    $(FractalKind::$name => $name::perturbation_exponent(params)),*
+                }
+            }
+
+            pub fn depth(self, params: &[F]) -> usize {
+                match self {
+                    $(FractalKind::$name => $name::depth(params)),*
+                }
+            }
+
+            pub fn has_derivative(self) -> bool {
+                match self {
+                    $(FractalKind::$name => $name::HAS_DERIVATIVE),*
+                }
+            }
+
+            pub fn is_autonomous(self) -> bool {
+                match self {
+                    $(FractalKind::$name => $name::AUTONOMOUS),*
+                }
+            }
+
+            pub fn init<X: SimdFloat>(self, params: &[F], z_init: Complexx<X>) -> Complexx<X> {
+                match self {
+                    $(FractalKind::$name => $name::init(params, z_init)),*
+                }
+            }
+
+            /// Computes a single step, `z` holds exactly
+            /// [`FractalKind::depth`] values. Prefer
+            /// [`FractalKind::iterate`] which only dispatches once.
+            #[inline(always)]
+            pub fn step<X: SimdFloat>(
+                self,
+                params: &[F],
+                z: &[Complexx<X>],
+                c: Complexx<X>,
+                i: u32,
+            ) -> Complexx<X> {
+                match self {
+                    $(FractalKind::$name => $name::step(params, z, c, i)),*
+                }
+            }
+
+            /// Derivative of a single step, see [`FractalKind::step`] and
+            /// [`FractalFormula::derivative`].
+            #[inline(always)]
+            pub fn derivative<X: SimdFloat>(
+                self,
+                params: &[F],
+                z: &[Complexx<X>],
+                dz: &[Complexx<X>],
+                dc: Complexx<X>,
+                i: u32,
+            ) -> Complexx<X> {
+                match self {
+                    $(FractalKind::$name => $name::derivative(params, z, dz, dc, i)),*
+                }
+            }
+
+            /// Runs the recurrence, see [`iterate`].
+            pub fn iterate<X: SimdFloat>(
+                self,
+                params: &[F],
+                z_init: Complexx<X>,
+                c: Complexx<X>,
+                bailout: F,
+                options: SampleOptions,
+            ) -> Orbit<X> {
+                match self {
+                    $(FractalKind::$name => iterate(
+                        $name::depth(params),
+                        $name::init(params, z_init),
+                        c,
+                        bailout,
+                        options,
+                        |z, c, i| $name::step(params, z, c, i),
+                    )),*
+                }
+            }
+
+            /// Runs the recurrence along with its derivative, see
+            /// [`iterate_with_derivative`].
+            pub fn iterate_with_derivative<X: SimdFloat>(
+                self,
+                params: &[F],
+                z_init: Complexx<X>,
+                c: Complexx<X>,
+                bailout: F,
+                options: SampleOptions,
+            ) -> Orbit<X> {
+                let (dz_init, dc) = pixel_derivatives(options);
+                match self {
+                    $(FractalKind::$name => {
+                        let depth = $name::depth(params);
+                        let mut z = vec![Complexx::zeros(); depth];
+                        let mut dz = vec![Complexx::zeros(); depth];
+                        z[depth - 1] = $name::init(params, z_init);
+                        dz[depth - 1] = dz_init;
+                        iterate_with_derivative(
+                            z,
+                            dz,
+                            c,
+                            dc,
+                            bailout,
+                            options,
+                            |z, c, i| $name::step(params, z, c, i),
+                            |z, dz, dc, i| $name::derivative(params, z, dz, dc, i),
+                        )
+                    }),*
+                }
+            }
+
+            /// Runs the recurrence refilling lanes, see
+            /// [`iterate_refilling`].
+            #[allow(clippy::too_many_arguments)]
+            pub fn iterate_refilling<X: SimdFloat>(
+                self,
+                params: &[F],
+                fractal: &Fractal,
+                count: usize,
+                pixels: impl Fn(&[usize; MAX_LANES]) -> Complexx<X>,
+                bailout: F,
+                options: SampleOptions,
+                done: impl FnMut(usize, &Sample, usize),
+            ) {
+                match self {
+                    $(FractalKind::$name => iterate_refilling(
+                        fractal,
+                        $name::depth(params),
+                        count,
+                        pixels,
+                        bailout,
+                        options,
+                        |z, c, i| $name::step(params, z, c, i),
+                        |z, dz, dc, i| $name::derivative(params, z, dz, dc, i),
+                        done,
+                    )),*
+                }
+            }
+        }
+    };
+}
+
+registry! {
+    Mandelbrot,
+    MandelbrotCustomExp,
+    SecondDegreeRecWithGrowingExponent,
+    SecondDegreeRecWithGrowingExponentParam,
+    SecondDegreeRecAlternating1WithGrowingExponent,
+    ThirdDegreeRecWithGrowingExponent,
+    NthDegreeRecWithGrowingExponent,
+    ThirdDegreeRecPairs,
+    SecondDegreeThirtySevenBlend,
+    ComplexLogisticMapLike,
+    Vshqwj,
+    Wmriho,
+    Iigdzh,
+    Fxdicq,
+    Mjygzr,
+    Zqcqvm,
+    BurningShip,
+    Tricorn,
+    Celtic,
+    Perpendicular,
+    Phoenix,
+    MagnetI,
+    MagnetII,
+}
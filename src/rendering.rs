@@ -1,26 +1,240 @@
-use std::{array, sync::mpsc};
+use std::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use wide::{f32x8, f64x4};
 
 use crate::{
-    complexx::Complexx, fractal::Fractal, mat::Mat2D, params::FrameParams, progress::Progress,
-    sampling::map_points_with_offsets, View, F, FX,
+    complexx::Complexx,
+    density::render_orbit_density_with,
+    double_double::DoubleF64x4,
+    error::{ErrorKind, Result},
+    fractal::{Fractal, Sample, SampleOptions},
+    mat::{Mat2D, Mat3D},
+    params::FrameParams,
+    perturbation::{needs_perturbation, ReferenceOrbit},
+    progress::Progress,
+    refill::sample_chunks,
+    root_finding::degree,
+    sampling::{has_usable_weight, ADAPTIVE_FIRST_SAMPLES},
+    simd::{Precision, SimdFloat, MAX_LANES},
+    View, F,
 };
 
+/// Side of the square tiles images are rendered by.
+const TILE_SIZE: usize = 32;
+
+/// Output of [`render_raw_image`].
+pub struct RawImage {
+    /// Mean of the values of the samples of each pixel.
+    pub values: Mat2D<F>,
+    /// For convergent fractals, index of the root reached by most samples
+    /// of each pixel (see [`Fractal::roots`]).
+    pub roots: Option<Mat2D<Option<u8>>>,
+    /// For Nebulabrots, the red, green and blue channels, `values` being
+    /// their sum (see [`crate::density`]).
+    pub channels: Option<[Mat2D<F>; 3]>,
+    /// Mean of each channel of `aov_channels` over the samples of each
+    /// pixel, the depth being the index of the channel. Not rendered for
+    /// orbit densities.
+    pub aovs: Option<Mat3D<F>>,
+    /// Every [`Channel`], except [`Channel::Distance`] when the coloring
+    /// mode doesn't need it (see
+    /// [`crate::coloring::ColoringMode::needs_distance`]) since tracking
+    /// derivatives is expensive.
+    pub aov_channels: Vec<Channel>,
+    /// Number of samples of the pixels of the image.
+    pub samples: usize,
+}
+
+impl RawImage {
+    /// Copy of the given channel, [`RawImage::values`] when `None`. Missing
+    /// channels are filled with zeros.
+    pub fn channel(&self, channel: Option<Channel>) -> Mat2D<F> {
+        let Some(channel) = channel else {
+            return self.values.clone();
+        };
+        let layer = self.aov_channels.iter().position(|&c| c == channel);
+
+        match (layer, &self.aovs) {
+            (Some(layer), Some(aovs)) => aovs.layer(layer),
+            _ => Mat2D::filled_with(0., self.values.width, self.values.height),
+        }
+    }
+
+    /// Whether the given channel was rendered, so that the image can be
+    /// colored from it without rendering it again.
+    pub fn has_channel(&self, channel: Option<Channel>) -> bool {
+        channel.is_none_or(|channel| self.aovs.is_some() && self.aov_channels.contains(&channel))
+    }
+}
+
+/// Quantities rendered alongside [`RawImage::values`] (arbitrary output
+/// variables), so that the same render can be colored in several ways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Channel {
+    /// Integer iteration count.
+    Iteration,
+    /// Normalized iteration count, as with `smooth`.
+    SmoothIteration,
+    /// `|z|` for the last value of the orbit.
+    Modulus,
+    /// `arg(z)` for the last value of the orbit, between -π and π.
+    Argument,
+    /// 1 for orbits that stopped before `max_iter` (escaped or converged),
+    /// 0 for the other ones.
+    Escaped,
+    /// Estimated distance to the boundary of the set, see
+    /// [`crate::coloring::ColoringMode::DistanceEstimate`]. Zero for fractals without a
+    /// derivative.
+    Distance,
+    /// Period of the cycle interior orbits were found to reach (see
+    /// [`crate::fractal::SampleOptions::check_periodicity`]), 0 when none
+    /// was detected.
+    Period,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 7] = [
+        Channel::Iteration,
+        Channel::SmoothIteration,
+        Channel::Modulus,
+        Channel::Argument,
+        Channel::Escaped,
+        Channel::Distance,
+        Channel::Period,
+    ];
+
+    fn value(self, sample: &Sample, lane: usize, max_iter: u32) -> F {
+        match (self, &sample.escape, sample.distance) {
+            (Channel::Iteration, Some(escape), _) => escape.iter[lane],
+            (Channel::SmoothIteration, Some(escape), _) => escape.smooth_iter[lane],
+            (Channel::Modulus, Some(escape), _) => escape.modulus[lane],
+            (Channel::Argument, Some(escape), _) => escape.argument[lane],
+            (Channel::Escaped, Some(escape), _) if escape.iter[lane] < max_iter as F => 1.,
+            (Channel::Distance, _, Some(distance)) => distance[lane],
+            (Channel::Period, Some(escape), _) => escape.period[lane],
+            _ => 0.,
+        }
+    }
+}
+
+/// Part of a [`RawImage`] being rendered, see
+/// [`render_raw_image_progressive`].
+pub struct Tile<'a> {
+    /// Position of the top left pixel of the tile in the image.
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Rows of the tile in [`RawImage::values`].
+    pub values: Vec<&'a mut [F]>,
+    /// Rows of the tile in [`RawImage::roots`].
+    pub roots: Option<Vec<&'a mut [Option<u8>]>>,
+    /// Rows of the tile in each layer of [`RawImage::aovs`].
+    pub aovs: Vec<Vec<&'a mut [F]>>,
+}
+
 pub fn render_raw_image(
     params: &FrameParams,
     view: &View,
     sampling_points: &[(F, F)],
     progress: Option<Progress>,
-) -> Mat2D<F> {
+) -> Result<RawImage> {
+    render_raw_image_progressive(params, view, sampling_points, progress, |_| ())
+}
+
+/// Same as [`render_raw_image`], calling `on_tile` from the rendering
+/// threads with each tile once its pixels are known, for progressive
+/// display. Orbit densities (see [`crate::density`]) are only known once
+/// the whole image is rendered, `on_tile` is not called for them.
+pub fn render_raw_image_progressive(
+    params: &FrameParams,
+    view: &View,
+    sampling_points: &[(F, F)],
+    progress: Option<Progress>,
+    on_tile: impl Fn(&Tile) + Sync,
+) -> Result<RawImage> {
+    let pixel_size = view.pixel_size(params.img_width);
+    let precision = params.precision.unwrap_or_default().resolve(
+        &params.fractal,
+        (view.cx, view.cy),
+        pixel_size,
+    );
+
+    if params.coloring_mode.needs_distance()
+        && (!params.fractal.has_derivative() || params.orbit_density.is_some())
+    {
+        return Err(ErrorKind::NoDistanceEstimate);
+    }
+
+    if params.orbit_trap.is_some() && !params.fractal.has_orbits() {
+        return Err(ErrorKind::NoOrbits);
+    }
+
+    if params.orbit_average.is_some()
+        && (!params.fractal.is_escape_time() || params.orbit_density.is_some())
+    {
+        return Err(ErrorKind::NoOrbitAverage);
+    }
+
+    if let Fractal::RootFinding { coefs, .. } = &params.fractal {
+        if degree(coefs) == 0 {
+            return Err(ErrorKind::ConstantPolynomial);
+        }
+    }
+
+    if let Some(density) = &params.orbit_density {
+        return match precision {
+            Precision::F32 => render_orbit_density_with::<f32x8>(params, view, density, progress),
+            Precision::Auto | Precision::F64 => {
+                render_orbit_density_with::<f64x4>(params, view, density, progress)
+            }
+            Precision::DoubleDouble => {
+                render_orbit_density_with::<DoubleF64x4>(params, view, density, progress)
+            }
+        };
+    }
+
+    match precision {
+        Precision::F32 => {
+            render_raw_image_with::<f32x8>(params, view, sampling_points, progress, &on_tile)
+        }
+        Precision::Auto | Precision::F64 => {
+            render_raw_image_with::<f64x4>(params, view, sampling_points, progress, &on_tile)
+        }
+        Precision::DoubleDouble => {
+            render_raw_image_with::<DoubleF64x4>(params, view, sampling_points, progress, &on_tile)
+        }
+    }
+}
+
+fn render_raw_image_with<X: SimdFloat>(
+    params: &FrameParams,
+    view: &View,
+    sampling_points: &[(F, F)],
+    progress: Option<Progress>,
+    on_tile: &(dyn Fn(&Tile) + Sync),
+) -> Result<RawImage> {
     let &FrameParams {
         img_width,
         img_height,
 
-        fractal,
+        ref fractal,
+        julia,
 
         max_iter,
+        bailout,
+        escape_metric,
+        smooth,
+        orbit_trap,
+        orbit_average,
+        check_periodicity,
 
+        coloring_mode,
         sampling,
         ..
     } = params;
@@ -39,76 +253,392 @@ pub fn render_raw_image(
         cy = 0.;
     }
 
-    let mut raw_image = Mat2D::filled_with(0., img_width as usize, img_height as usize);
-
-    let rng = fastrand::Rng::new();
-    let (tx, rx) = mpsc::channel();
-    (0..img_height)
-        .flat_map(|j| (0..img_width).map(move |i| (i, j)))
-        .par_bridge()
-        .for_each_with((tx, rng), |(s, rng), (i, j)| {
-            let x = i as F;
-            let y = j as F;
-
-            let (offset_x, offset_y) = if sampling.random_offsets {
-                #[cfg(feature = "force_f32")]
-                let v = (rng.f32(), rng.f32());
-                #[cfg(not(feature = "force_f32"))]
-                let v = (rng.f64(), rng.f64());
-
-                v
-            } else {
-                (0., 0.)
+    let formula = fractal.compile_formula()?;
+    let roots = fractal.roots();
+
+    let options = SampleOptions {
+        max_iter,
+        smooth: smooth.unwrap_or(false),
+        julia,
+        bailout,
+        escape_metric: escape_metric.unwrap_or_default(),
+        formula: formula.as_ref(),
+        roots: &roots,
+        distance_estimation: coloring_mode.needs_distance(),
+        orbit_trap,
+        orbit_average,
+        check_periodicity: check_periodicity.unwrap_or(false),
+    };
+
+    // Switch to perturbation when `X` is not precise enough anymore.
+    let pixel_size = view.pixel_size(img_width);
+    let reference_orbit = if needs_perturbation::<X>((cx, cy), pixel_size) {
+        ReferenceOrbit::new(fractal, (cx, cy), pixel_size, options)
+    } else {
+        None
+    };
+
+    let mut values = Mat2D::filled_with(0., img_width as usize, img_height as usize);
+    let mut pixel_roots = (!roots.is_empty())
+        .then(|| Mat2D::filled_with(None, img_width as usize, img_height as usize));
+    let aov_channels: Vec<_> = Channel::ALL
+        .into_iter()
+        .filter(|&channel| channel != Channel::Distance || coloring_mode.needs_distance())
+        .collect();
+    let mut aovs = Mat3D::filled_with(
+        0.,
+        img_width as usize,
+        img_height as usize,
+        aov_channels.len(),
+    );
+
+    let value_tiles = values.tiles_mut(TILE_SIZE);
+    let root_tiles: Vec<_> = match &mut pixel_roots {
+        Some(pixel_roots) => pixel_roots
+            .tiles_mut(TILE_SIZE)
+            .into_iter()
+            .map(Some)
+            .collect(),
+        None => value_tiles.iter().map(|_| None).collect(),
+    };
+    let tiles_x = (img_width as usize).div_ceil(TILE_SIZE);
+    let tiles: Vec<Tile> = value_tiles
+        .into_iter()
+        .zip(root_tiles)
+        .zip(aovs.tiles_mut(TILE_SIZE))
+        .enumerate()
+        .map(|(k, ((values, roots), aovs))| Tile {
+            x: k % tiles_x * TILE_SIZE,
+            y: k / tiles_x * TILE_SIZE,
+            width: values[0].len(),
+            height: values.len(),
+            values,
+            roots,
+            aovs,
+        })
+        .collect();
+
+    // With adaptive sampling, `sampling_points` are the points of the
+    // pixels that get the most samples.
+    let max_samples = sampling_points.len();
+    let first_samples = match sampling.level.adaptive_threshold() {
+        Some(_) => max_samples.min(ADAPTIVE_FIRST_SAMPLES),
+        None => max_samples,
+    };
+    let sample_count = AtomicUsize::new(0);
+
+    // Offset from the center of the view of a point of the image (in
+    // pixels).
+    let view_offset = |x: F, y: F| {
+        (
+            0.5 * width * (x / img_width as F - 0.5),
+            0.5 * height * (y / img_height as F - 0.5),
+        )
+    };
+    let sample_offsets = |offsets: &[(F, F)], done: &mut dyn FnMut(usize, &Sample, usize)| {
+        let offset = |indices: &[usize; MAX_LANES]| {
+            let re = X::from_fn(|lane| offsets[indices[lane]].0);
+            let im = X::from_fn(|lane| offsets[indices[lane]].1);
+            Complexx { re, im } * Complexx::from_polar_splat(1., rotate)
+        };
+
+        match &reference_orbit {
+            Some(reference_orbit) => sample_chunks(
+                offsets.len(),
+                offset,
+                |offset| reference_orbit.sample(fractal, offset, options),
+                done,
+            ),
+            None => fractal.sample_points(
+                offsets.len(),
+                |indices| offset(indices) + Complexx::splat(cx, cy),
+                options,
+                done,
+            ),
+        }
+    };
+
+    // With adaptive sampling, the range of the values of the image, from a
+    // sample at the center of each pixel, to tell which pixels are
+    // uncertain.
+    let value_range = sampling.level.adaptive_threshold().map(|_| {
+        let (min, max) = (0..img_height)
+            .into_par_iter()
+            .map(|y| {
+                let offsets: Vec<_> = (0..img_width)
+                    .map(|x| view_offset(x as F + 0.5, y as F + 0.5))
+                    .collect();
+                let (mut min, mut max) = (F::INFINITY, F::NEG_INFINITY);
+                sample_offsets(&offsets, &mut |_, sample, lane| {
+                    let value = sample.value[lane];
+                    if value.is_finite() {
+                        (min, max) = (min.min(value), max.max(value));
+                    }
+                });
+                (min, max)
+            })
+            .reduce(
+                || (F::INFINITY, F::NEG_INFINITY),
+                |(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)),
+            );
+        max - min
+    });
+    // Pixels around each tile also get the first pass (but are not
+    // written) with adaptive sampling, so that uncertain pixels spread to
+    // the neighboring tiles.
+    let apron = if value_range.is_some() { 1 } else { 0 };
+
+    // Tiles are sampled as a whole so that lanes whose orbit ended pick up
+    // the next samples of the tile (see `Fractal::sample_points`).
+    tiles.into_par_iter().for_each_init(
+        || (fastrand::Rng::new(), TileBuffers::default()),
+        |(rng, buffers), mut tile| {
+            let TileBuffers {
+                pixel_offsets,
+                pixel_sums,
+                pixel_channels,
+                root_weights,
+                uncertain,
+                passes,
+                offsets,
+                sample_weights,
+                sample_values,
+                sample_channels,
+                sample_roots,
+            } = buffers;
+
+            // Pixels sampled for the tile.
+            let x0 = tile.x.saturating_sub(apron);
+            let y0 = tile.y.saturating_sub(apron);
+            let area_width = (tile.x + tile.width + apron).min(img_width as usize) - x0;
+            let area_height = (tile.y + tile.height + apron).min(img_height as usize) - y0;
+            let in_tile = |pixel: usize| {
+                let (x, y) = (x0 + pixel % area_width, y0 + pixel / area_width);
+                (tile.x..tile.x + tile.width).contains(&x)
+                    && (tile.y..tile.y + tile.height).contains(&y)
             };
-            let sampling_points = sampling_points
-                .iter()
-                .map(|&(dx, dy)| map_points_with_offsets(dx, dy, offset_x, offset_y))
-                .collect::<Vec<_>>();
-
-            #[cfg(feature = "force_f32")]
-            const CHUNK_SIZE: usize = 8;
-            #[cfg(not(feature = "force_f32"))]
-            const CHUNK_SIZE: usize = 4;
-            let value = sampling_points
-                .chunks(CHUNK_SIZE)
-                .flat_map(|d| {
-                    let l = d.len();
-                    let re = FX::from(array::from_fn(|i| {
-                        // Here we use `i % l` to avoid out of bounds error (when i < 4).
-                        // When `i < 4`, the modulo operation will repeat the sample
-                        // but as we use simd this is acceptable (the cost is the
-                        // same whether it is computed along with the others or not).
-                        let (dx, _) = d[i % l];
-                        cx + 0.5 * width * ((x + 0.5 + dx) / img_width as F - 0.5)
-                    }));
-                    let im = FX::from(array::from_fn(|i| {
-                        let (_, dy) = d[i % l];
-                        cy + 0.5 * height * ((y + 0.5 + dy) / img_height as F - 0.5)
-                    }));
-
-                    let iter = {
-                        let c = Complexx::splat(cx, cy);
-                        fractal.sample(
-                            (Complexx { re, im } - c) * Complexx::from_polar_splat(1., rotate) + c,
-                            max_iter,
-                        )
-                    };
-
-                    (0..l).map(move |i| iter[i])
-                })
-                .sum::<F>()
-                / sampling_points.len() as F;
-
-            s.send(((i, j), value)).unwrap();
+
+            let pixel_count = area_width * area_height;
+            pixel_offsets.clear();
+            pixel_offsets.extend((0..pixel_count).map(|_| {
+                if sampling.random_offsets {
+                    (rng.f64(), rng.f64())
+                } else {
+                    (0., 0.)
+                }
+            }));
+            pixel_sums.clear();
+            pixel_sums.resize(pixel_count, PixelSums::default());
+            pixel_channels.clear();
+            pixel_channels.resize(pixel_count * aov_channels.len(), (0., 0.));
+            root_weights.clear();
+            root_weights.resize(pixel_count * roots.len(), (0., 0));
+
+            // Pixels and sampling points of the samples of the pass.
+            passes.clear();
+            passes.extend((0..pixel_count).map(|pixel| (pixel, 0..first_samples)));
+
+            while !passes.is_empty() {
+                // Offsets from the center of the view of the samples.
+                offsets.clear();
+                sample_weights.clear();
+                for (pixel, points) in passes.iter() {
+                    let x = (x0 + pixel % area_width) as F;
+                    let y = (y0 + pixel / area_width) as F;
+                    for &point in &sampling_points[points.clone()] {
+                        let (dx, dy, weight) = sampling.map_point(point, pixel_offsets[*pixel]);
+                        offsets.push(view_offset(x + 0.5 + dx, y + 0.5 + dy));
+                        sample_weights.push(weight);
+                    }
+                }
+
+                // Value, channels and root of each sample.
+                sample_values.clear();
+                sample_values.resize(offsets.len(), 0.);
+                sample_channels.clear();
+                sample_channels.resize(offsets.len() * aov_channels.len(), 0.);
+                sample_roots.clear();
+                sample_roots.resize(offsets.len(), None);
+                let mut done = |k: usize, sample: &Sample, lane: usize| {
+                    sample_values[k] = sample.value[lane];
+                    for (c, &channel) in aov_channels.iter().enumerate() {
+                        sample_channels[k * aov_channels.len() + c] =
+                            channel.value(sample, lane, max_iter);
+                    }
+                    sample_roots[k] = sample.root[lane];
+                };
+
+                sample_offsets(offsets, &mut done);
+
+                let mut k = 0;
+                for (pixel, points) in passes.iter() {
+                    let sums = &mut pixel_sums[*pixel];
+                    for _ in points.clone() {
+                        let (weight, value) = (sample_weights[k], sample_values[k]);
+                        sums.samples += 1;
+                        sums.weight += weight;
+                        sums.abs_weight += weight.abs();
+                        sums.value += weight * value;
+                        sums.plain_value += value;
+                        sums.plain_value_sqr += value * value;
+                        let channels = k * aov_channels.len()..(k + 1) * aov_channels.len();
+                        for ((channel, plain_channel), &sample_channel) in pixel_channels
+                            [*pixel * aov_channels.len()..(*pixel + 1) * aov_channels.len()]
+                            .iter_mut()
+                            .zip(&sample_channels[channels])
+                        {
+                            *channel += weight * sample_channel;
+                            *plain_channel += sample_channel;
+                        }
+                        if let Some(root) = sample_roots[k] {
+                            let (root_weight, root_samples) =
+                                &mut root_weights[*pixel * roots.len() + root as usize];
+                            *root_weight += weight;
+                            *root_samples += 1;
+                        }
+                        k += 1;
+                    }
+                }
+
+                let (Some(threshold), Some(value_range)) =
+                    (sampling.level.adaptive_threshold(), value_range)
+                else {
+                    break;
+                };
+
+                // Pixels whose value is still uncertain, as well as their
+                // neighbors (their samples may have missed details that
+                // show up in the neighbors), get as many new samples as
+                // they already have. Pixels around the tile are not
+                // refined, they only tell whether they are uncertain after
+                // the first pass.
+                uncertain.clear();
+                uncertain.extend(
+                    pixel_sums
+                        .iter()
+                        .map(|sums| sums.standard_error() > threshold * value_range),
+                );
+                passes.clear();
+                passes.extend(pixel_sums.iter().enumerate().filter_map(|(pixel, sums)| {
+                    let (i, j) = (pixel % area_width, pixel / area_width);
+                    let near_uncertain = uncertain[pixel]
+                        || (i > 0 && uncertain[pixel - 1])
+                        || (i + 1 < area_width && uncertain[pixel + 1])
+                        || (j > 0 && uncertain[pixel - area_width])
+                        || (j + 1 < area_height && uncertain[pixel + area_width]);
+                    (in_tile(pixel) && sums.samples < max_samples && near_uncertain)
+                        .then(|| (pixel, sums.samples..max_samples.min(2 * sums.samples)))
+                }));
+            }
+
+            for (pixel, sums) in pixel_sums.iter().enumerate() {
+                if !in_tile(pixel) {
+                    continue;
+                }
+                sample_count.fetch_add(sums.samples, Ordering::Relaxed);
+                let (i, j) = (
+                    x0 + pixel % area_width - tile.x,
+                    y0 + pixel / area_width - tile.y,
+                );
+
+                tile.values[j][i] = sums.mean();
+                for (aov, &(channel, plain_channel)) in tile.aovs.iter_mut().zip(
+                    &pixel_channels[pixel * aov_channels.len()..(pixel + 1) * aov_channels.len()],
+                ) {
+                    aov[j][i] = sums.mean_of(channel, plain_channel);
+                }
+                if let Some(tile_roots) = &mut tile.roots {
+                    // The root reached by the most samples, by weight when
+                    // the weights of the pixel can be used.
+                    let weighted = sums.has_usable_weight();
+                    tile_roots[j][i] = root_weights[pixel * roots.len()..(pixel + 1) * roots.len()]
+                        .iter()
+                        .map(|&(weight, samples)| if weighted { weight } else { samples as F })
+                        .enumerate()
+                        .filter(|&(_, weight)| weight > 0.)
+                        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                        .map(|(root, _)| root as u8);
+                }
+            }
+
+            on_tile(&tile);
 
             if let Some(progress) = &progress {
-                progress.incr();
+                progress.add(tile.width * tile.height);
             }
-        });
+        },
+    );
+
+    Ok(RawImage {
+        values,
+        roots: pixel_roots,
+        channels: None,
+        aovs: Some(aovs),
+        aov_channels,
+        samples: sample_count.into_inner(),
+    })
+}
 
-    for ((i, j), sample) in rx {
-        raw_image[(i as usize, j as usize)] = sample;
+/// Sums over the samples of a pixel, weighted by the reconstruction
+/// filter (see [`crate::sampling::Sampling::map_point`]), and plain sums
+/// for when the weights can't be used (see [`has_usable_weight`]).
+#[derive(Debug, Clone, Copy, Default)]
+struct PixelSums {
+    samples: usize,
+    weight: F,
+    abs_weight: F,
+    value: F,
+    plain_value: F,
+    /// Sum of the squares of the values, for their variance.
+    plain_value_sqr: F,
+}
+
+impl PixelSums {
+    fn has_usable_weight(&self) -> bool {
+        has_usable_weight(self.weight, self.abs_weight)
     }
 
-    raw_image
+    fn mean(&self) -> F {
+        self.mean_of(self.value, self.plain_value)
+    }
+
+    /// Mean of a quantity given its weighted and plain sums over the
+    /// samples of the pixel.
+    fn mean_of(&self, sum: F, plain_sum: F) -> F {
+        if self.has_usable_weight() {
+            sum / self.weight
+        } else {
+            plain_sum / self.samples as F
+        }
+    }
+
+    /// Standard error of the mean of the values, ignoring the weights of
+    /// the samples.
+    fn standard_error(&self) -> F {
+        let samples = self.samples as F;
+        let mean = self.plain_value / samples;
+        let variance = (self.plain_value_sqr / samples - mean * mean).max(0.);
+        (variance / samples).sqrt()
+    }
+}
+
+/// Scratch buffers reused by the tiles rendered in the same thread.
+#[derive(Default)]
+struct TileBuffers {
+    /// Random offsets of the sampling points of each pixel.
+    pixel_offsets: Vec<(F, F)>,
+    pixel_sums: Vec<PixelSums>,
+    /// Weighted and plain sums of the channels of each pixel.
+    pixel_channels: Vec<(F, F)>,
+    /// Weight and number of the samples of each pixel that reached each
+    /// root.
+    root_weights: Vec<(F, usize)>,
+    uncertain: Vec<bool>,
+    passes: Vec<(usize, Range<usize>)>,
+    offsets: Vec<(F, F)>,
+    sample_weights: Vec<F>,
+    sample_values: Vec<F>,
+    /// Channels of each sample, one after the other.
+    sample_channels: Vec<F>,
+    sample_roots: Vec<Option<u8>>,
 }
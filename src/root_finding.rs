@@ -0,0 +1,236 @@
+//! Convergent fractals (see [`Fractal::RootFinding`]): the orbits are
+//! those of a root-finding method applied to a polynomial, and pixels
+//! are iterated until they stop moving instead of until they escape.
+//!
+//! [`Fractal::RootFinding`]: crate::fractal::Fractal::RootFinding
+
+use serde::{Deserialize, Serialize};
+
+use crate::{complexx::Complexx, simd::SimdFloat, F};
+
+/// Orbits are considered converged when a step is shorter than this.
+pub const CONVERGENCE_EPSILON: F = 1e-5;
+
+/// Maximum distance between a converged orbit and the root it is
+/// attributed to.
+const ROOT_TOLERANCE: F = 1e-3;
+
+/// Highest degree of the polynomials, so that roots can be indexed by a
+/// `u8` (see [`root_index`]).
+pub const MAX_DEGREE: usize = u8::MAX as usize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RootMethod {
+    /// `z_{n+1} = z_n - R p(z_n) / p'(z_n)`, the pixel is `z_0`.
+    #[default]
+    Newton,
+    /// `z_{n+1} = z_n - R p(z_n) / p'(z_n) + c`, starting at `z_0 = 1`
+    /// (the pixel in Julia mode).
+    Nova,
+    /// `z_{n+1} = z_n - R 2 p p' / (2 p'^2 - p p'')`, the pixel is `z_0`.
+    Halley,
+}
+
+/// A polynomial with coefficients splatted for `X`, lowest degree first.
+pub struct Polynomial<X: SimdFloat> {
+    coefs: Vec<Complexx<X>>,
+}
+
+impl<X: SimdFloat> Polynomial<X> {
+    pub fn new(coefs: &[(F, F)]) -> Polynomial<X> {
+        Polynomial {
+            coefs: coefs
+                .iter()
+                .map(|&(re, im)| Complexx::splat(re, im))
+                .collect(),
+        }
+    }
+
+    /// Computes `(p(z), p'(z), p''(z))` using Horner's method.
+    #[inline(always)]
+    fn eval(&self, z: Complexx<X>) -> (Complexx<X>, Complexx<X>, Complexx<X>) {
+        let mut p = Complexx::zeros();
+        let mut dp = Complexx::zeros();
+        let mut ddp = Complexx::zeros();
+        for &a in self.coefs.iter().rev() {
+            ddp = ddp * z + dp * 2.;
+            dp = dp * z + p;
+            p = p * z + a;
+        }
+        (p, dp, ddp)
+    }
+
+    /// Step of the given method, `relaxation` is `R`.
+    #[inline(always)]
+    pub fn step(
+        &self,
+        method: RootMethod,
+        relaxation: F,
+        z: Complexx<X>,
+        c: Complexx<X>,
+    ) -> Complexx<X> {
+        let (p, dp, ddp) = self.eval(z);
+        match method {
+            RootMethod::Newton => z - p / dp * relaxation,
+            RootMethod::Nova => z - p / dp * relaxation + c,
+            RootMethod::Halley => z - p * dp * (2. * relaxation) / (dp * dp * 2. - p * ddp),
+        }
+    }
+}
+
+/// Iterates `step` until every lane converges or `max_iter` is reached.
+///
+/// Returns the iteration counts (`max_iter` for lanes that didn't
+/// converge) and the last values of the orbits.
+#[inline(always)]
+pub fn converge<X: SimdFloat>(
+    mut z: Complexx<X>,
+    max_iter: u32,
+    mut step: impl FnMut(Complexx<X>) -> Complexx<X>,
+) -> (X, Complexx<X>) {
+    let one = X::splat(1.0);
+    let zero = X::splat(0.0);
+
+    let epsilon = X::splat(CONVERGENCE_EPSILON * CONVERGENCE_EPSILON);
+
+    // All bits set for the lanes that haven't converged yet, zero for
+    // the other ones.
+    let mut moving = zero.cmp_le(zero);
+    let mut iter = zero;
+    for _ in 0..max_iter {
+        if !moving.any() {
+            break;
+        }
+
+        let new_z = step(z);
+        iter += moving.blend(one, zero);
+        moving = moving.blend(epsilon.cmp_lt((new_z - z).norm_sqr()), zero);
+        z = Complexx::select(moving, new_z, z);
+    }
+
+    (iter, z)
+}
+
+/// Index of the root of `roots` closest to `z`, if close enough.
+pub fn root_index(z: (F, F), roots: &[(F, F)]) -> Option<u8> {
+    roots
+        .iter()
+        .map(|&(re, im)| (z.0 - re).powi(2) + (z.1 - im).powi(2))
+        .enumerate()
+        .filter(|&(_, d)| d < ROOT_TOLERANCE * ROOT_TOLERANCE)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(k, _)| k as u8)
+}
+
+/// Degree of the polynomial with the given coefficients (lowest degree
+/// first), zero for constant polynomials.
+pub fn degree(coefs: &[(F, F)]) -> usize {
+    coefs
+        .iter()
+        .rposition(|&(re, im)| re != 0. || im != 0.)
+        .unwrap_or(0)
+}
+
+/// Roots of the polynomial with the given coefficients (lowest degree
+/// first), computed using the Durand-Kerner method.
+pub fn polynomial_roots(coefs: &[(F, F)]) -> Vec<(F, F)> {
+    fn mul((a, b): (F, F), (c, d): (F, F)) -> (F, F) {
+        (a * c - b * d, a * d + b * c)
+    }
+    fn div((a, b): (F, F), (c, d): (F, F)) -> (F, F) {
+        let n = c * c + d * d;
+        ((a * c + b * d) / n, (b * c - a * d) / n)
+    }
+
+    let degree = match degree(coefs) {
+        0 => return Vec::new(),
+        degree => degree,
+    };
+    let lead = coefs[degree];
+    let coefs: Vec<(F, F)> = coefs[..=degree].iter().map(|&a| div(a, lead)).collect();
+    let eval = |z| {
+        coefs.iter().rev().fold((0., 0.), |acc, &(re, im)| {
+            let (p_re, p_im) = mul(acc, z);
+            (p_re + re, p_im + im)
+        })
+    };
+
+    // Usual starting points, powers of a number that is neither real nor
+    // a root of unity.
+    let mut roots: Vec<(F, F)> = (0..degree)
+        .scan((1., 0.), |z, _| {
+            *z = mul(*z, (0.4, 0.9));
+            Some(*z)
+        })
+        .collect();
+    for _ in 0..1000 {
+        let mut max_change: F = 0.;
+        for k in 0..degree {
+            let denominator = (0..degree).filter(|&j| j != k).fold((1., 0.), |acc, j| {
+                mul(acc, (roots[k].0 - roots[j].0, roots[k].1 - roots[j].1))
+            });
+            let (d_re, d_im) = div(eval(roots[k]), denominator);
+            roots[k] = (roots[k].0 - d_re, roots[k].1 - d_im);
+            max_change = max_change.max(d_re.abs() + d_im.abs());
+        }
+        if max_change < 1e-15 {
+            break;
+        }
+    }
+
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(coefs: &[(F, F)], expected: &[(F, F)]) {
+        let roots = polynomial_roots(coefs);
+        assert_eq!(roots.len(), expected.len(), "{:?}", roots);
+        for &(re, im) in expected {
+            assert!(
+                roots
+                    .iter()
+                    .any(|r| (r.0 - re).abs() < 1e-10 && (r.1 - im).abs() < 1e-10),
+                "{:?} not in {:?}",
+                (re, im),
+                roots
+            );
+        }
+    }
+
+    #[test]
+    fn real_coefficients() {
+        assert_roots(&[(-1., 0.), (0., 0.), (1., 0.)], &[(1., 0.), (-1., 0.)]);
+        let s = 0.75f64.sqrt();
+        assert_roots(
+            &[(-1., 0.), (0., 0.), (0., 0.), (1., 0.)],
+            &[(1., 0.), (-0.5, s), (-0.5, -s)],
+        );
+        // 2z^2 + 2 = 0, the leading coefficient doesn't have to be 1.
+        assert_roots(&[(2., 0.), (0., 0.), (2., 0.)], &[(0., 1.), (0., -1.)]);
+    }
+
+    #[test]
+    fn complex_coefficients() {
+        // (z - i)(z - 2) = z^2 - (2 + i)z + 2i
+        assert_roots(&[(0., 2.), (-2., -1.), (1., 0.)], &[(0., 1.), (2., 0.)]);
+    }
+
+    #[test]
+    fn degrees() {
+        assert_eq!(degree(&[]), 0);
+        assert_eq!(degree(&[(0., 0.), (0., 0.)]), 0);
+        assert_eq!(degree(&[(1., 0.), (0., 2.), (0., 0.)]), 1);
+    }
+
+    #[test]
+    fn degenerate() {
+        assert_roots(&[], &[]);
+        assert_roots(&[(3., 0.)], &[]);
+        assert_roots(&[(3., 0.), (0., 0.)], &[]);
+        // Trailing zero coefficients don't add roots.
+        assert_roots(&[(-4., 0.), (2., 0.), (0., 0.)], &[(2., 0.)]);
+    }
+}
@@ -6,13 +6,168 @@ use crate::{
     F,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Number of samples every pixel gets in the first pass of
+/// [`SamplingLevel::Adaptive`].
+pub const ADAPTIVE_FIRST_SAMPLES: usize = 8;
+
+/// Pixels whose sum of sample weights is below this fraction of the sum of
+/// their absolute values get the plain mean of their samples instead (see
+/// [`has_usable_weight`]).
+const MIN_WEIGHT_RATIO: F = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Sampling {
     pub level: SamplingLevel,
     pub random_offsets: bool,
+    /// Filter weighting the samples of each pixel. When not set, samples
+    /// are spread around the pixel by [`map_points_with_offsets`] and
+    /// pixels get their plain mean.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<ReconstructionFilter>,
+}
+
+impl Sampling {
+    /// Position of a sampling point relative to the center of its pixel
+    /// (in pixels) and its weight in the value of the pixel. `offset` is
+    /// the random offset of the pixel.
+    pub fn map_point(&self, (x, y): (F, F), (offset_x, offset_y): (F, F)) -> (F, F, F) {
+        match self.filter {
+            Some(filter) => {
+                // Samples cover the support of the filter evenly.
+                let radius = filter.radius();
+                let dx = radius * (2. * ((x + offset_x) % 1.) - 1.);
+                let dy = radius * (2. * ((y + offset_y) % 1.) - 1.);
+                (dx, dy, filter.weight(dx) * filter.weight(dy))
+            }
+            None => {
+                let (dx, dy) = map_points_with_offsets(x, y, offset_x, offset_y);
+                (dx, dy, 1.)
+            }
+        }
+    }
+}
+
+/// Whether the weighted mean of the samples of a pixel can be used, given
+/// the sum of their weights and of the absolute values of their weights.
+/// With negative lobes, the weights of a few samples can cancel out and
+/// the weighted mean blows up, the plain mean of the samples is used
+/// instead.
+pub fn has_usable_weight(weight: F, abs_weight: F) -> bool {
+    weight > MIN_WEIGHT_RATIO * abs_weight
+}
+
+/// Reconstruction filters, giving the weight of samples according to
+/// their distance to the center of their pixel along each axis (in
+/// pixels). Filters with negative lobes (Mitchell–Netravali and Lanczos)
+/// give sharper images but need more samples.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "ReconstructionFilterFields")]
+pub enum ReconstructionFilter {
+    /// Plain mean of the samples inside the pixel.
+    Box,
+    /// `1 - |x|`, over the neighboring pixels.
+    Tent,
+    /// `exp(-x² / (2 sigma²))`, cut off at `3 sigma`.
+    Gaussian { sigma: F },
+    /// Mitchell–Netravali cubic with `B = C = 1/3`, over two pixels on
+    /// each side.
+    MitchellNetravali,
+    /// `sinc(x) sinc(x / radius)`, over `radius` pixels on each side.
+    Lanczos { radius: F },
+}
+
+impl ReconstructionFilter {
+    /// Distance from the center of the pixel beyond which the weight is
+    /// zero.
+    pub fn radius(&self) -> F {
+        match *self {
+            ReconstructionFilter::Box => 0.5,
+            ReconstructionFilter::Tent => 1.,
+            ReconstructionFilter::Gaussian { sigma } => 3. * sigma,
+            ReconstructionFilter::MitchellNetravali => 2.,
+            ReconstructionFilter::Lanczos { radius } => radius,
+        }
+    }
+
+    pub fn weight(&self, x: F) -> F {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.;
+        }
+
+        match *self {
+            ReconstructionFilter::Box => 1.,
+            ReconstructionFilter::Tent => 1. - x,
+            ReconstructionFilter::Gaussian { sigma } => (-x * x / (2. * sigma * sigma)).exp(),
+            ReconstructionFilter::MitchellNetravali => {
+                const B: F = 1. / 3.;
+                const C: F = 1. / 3.;
+
+                let (x2, x3) = (x * x, x * x * x);
+                if x < 1. {
+                    ((12. - 9. * B - 6. * C) * x3 + (-18. + 12. * B + 6. * C) * x2 + (6. - 2. * B))
+                        / 6.
+                } else {
+                    ((-B - 6. * C) * x3
+                        + (6. * B + 30. * C) * x2
+                        + (-12. * B - 48. * C) * x
+                        + (8. * B + 24. * C))
+                        / 6.
+                }
+            }
+            ReconstructionFilter::Lanczos { radius } => {
+                let sinc = |x: F| {
+                    if x == 0. {
+                        1.
+                    } else {
+                        let x = std::f64::consts::PI as F * x;
+                        x.sin() / x
+                    }
+                };
+                sinc(x) * sinc(x / radius)
+            }
+        }
+    }
+}
+
+/// Same as [`ReconstructionFilter`], checked when deserializing it.
+#[derive(Deserialize)]
+#[serde(rename = "ReconstructionFilter")]
+enum ReconstructionFilterFields {
+    Box,
+    Tent,
+    Gaussian { sigma: F },
+    MitchellNetravali,
+    Lanczos { radius: F },
+}
+
+impl TryFrom<ReconstructionFilterFields> for ReconstructionFilter {
+    type Error = &'static str;
+
+    fn try_from(fields: ReconstructionFilterFields) -> std::result::Result<Self, Self::Error> {
+        Ok(match fields {
+            ReconstructionFilterFields::Box => ReconstructionFilter::Box,
+            ReconstructionFilterFields::Tent => ReconstructionFilter::Tent,
+            ReconstructionFilterFields::Gaussian { sigma } => {
+                if !sigma.is_finite() || sigma <= 0. {
+                    return Err("the sigma of a Gaussian filter must be positive");
+                }
+                ReconstructionFilter::Gaussian { sigma }
+            }
+            ReconstructionFilterFields::MitchellNetravali => {
+                ReconstructionFilter::MitchellNetravali
+            }
+            ReconstructionFilterFields::Lanczos { radius } => {
+                if !radius.is_finite() || radius <= 0. {
+                    return Err("the radius of a Lanczos filter must be positive");
+                }
+                ReconstructionFilter::Lanczos { radius }
+            }
+        })
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum SamplingLevel {
     Exploration,
     Low,
@@ -23,8 +178,35 @@ pub enum SamplingLevel {
     Extreme1,
     Extreme2,
     Extreme3,
+    /// Pixels first get [`ADAPTIVE_FIRST_SAMPLES`] samples, then the
+    /// number of samples of the pixels whose value is still uncertain
+    /// (and of their neighbors) is doubled until it reaches
+    /// `max_samples`. The value of a pixel is uncertain while the
+    /// standard error of the mean of its samples is above `threshold`
+    /// times the range of the values of the image (sampled at the center
+    /// of each pixel).
+    Adaptive {
+        max_samples: u32,
+        threshold: F,
+    },
+    /// The given number of samples per pixel.
+    Samples(u32),
 }
 
+impl SamplingLevel {
+    /// Threshold of [`SamplingLevel::Adaptive`], `None` for the levels
+    /// that give the same samples to every pixel.
+    pub fn adaptive_threshold(&self) -> Option<F> {
+        match *self {
+            SamplingLevel::Adaptive { threshold, .. } => Some(threshold),
+            _ => None,
+        }
+    }
+}
+
+/// Sampling points of a pixel, in `[0, 1]²`. For
+/// [`SamplingLevel::Adaptive`], every prefix of the points is spread over
+/// the pixel.
 pub fn generate_sampling_points(sampling_level: SamplingLevel) -> Vec<(F, F)> {
     let n = match sampling_level {
         SamplingLevel::Exploration => 8,
@@ -36,6 +218,10 @@ pub fn generate_sampling_points(sampling_level: SamplingLevel) -> Vec<(F, F)> {
         SamplingLevel::Extreme1 => 233,
         SamplingLevel::Extreme2 => 377,
         SamplingLevel::Extreme3 => 610,
+        SamplingLevel::Samples(n) => n.max(1) as usize,
+        SamplingLevel::Adaptive { max_samples, .. } => {
+            return generate_progressive_points(max_samples.max(1))
+        }
     };
 
     const PHI: F = 1.618033988749895;
@@ -51,6 +237,26 @@ pub fn generate_sampling_points(sampling_level: SamplingLevel) -> Vec<(F, F)> {
         .collect::<Vec<_>>()
 }
 
+/// Two-dimensional generalization of the golden ratio sequence (using
+/// the plastic number), whose prefixes are all evenly spread. Points
+/// come in pairs symmetric about the center of the pixel, so that
+/// prefixes of even length are centered on it.
+fn generate_progressive_points(n: u32) -> Vec<(F, F)> {
+    const G: F = 1.324717957244746;
+
+    (0..n)
+        .map(|i| {
+            let k = (i / 2) as F;
+            let (x, y) = ((0.5 + k / G) % 1., (0.5 + k / (G * G)) % 1.);
+            if i % 2 == 0 {
+                (x, y)
+            } else {
+                (1. - x, 1. - y)
+            }
+        })
+        .collect()
+}
+
 pub fn map_points_with_offsets(x: F, y: F, offset_x: F, offset_y: F) -> (F, F) {
     #[inline]
     fn tent(x: F) -> F {
@@ -70,7 +276,7 @@ pub fn map_points_with_offsets(x: F, y: F, offset_x: F, offset_y: F) -> (F, F) {
     (x, y)
 }
 
-pub fn preview_sampling_points(sampling_points: &Vec<(F, F)>) -> Result<()> {
+pub fn preview_sampling_points(sampling: &Sampling, sampling_points: &Vec<(F, F)>) -> Result<()> {
     let size = 350;
     let center = size / 2;
     let px = 50;
@@ -85,17 +291,17 @@ pub fn preview_sampling_points(sampling_points: &Vec<(F, F)>) -> Result<()> {
             };
 
             if i == 0 && j == 0 {
-                #[cfg(feature = "force_f32")]
-                let (offset_x, offset_y) = (fastrand::f32(), fastrand::f32());
-                #[cfg(not(feature = "force_f32"))]
                 let (offset_x, offset_y) = (fastrand::f64(), fastrand::f64());
-                for &(x, y) in sampling_points {
-                    let (x, y) = map_points_with_offsets(x, y, offset_x, offset_y);
-                    preview.put_pixel(
-                        (center as F + 2. * px as F * (x + i as F)) as u32,
-                        (center as F + 2. * px as F * (y + j as F)) as u32,
-                        color,
+                for &point in sampling_points {
+                    let (x, y, _) = sampling.map_point(point, (offset_x, offset_y));
+                    let (x, y) = (
+                        center as F + 2. * px as F * (x + i as F),
+                        center as F + 2. * px as F * (y + j as F),
                     );
+                    // Wide filters spread points past the neighboring pixels.
+                    if (0. ..size as F).contains(&x) && (0. ..size as F).contains(&y) {
+                        preview.put_pixel(x as u32, y as u32, color);
+                    }
                 }
             }
         }
@@ -125,3 +331,84 @@ pub fn preview_sampling_points(sampling_points: &Vec<(F, F)>) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: F, b: F) {
+        assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn filter_weights() {
+        let filters = [
+            ReconstructionFilter::Box,
+            ReconstructionFilter::Tent,
+            ReconstructionFilter::Gaussian { sigma: 0.5 },
+            ReconstructionFilter::MitchellNetravali,
+            ReconstructionFilter::Lanczos { radius: 3. },
+        ];
+        for filter in filters {
+            let radius = filter.radius();
+            assert_eq!(filter.weight(radius + 1e-9), 0., "{:?}", filter);
+            assert_eq!(filter.weight(-radius - 1e-9), 0., "{:?}", filter);
+            for x in [0.1, 0.4, 0.9, 1.7] {
+                assert_eq!(filter.weight(x), filter.weight(-x), "{:?}", filter);
+            }
+        }
+
+        assert_eq!(ReconstructionFilter::Box.weight(0.5), 1.);
+        assert_close(ReconstructionFilter::Tent.weight(0.25), 0.75);
+        let gaussian = ReconstructionFilter::Gaussian { sigma: 0.5 };
+        assert_close(gaussian.weight(0.), 1.);
+        assert_close(gaussian.weight(0.5), (-0.5 as F).exp());
+
+        let mitchell = ReconstructionFilter::MitchellNetravali;
+        assert_close(mitchell.weight(0.), 8. / 9.);
+        // Both pieces meet at 1, and the weight vanishes at the radius.
+        assert_close(mitchell.weight(1. - 1e-12), 1. / 18.);
+        assert_close(mitchell.weight(1.), 1. / 18.);
+        assert_close(mitchell.weight(2.), 0.);
+        assert!(mitchell.weight(1.5) < 0.);
+
+        let lanczos = ReconstructionFilter::Lanczos { radius: 3. };
+        assert_close(lanczos.weight(0.), 1.);
+        assert_close(lanczos.weight(1.), 0.);
+        assert_close(lanczos.weight(2.), 0.);
+        assert!(lanczos.weight(1.5) < 0.);
+    }
+
+    #[test]
+    fn filter_validation() {
+        let parse = |s: &str| ron::from_str::<ReconstructionFilter>(s);
+        assert_eq!(
+            parse("Gaussian(sigma: 0.3)").unwrap(),
+            ReconstructionFilter::Gaussian { sigma: 0.3 }
+        );
+        assert_eq!(
+            parse("Lanczos(radius: 2.)").unwrap(),
+            ReconstructionFilter::Lanczos { radius: 2. }
+        );
+        for s in [
+            "Gaussian(sigma: 0.)",
+            "Gaussian(sigma: -1.)",
+            "Gaussian(sigma: NaN)",
+            "Lanczos(radius: 0.)",
+            "Lanczos(radius: inf)",
+        ] {
+            let error = parse(s).unwrap_err().to_string();
+            assert!(error.contains("must be positive"), "{}: {}", s, error);
+        }
+    }
+
+    #[test]
+    fn usable_weight() {
+        assert!(has_usable_weight(1., 1.));
+        assert!(has_usable_weight(0.5, 2.));
+        assert!(!has_usable_weight(0., 2.));
+        assert!(!has_usable_weight(-0.5, 2.));
+        assert!(!has_usable_weight(1e-3, 2.));
+        assert!(!has_usable_weight(0., 0.));
+    }
+}
@@ -0,0 +1,517 @@
+//! 3D fractals (Mandelbulb and quaternion Julia sets) rendered by ray
+//! marching: each ray advances by the distance estimate of the fractal
+//! until it is closer to the surface than the size of a pixel. Hit points
+//! are lit with soft shadows and ambient occlusion and colored with the
+//! gradient using an orbit trap.
+
+use std::ops::{Add, Mul, Sub};
+
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use wide::f64x4;
+
+use crate::{
+    coloring::color_mapping,
+    params::SceneParams,
+    progress::Progress,
+    sampling::has_usable_weight,
+    simd::{SimdFloat, MAX_LANES},
+    F,
+};
+
+/// Squared escape radius of the orbits.
+const BAILOUT: F = 16.;
+
+/// Default number of steps of a ray before giving up.
+const DEFAULT_MAX_STEPS: u32 = 256;
+/// Distance below which a ray hits the surface, whatever the size of the
+/// pixels.
+const MIN_DISTANCE: F = 1e-6;
+
+/// Default direction towards the light.
+const DEFAULT_LIGHT: (F, F, F) = (-0.6, 1., -0.8);
+/// Part of the light that doesn't depend on the direction of the surface
+/// (only on its occlusion).
+const AMBIENT: F = 0.25;
+/// Sharpness of the shadows, higher values give harder shadows.
+const SHADOW_HARDNESS: F = 16.;
+const SHADOW_STEPS: u32 = 64;
+/// Distance between the samples of the ambient occlusion (along the
+/// normal).
+const OCCLUSION_STEP: F = 0.02;
+const OCCLUSION_SAMPLES: u32 = 5;
+const OCCLUSION_STRENGTH: F = 3.;
+
+/// Position and orientation of the camera, the up direction is `+y`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenericCamera<T> {
+    pub position: (T, T, T),
+    pub look_at: (T, T, T),
+    /// Vertical field of view in degrees.
+    pub fov: T,
+}
+
+pub type Camera = GenericCamera<F>;
+
+impl<T> GenericCamera<T> {
+    /// Maps the values of every parameter.
+    pub fn map_params<U>(&self, f: &impl Fn(&T) -> U) -> GenericCamera<U> {
+        let (px, py, pz) = &self.position;
+        let (lx, ly, lz) = &self.look_at;
+        GenericCamera {
+            position: (f(px), f(py), f(pz)),
+            look_at: (f(lx), f(ly), f(lz)),
+            fov: f(&self.fov),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Fractal3D {
+    /// `z ← z^power + c` where the power of a point of the space is taken
+    /// in spherical coordinates (the radius is raised to `power` and the
+    /// angles are multiplied by it).
+    Mandelbulb { power: F },
+    /// Julia set of `q ← q² + c` in the quaternions, sliced at `w = 0`.
+    /// `c` is `(x, y, z, w)`.
+    QuaternionJulia { c: (F, F, F, F) },
+}
+
+impl Fractal3D {
+    /// Radius of a sphere containing the fractal.
+    fn bounding_radius(&self) -> F {
+        match *self {
+            Fractal3D::Mandelbulb { .. } => 2.,
+            Fractal3D::QuaternionJulia { c: (x, y, z, w) } => {
+                let c = (x * x + y * y + z * z + w * w).sqrt();
+                (0.5 + (0.25 + c).sqrt()).max(2.)
+            }
+        }
+    }
+
+    /// Lower bound of the distance from `p` to the fractal (negative
+    /// inside of it) and orbit trap: the minimum of `|z|` along the
+    /// orbit (after the starting point).
+    #[inline]
+    fn distance<X: SimdFloat>(&self, p: Vec3<X>, max_iter: u32) -> (X, X) {
+        let zero = X::splat(0.);
+        let one = X::splat(1.);
+        let bailout = X::splat(BAILOUT);
+
+        // `r2` is the squared norm of `z` and `dr` the norm of its
+        // derivative, the values of escaped lanes are frozen.
+        let (r2, dr, trap) = match *self {
+            Fractal3D::Mandelbulb { power } => {
+                let n = X::splat(power);
+                let mut z = p;
+                let mut dr = one;
+                let mut r2 = z.dot(z);
+                let mut trap = X::splat(F::MAX);
+                for _ in 0..max_iter {
+                    let active = r2.cmp_le(bailout);
+                    if !active.any() {
+                        break;
+                    }
+
+                    let r = r2.sqrt();
+                    let theta = (z.x * z.x + z.y * z.y).sqrt().atan2(z.z) * n;
+                    let phi = z.y.atan2(z.x) * n;
+                    let rn = r.powf(power);
+                    let new_z = Vec3 {
+                        x: rn * theta.sin() * phi.cos(),
+                        y: rn * theta.sin() * phi.sin(),
+                        z: rn * theta.cos(),
+                    } + p;
+
+                    dr = active.blend(r.powf(power - 1.) * n * dr + one, dr);
+                    z = Vec3::select(active, new_z, z);
+                    r2 = z.dot(z);
+                    trap = active.blend(trap.min(r2), trap);
+                }
+                (r2, dr, trap)
+            }
+            Fractal3D::QuaternionJulia {
+                c: (cx, cy, cz, cw),
+            } => {
+                let two = X::splat(2.);
+                let (mut a, mut b, mut c, mut d) = (p.x, p.y, p.z, zero);
+                let mut dr = one;
+                let mut r2 = p.dot(p);
+                let mut trap = X::splat(F::MAX);
+                for _ in 0..max_iter {
+                    let active = r2.cmp_le(bailout);
+                    if !active.any() {
+                        break;
+                    }
+
+                    dr = active.blend(two * r2.sqrt() * dr, dr);
+                    let new_a = a * a - b * b - c * c - d * d + X::splat(cx);
+                    let new_b = two * a * b + X::splat(cy);
+                    let new_c = two * a * c + X::splat(cz);
+                    let new_d = two * a * d + X::splat(cw);
+                    a = active.blend(new_a, a);
+                    b = active.blend(new_b, b);
+                    c = active.blend(new_c, c);
+                    d = active.blend(new_d, d);
+
+                    r2 = a * a + b * b + c * c + d * d;
+                    trap = active.blend(trap.min(r2), trap);
+                }
+                (r2, dr, trap)
+            }
+        };
+
+        let r = r2.sqrt();
+        let distance = X::splat(0.5) * r * r.ln() / dr;
+
+        // Beyond the escape radius there are no iterations and the
+        // estimate is too large, the distance to a sphere containing the
+        // fractal is used instead.
+        let p2 = p.dot(p);
+        let distance = bailout
+            .cmp_lt(p2)
+            .blend(p2.sqrt() - X::splat(self.bounding_radius()), distance);
+
+        (distance, trap.sqrt())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Vec3<X> {
+    x: X,
+    y: X,
+    z: X,
+}
+
+impl<X: SimdFloat> Vec3<X> {
+    fn splat((x, y, z): (F, F, F)) -> Vec3<X> {
+        Vec3 {
+            x: X::splat(x),
+            y: X::splat(y),
+            z: X::splat(z),
+        }
+    }
+
+    fn select(mask: X, t: Vec3<X>, f: Vec3<X>) -> Vec3<X> {
+        Vec3 {
+            x: mask.blend(t.x, f.x),
+            y: mask.blend(t.y, f.y),
+            z: mask.blend(t.z, f.z),
+        }
+    }
+
+    fn dot(self, rhs: Vec3<X>) -> X {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    fn normalize(self) -> Vec3<X> {
+        self * (X::splat(1.) / self.dot(self).sqrt())
+    }
+}
+
+impl<X: SimdFloat> Add for Vec3<X> {
+    type Output = Vec3<X>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Vec3 {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+            z: self.z + rhs.z,
+        }
+    }
+}
+
+impl<X: SimdFloat> Sub for Vec3<X> {
+    type Output = Vec3<X>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Vec3 {
+            x: self.x - rhs.x,
+            y: self.y - rhs.y,
+            z: self.z - rhs.z,
+        }
+    }
+}
+
+impl<X: SimdFloat> Mul<X> for Vec3<X> {
+    type Output = Vec3<X>;
+
+    fn mul(self, rhs: X) -> Self::Output {
+        Vec3 {
+            x: self.x * rhs,
+            y: self.y * rhs,
+            z: self.z * rhs,
+        }
+    }
+}
+
+fn sub((ax, ay, az): (F, F, F), (bx, by, bz): (F, F, F)) -> (F, F, F) {
+    (ax - bx, ay - by, az - bz)
+}
+
+fn cross((ax, ay, az): (F, F, F), (bx, by, bz): (F, F, F)) -> (F, F, F) {
+    (ay * bz - az * by, az * bx - ax * bz, ax * by - ay * bx)
+}
+
+fn normalize((x, y, z): (F, F, F)) -> (F, F, F) {
+    let norm = (x * x + y * y + z * z).sqrt();
+    (x / norm, y / norm, z / norm)
+}
+
+/// Scene being rendered, with the settings that don't change from pixel
+/// to pixel.
+struct Scene<'a> {
+    fractal: Fractal3D,
+    max_iter: u32,
+    max_steps: u32,
+    /// Distance after which rays are considered to have missed the
+    /// fractal.
+    max_distance: F,
+    /// Angle covered by a pixel (approximately), used to stop rays when
+    /// they are closer to the surface than a pixel.
+    pixel_angle: F,
+    light: (F, F, F),
+    custom_gradient: Option<&'a Vec<(f32, [u8; 3])>>,
+}
+
+impl Scene<'_> {
+    #[inline]
+    fn distance<X: SimdFloat>(&self, p: Vec3<X>) -> X {
+        self.fractal.distance(p, self.max_iter).0
+    }
+
+    /// Marches the rays, returns the distance they traveled and a mask of
+    /// the ones that hit the fractal.
+    fn march<X: SimdFloat>(&self, origin: Vec3<X>, dir: Vec3<X>) -> (X, X) {
+        let zero = X::splat(0.);
+        let yes = zero.cmp_le(zero);
+        let max_distance = X::splat(self.max_distance);
+
+        let mut t = zero;
+        let mut hit = zero;
+        let mut active = yes;
+        for _ in 0..self.max_steps {
+            if !active.any() {
+                break;
+            }
+
+            let d = self.distance(origin + dir * t);
+            let close = d.cmp_lt(self.epsilon(t));
+            hit = active.blend(close, hit);
+
+            let stop = close.blend(yes, max_distance.cmp_lt(t));
+            active = stop.blend(zero, active);
+            t += active.blend(d, zero);
+        }
+
+        (t, hit)
+    }
+
+    /// Distance below which a ray that traveled `t` hits the surface.
+    #[inline]
+    fn epsilon<X: SimdFloat>(&self, t: X) -> X {
+        (t * X::splat(0.5 * self.pixel_angle)).max(X::splat(MIN_DISTANCE))
+    }
+
+    /// Normal of the surface (the gradient of the distance estimate),
+    /// using the tetrahedron technique.
+    fn normal<X: SimdFloat>(&self, p: Vec3<X>, h: X) -> Vec3<X> {
+        [(1., -1., -1.), (-1., -1., 1.), (-1., 1., -1.), (1., 1., 1.)]
+            .into_iter()
+            .map(|k| {
+                let k = Vec3::splat(k);
+                k * self.distance(p + k * h)
+            })
+            .reduce(|a, b| a + b)
+            .unwrap()
+            .normalize()
+    }
+
+    /// Light reaching `p` from the light direction, between 0 (in the
+    /// shadow) and 1, with penumbras for rays that get close to the
+    /// fractal.
+    fn soft_shadow<X: SimdFloat>(&self, p: Vec3<X>, light: Vec3<X>, epsilon: X) -> X {
+        let zero = X::splat(0.);
+        let max_distance = X::splat(self.max_distance);
+        let hardness = X::splat(SHADOW_HARDNESS);
+
+        let mut shadow = X::splat(1.);
+        let mut t = epsilon * X::splat(10.);
+        for _ in 0..SHADOW_STEPS {
+            let active = t.cmp_lt(max_distance).blend(zero.cmp_lt(shadow), zero);
+            if !active.any() {
+                break;
+            }
+
+            let d = self.distance(p + light * t);
+            shadow = active.blend(shadow.min(hardness * d / t), shadow);
+            t += active.blend(d.max(epsilon), zero);
+        }
+
+        shadow.max(zero).min(X::splat(1.))
+    }
+
+    /// Ambient occlusion at `p`: 1 in the open and lower in the creases,
+    /// where the fractal is closer than the distance along the normal.
+    fn occlusion<X: SimdFloat>(&self, p: Vec3<X>, normal: Vec3<X>) -> X {
+        let mut occlusion = X::splat(0.);
+        let mut weight = 1.;
+        for i in 1..=OCCLUSION_SAMPLES {
+            let h = X::splat(OCCLUSION_STEP * i as F);
+            occlusion += (h - self.distance(p + normal * h)) * X::splat(weight);
+            weight *= 0.5;
+        }
+
+        (X::splat(1.) - X::splat(OCCLUSION_STRENGTH) * occlusion)
+            .max(X::splat(0.))
+            .min(X::splat(1.))
+    }
+
+    /// Colors of the rays, black when they miss the fractal.
+    fn shade<X: SimdFloat>(&self, origin: Vec3<X>, dir: Vec3<X>) -> [[F; 3]; MAX_LANES] {
+        let zero = X::splat(0.);
+
+        let (t, hit) = self.march(origin, dir);
+        let mut colors = [[0.; 3]; MAX_LANES];
+        if !hit.any() {
+            return colors;
+        }
+
+        let epsilon = self.epsilon(t);
+        let p = origin + dir * t;
+        let normal = self.normal(p, epsilon);
+        // Moved out of the surface so that it doesn't shadow itself.
+        let p = p + normal * (epsilon * X::splat(2.));
+        let light = Vec3::splat(self.light);
+
+        let diffuse = normal.dot(light).max(zero) * self.soft_shadow(p, light, epsilon);
+        let occlusion = self.occlusion(p, normal);
+        let intensity = X::splat(AMBIENT) * occlusion + X::splat(1. - AMBIENT) * diffuse;
+        let (_, trap) = self.fractal.distance(p, self.max_iter);
+
+        // The trap is normalized so that it fits in the gradient.
+        let trap = trap / X::splat(self.fractal.bounding_radius());
+
+        let (hit, intensity, trap) = (hit.move_mask(), intensity.to_array(), trap.to_array());
+        for lane in 0..X::LANES {
+            if hit & (1 << lane) != 0 {
+                let Rgb(color) = color_mapping(trap[lane].min(1.), self.custom_gradient);
+                colors[lane] = color.map(|c| c as F * intensity[lane].max(0.));
+            }
+        }
+
+        colors
+    }
+}
+
+/// Renders the scene, the color of each pixel being the mean of the
+/// colors of its samples.
+pub fn render_scene(
+    params: &SceneParams,
+    sampling_points: &[(F, F)],
+    progress: Option<Progress>,
+) -> RgbImage {
+    let &SceneParams {
+        img_width,
+        img_height,
+        ref camera,
+        fractal,
+        max_iter,
+        max_steps,
+        light,
+        sampling,
+        ref custom_gradient,
+    } = params;
+
+    let forward = normalize(sub(camera.look_at, camera.position));
+    let world_up = if cross((0., 1., 0.), forward) == (0., 0., 0.) {
+        (0., 0., 1.)
+    } else {
+        (0., 1., 0.)
+    };
+    let right = normalize(cross(world_up, forward));
+    let up = cross(forward, right);
+
+    let tan_half_fov = (0.5 * camera.fov.to_radians()).tan();
+    let aspect_ratio = img_width as F / img_height as F;
+
+    let (px, py, pz) = camera.position;
+    let scene = Scene {
+        fractal,
+        max_iter,
+        max_steps: max_steps.unwrap_or(DEFAULT_MAX_STEPS),
+        max_distance: (px * px + py * py + pz * pz).sqrt() + fractal.bounding_radius(),
+        pixel_angle: 2. * tan_half_fov / img_height as F,
+        light: normalize(light.unwrap_or(DEFAULT_LIGHT)),
+        custom_gradient: custom_gradient.as_ref(),
+    };
+
+    let origin = Vec3::<f64x4>::splat(camera.position);
+    let (forward, right, up) = (
+        Vec3::<f64x4>::splat(forward),
+        Vec3::<f64x4>::splat(right),
+        Vec3::<f64x4>::splat(up),
+    );
+
+    let pixels: Vec<[F; 3]> = (0..img_width as usize * img_height as usize)
+        .into_par_iter()
+        .map_init(fastrand::Rng::new, |rng, index| {
+            let x = (index % img_width as usize) as F;
+            let y = (index / img_width as usize) as F;
+
+            let offset = if sampling.random_offsets {
+                (rng.f64(), rng.f64())
+            } else {
+                (0., 0.)
+            };
+            let sampling_points = sampling_points
+                .iter()
+                .map(|&point| sampling.map_point(point, offset))
+                .collect::<Vec<_>>();
+
+            let mut color = [0.; 3];
+            let mut plain_color = [0.; 3];
+            let (mut weight, mut abs_weight) = (0., 0.);
+            for d in sampling_points.chunks(f64x4::LANES) {
+                let l = d.len();
+                // Position on the screen, in `[-1, 1]` vertically.
+                let u = f64x4::from_fn(|i| {
+                    let (dx, _, _) = d[i % l];
+                    (2. * (x + 0.5 + dx) / img_width as F - 1.) * aspect_ratio * tan_half_fov
+                });
+                let v = f64x4::from_fn(|i| {
+                    let (_, dy, _) = d[i % l];
+                    (1. - 2. * (y + 0.5 + dy) / img_height as F) * tan_half_fov
+                });
+                let dir = (forward + right * u + up * v).normalize();
+
+                for (lane_color, &(_, _, w)) in scene.shade(origin, dir).iter().zip(d) {
+                    for ((c, plain_c), lane_c) in
+                        color.iter_mut().zip(&mut plain_color).zip(lane_color)
+                    {
+                        *c += w * lane_c;
+                        *plain_c += lane_c;
+                    }
+                    weight += w;
+                    abs_weight += w.abs();
+                }
+            }
+
+            if let Some(progress) = &progress {
+                progress.incr();
+            }
+
+            if has_usable_weight(weight, abs_weight) {
+                color.map(|c| c / weight)
+            } else {
+                plain_color.map(|c| c / sampling_points.len() as F)
+            }
+        })
+        .collect();
+
+    RgbImage::from_fn(img_width, img_height, |i, j| {
+        let color = pixels[i as usize + j as usize * img_width as usize];
+        Rgb(color.map(|c| c.round().clamp(0., 255.) as u8))
+    })
+}
@@ -0,0 +1,184 @@
+//! The simd float types fractals can be sampled with, they are picked at
+//! runtime using [`Precision`].
+
+use std::{
+    array,
+    fmt::Debug,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+};
+
+use serde::{Deserialize, Serialize};
+use wide::{f32x8, f64x4, CmpLe, CmpLt};
+
+use crate::{double_double::DoubleF64x4, fractal::Fractal, perturbation::needs_perturbation, F};
+
+/// Maximum number of lanes of a [`SimdFloat`].
+pub const MAX_LANES: usize = 8;
+
+/// Values of the lanes of a [`SimdFloat`], only the first
+/// [`SimdFloat::LANES`] are meaningful.
+pub type Out = [F; MAX_LANES];
+
+/// A simd float type, it mirrors the api of the `wide` types. Comparisons
+/// return masks to be used with [`SimdFloat::blend`].
+pub trait SimdFloat:
+    Copy
+    + Debug
+    + Send
+    + Sync
+    + Add<Output = Self>
+    + AddAssign
+    + Sub<Output = Self>
+    + SubAssign
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + CmpLe<Output = Self>
+    + CmpLt<Output = Self>
+{
+    const LANES: usize;
+    /// Precision of a lane.
+    const EPSILON: F;
+
+    fn splat(x: F) -> Self;
+    fn from_fn(f: impl FnMut(usize) -> F) -> Self;
+    fn to_array(self) -> Out;
+
+    /// Uses `self` as a mask, takes `t` where it is set and `f`
+    /// elsewhere.
+    fn blend(self, t: Self, f: Self) -> Self;
+    fn any(self) -> bool;
+    fn move_mask(self) -> i32;
+
+    fn abs(self) -> Self;
+    fn max(self, rhs: Self) -> Self;
+    fn min(self, rhs: Self) -> Self;
+    fn sqrt(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn atan2(self, x: Self) -> Self;
+    fn powf(self, exp: F) -> Self;
+}
+
+macro_rules! impl_simd_float {
+    ($t:ty, $s:ty, $lanes:expr, $epsilon:expr) => {
+        impl SimdFloat for $t {
+            const LANES: usize = $lanes;
+            const EPSILON: F = $epsilon as F;
+
+            #[inline(always)]
+            fn splat(x: F) -> $t {
+                <$t>::splat(x as $s)
+            }
+            #[inline(always)]
+            fn from_fn(mut f: impl FnMut(usize) -> F) -> $t {
+                <$t>::from(array::from_fn::<$s, $lanes, _>(|i| f(i) as $s))
+            }
+            #[inline(always)]
+            fn to_array(self) -> Out {
+                let values = self.to_array();
+                array::from_fn(|i| values.get(i).map_or(0., |&v| v as F))
+            }
+
+            #[inline(always)]
+            fn blend(self, t: $t, f: $t) -> $t {
+                self.blend(t, f)
+            }
+            #[inline(always)]
+            fn any(self) -> bool {
+                self.any()
+            }
+            #[inline(always)]
+            fn move_mask(self) -> i32 {
+                self.move_mask()
+            }
+
+            #[inline(always)]
+            fn abs(self) -> $t {
+                self.abs()
+            }
+            #[inline(always)]
+            fn max(self, rhs: $t) -> $t {
+                self.max(rhs)
+            }
+            #[inline(always)]
+            fn min(self, rhs: $t) -> $t {
+                self.min(rhs)
+            }
+            #[inline(always)]
+            fn sqrt(self) -> $t {
+                self.sqrt()
+            }
+            #[inline(always)]
+            fn sin(self) -> $t {
+                self.sin()
+            }
+            #[inline(always)]
+            fn cos(self) -> $t {
+                self.cos()
+            }
+            #[inline(always)]
+            fn exp(self) -> $t {
+                self.exp()
+            }
+            #[inline(always)]
+            fn ln(self) -> $t {
+                self.ln()
+            }
+            #[inline(always)]
+            fn atan2(self, x: $t) -> $t {
+                self.atan2(x)
+            }
+            #[inline(always)]
+            fn powf(self, exp: F) -> $t {
+                self.powf(exp as $s)
+            }
+        }
+    };
+}
+
+impl_simd_float!(f32x8, f32, 8, f32::EPSILON);
+impl_simd_float!(f64x4, f64, 4, f64::EPSILON);
+impl_simd_float!(DoubleF64x4, f64, 4, DoubleF64x4::EPSILON);
+
+/// Float type used to render.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Precision {
+    /// `F32` for pixels much larger than what it can tell apart (such as
+    /// previews in the GUI), `DoubleDouble` when `F64` can't tell pixels
+    /// apart at the current zoom, `F64` otherwise.
+    #[default]
+    Auto,
+    F32,
+    F64,
+    /// About twice the precision of `F64`, and several times slower.
+    DoubleDouble,
+}
+
+impl Precision {
+    /// How much larger than what `F32` can tell apart pixels must be for
+    /// [`Precision::Auto`] to pick it, since its rounding errors add up
+    /// over the iterations.
+    const F32_MARGIN: F = 32.;
+
+    /// Turns [`Precision::Auto`] into an actual precision for pixels of
+    /// the given size around `center`.
+    pub fn resolve(self, fractal: &Fractal, center: (F, F), pixel_size: F) -> Precision {
+        match self {
+            Precision::Auto => {
+                if !needs_perturbation::<f32x8>(center, pixel_size / Precision::F32_MARGIN) {
+                    Precision::F32
+                } else if !needs_perturbation::<f64x4>(center, pixel_size)
+                    || fractal.perturbation_exponent().is_some()
+                {
+                    Precision::F64
+                } else {
+                    Precision::DoubleDouble
+                }
+            }
+            precision => precision,
+        }
+    }
+}